@@ -0,0 +1,272 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright © 2019 Corporation for Digital Scholarship
+
+//! Problems with the input that the processor works around rather than failing on. See
+//! `Processor::diagnostics`.
+
+use super::Processor;
+use crate::prelude::*;
+use citeproc_db::LocaleFileError;
+use citeproc_io::{DateOrRange, Locators};
+use csl::{AnyVariable, Lang, LocaleSource, NumberVariable, Severity};
+use fnv::{FnvHashMap, FnvHashSet};
+use std::collections::HashSet;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DiagnosticKind {
+    /// A cite's reference isn't in the library. The cite renders as `???`.
+    MissingReference,
+    /// A locator `label` isn't a CSL locator type. It is treated as a page.
+    UnknownLocatorLabel,
+    /// A date couldn't be parsed, or was given as a `literal`, so it is printed as written and
+    /// can't be sorted or disambiguated by.
+    LiteralDate,
+    /// A number variable rendered with `<number>` couldn't be parsed as a number, so it is
+    /// printed as written.
+    NonNumericValue,
+    /// No locale file could be found for a language in use.
+    MissingLocale,
+    /// A locale file couldn't be parsed.
+    InvalidLocale,
+}
+
+/// Something about the input that the output doesn't fully reflect.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub kind: DiagnosticKind,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cluster_id: Option<ClusterId>,
+    /// The position of the cite in its cluster
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cite_index: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ref_id: Option<Atom>,
+}
+
+/// A reference in the bibliography that lacks variables the style would render for it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IncompleteReference {
+    pub ref_id: Atom,
+    /// Sorted by name
+    pub missing: Vec<AnyVariable>,
+}
+
+impl Diagnostic {
+    fn warning(kind: DiagnosticKind, message: String) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            kind,
+            message,
+            cluster_id: None,
+            cite_index: None,
+            ref_id: None,
+        }
+    }
+
+    fn in_cite(mut self, cluster_id: ClusterId, cite_index: u32, ref_id: &Atom) -> Self {
+        self.cluster_id = Some(cluster_id);
+        self.cite_index = Some(cite_index);
+        self.ref_id = Some(ref_id.clone());
+        self
+    }
+
+    fn in_reference(mut self, ref_id: &Atom) -> Self {
+        self.ref_id = Some(ref_id.clone());
+        self
+    }
+}
+
+impl Processor {
+    /// Lists everything the processor had to work around in the current document: cites of
+    /// missing references, unknown locator labels, dates it couldn't parse, numbers a `<number>`
+    /// had to print as written, and locales it couldn't find or parse.
+    ///
+    /// The numbers are recorded while rendering, so this renders whatever hasn't been already.
+    /// The rest are problems with the inputs themselves, whatever the style does with them.
+    ///
+    /// Cites come first, in document order, then references used in the document, by id, then
+    /// locales.
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+        self.cite_diagnostics(&mut diagnostics);
+        self.reference_diagnostics(&mut diagnostics);
+        self.locale_diagnostics(&mut diagnostics);
+        diagnostics
+    }
+
+    /// Lists the references in the bibliography that are missing variables the style would
+    /// render for their type, like an `article-journal` without a `container-title`, by id.
+    /// See `CiteDatabase::missing_variables`.
+    pub fn incomplete_references(&self) -> Vec<IncompleteReference> {
+        let participants = self.disamb_participants();
+        let mut ref_ids: Vec<&Atom> = participants.iter().collect();
+        ref_ids.sort();
+        ref_ids
+            .into_iter()
+            .filter_map(|ref_id| {
+                let missing = self.missing_variables(ref_id.clone());
+                if missing.is_empty() {
+                    return None;
+                }
+                Some(IncompleteReference {
+                    ref_id: ref_id.clone(),
+                    missing: (*missing).clone(),
+                })
+            })
+            .collect()
+    }
+
+    fn cite_diagnostics(&self, diagnostics: &mut Vec<Diagnostic>) {
+        use DiagnosticKind::*;
+        for cluster in self.clusters_sorted().iter() {
+            for (index, &cite_id) in cluster.cites.iter().enumerate() {
+                let cite = cite_id.lookup(self);
+                let in_cite = |d: Diagnostic| d.in_cite(cluster.id, index as u32, &cite.ref_id);
+                if self.reference(cite.ref_id.clone()).is_none() {
+                    diagnostics.push(in_cite(Diagnostic::warning(
+                        MissingReference,
+                        format!("reference {} not found", cite.ref_id),
+                    )));
+                }
+                let locators = match &cite.locators {
+                    Some(Locators::Single(l)) => std::slice::from_ref(l),
+                    Some(Locators::Multiple { locators }) => locators.as_slice(),
+                    None => &[],
+                };
+                for label in locators.iter().filter_map(|l| l.unknown_label.as_ref()) {
+                    diagnostics.push(in_cite(Diagnostic::warning(
+                        UnknownLocatorLabel,
+                        format!("unknown locator label `{}`, treated as a page", label),
+                    )));
+                }
+            }
+        }
+    }
+
+    fn reference_diagnostics(&self, diagnostics: &mut Vec<Diagnostic>) {
+        use DiagnosticKind::*;
+        let non_numeric = self.non_numeric_values();
+        let participants = self.disamb_participants();
+        let mut ref_ids: Vec<&Atom> = participants.iter().collect();
+        ref_ids.sort();
+        for ref_id in ref_ids {
+            let refr = match self.reference(ref_id.clone()) {
+                Some(refr) => refr,
+                None => continue,
+            };
+            let mut dates: Vec<_> = refr
+                .date
+                .iter()
+                .filter_map(|(var, date)| match date {
+                    DateOrRange::Literal(lit) => Some((var.as_ref(), lit)),
+                    _ => None,
+                })
+                .collect();
+            dates.sort();
+            for (var, lit) in dates {
+                diagnostics.push(
+                    Diagnostic::warning(
+                        LiteralDate,
+                        format!("{} `{}` is not a date, and is printed as written", var, lit),
+                    )
+                    .in_reference(ref_id),
+                );
+            }
+            let mut numbers: Vec<_> = non_numeric
+                .get(ref_id)
+                .into_iter()
+                .flatten()
+                .filter_map(|var| {
+                    let value = refr.number.get(var)?;
+                    Some((var.as_ref(), value.verbatim()))
+                })
+                .collect();
+            numbers.sort();
+            for (var, value) in numbers {
+                diagnostics.push(
+                    Diagnostic::warning(
+                        NonNumericValue,
+                        format!(
+                            "{} `{}` is not a number, and is printed as written",
+                            var, value
+                        ),
+                    )
+                    .in_reference(ref_id),
+                );
+            }
+        }
+    }
+
+    /// The number variables that a `<number>` printed as written, by reference, as recorded while
+    /// rendering the document's cites and the bibliography.
+    fn non_numeric_values(&self) -> FnvHashMap<Atom, FnvHashSet<NumberVariable>> {
+        let mut found: FnvHashMap<Atom, FnvHashSet<NumberVariable>> = FnvHashMap::default();
+        let mut record = |ref_id: &Atom, vars: &FnvHashSet<NumberVariable>| {
+            if !vars.is_empty() {
+                found
+                    .entry(ref_id.clone())
+                    .or_default()
+                    .extend(vars.iter().cloned());
+            }
+        };
+        for cluster in self.clusters_sorted().iter() {
+            for &cite_id in cluster.cites.iter() {
+                let gen = self.ir_gen4_conditionals(cite_id);
+                record(&cite_id.lookup(self).ref_id, gen.non_numeric());
+            }
+        }
+        if self.style().bibliography.is_some() {
+            for ref_id in self.sorted_refs().0.iter() {
+                if let Some(gen) = self.bib_item_gen0(ref_id.clone()) {
+                    record(ref_id, gen.non_numeric());
+                }
+            }
+        }
+        found
+    }
+
+    fn locale_diagnostics(&self, diagnostics: &mut Vec<Diagnostic>) {
+        let mut langs = self.get_langs_in_use();
+        langs.sort_by_key(|lang| lang.to_string());
+        // Several languages can fall back to the same file
+        let mut invalid = HashSet::new();
+        for lang in langs {
+            let mut found = lang == Lang::en_us();
+            for source in lang.iter() {
+                let file = match source {
+                    LocaleSource::File(file) if file != Lang::en_us() => file,
+                    _ => continue,
+                };
+                match self.locale_file(file.clone()) {
+                    Ok(_) => found = true,
+                    Err(LocaleFileError::NotFound) => {}
+                    Err(LocaleFileError::Invalid(e)) => {
+                        if invalid.insert(file.clone()) {
+                            diagnostics.push(Diagnostic {
+                                severity: Severity::Error,
+                                ..Diagnostic::warning(
+                                    DiagnosticKind::InvalidLocale,
+                                    format!("could not parse locale {}: {}", file, e),
+                                )
+                            });
+                        }
+                    }
+                }
+            }
+            if !found {
+                diagnostics.push(Diagnostic::warning(
+                    DiagnosticKind::MissingLocale,
+                    format!("no locale found for {}, falling back to en-US", lang),
+                ));
+            }
+        }
+    }
+}
@@ -8,6 +8,9 @@
 #![allow(clippy::large_enum_variant)]
 #![allow(clippy::enum_variant_names)]
 
+pub mod diagnostics;
+pub mod snapshot;
+pub mod transaction;
 pub mod update;
 
 #[cfg(test)]
@@ -16,10 +19,13 @@ mod test;
 use crate::prelude::*;
 
 use self::update::{
-    BibliographyMeta, BibliographyUpdate, DocUpdate, SecondFieldAlign, UpdateSummary,
+    BibEntry, BibliographyMeta, BibliographyUpdate, DeliveredClusters, DocUpdate, FirstField,
+    SecondFieldAlign, UpdateSummary,
 };
 use citeproc_db::{CiteDatabaseStorage, HasFetcher, LocaleDatabaseStorage, StyleDatabaseStorage};
 use citeproc_proc::db::IrDatabaseStorage;
+use citeproc_proc::semantic::CiteSpans;
+use citeproc_proc::standalone::StandaloneReference;
 
 use salsa::Durability;
 #[cfg(feature = "rayon")]
@@ -31,17 +37,19 @@ use std::sync::Mutex;
 
 use csl::Lang;
 use csl::Style;
-use csl::StyleError;
+use csl::{AnyStyle, CslError, CslType, Info, InvalidCsl, Severity, StyleError};
 
-use citeproc_io::output::{markup::Markup, OutputFormat};
-use citeproc_io::{Cite, Cluster, ClusterId, ClusterNumber, Reference};
+use citeproc_io::output::{markup::HtmlMetadata, markup::Markup, OutputFormat};
+use citeproc_io::{
+    BibliographyFilter, Cite, Cluster, ClusterId, ClusterNumber, Reference, ReferencePatch,
+};
 use csl::Atom;
 
 #[allow(dead_code)]
 type MarkupBuild = <Markup as OutputFormat>::Build;
 #[allow(dead_code)]
 type MarkupOutput = <Markup as OutputFormat>::Output;
-use fnv::FnvHashMap;
+use fnv::{FnvHashMap, FnvHashSet};
 
 struct SavedBib {
     sorted_refs: Arc<(Vec<Atom>, FnvHashMap<Atom, u32>)>,
@@ -66,10 +74,40 @@ impl SavedBib {
 pub struct Processor {
     runtime: salsa::Runtime<Self>,
     pub fetcher: Arc<dyn LocaleFetcher>,
+    /// Supplies the parents of dependent styles
+    style_fetcher: Option<Arc<dyn StyleFetcher>>,
     pub formatter: Markup,
+    /// The source of the `style` input, for snapshots
+    style_text: Arc<String>,
     queue: Arc<Mutex<Vec<DocUpdate>>>,
     save_updates: bool,
+    last_clusters: Arc<Mutex<DeliveredClusters>>,
     last_bibliography: Arc<Mutex<SavedBib>>,
+    last_named_bibliographies: Arc<Mutex<FnvHashMap<Atom, SavedBib>>>,
+    last_section_bibliographies: Arc<Mutex<FnvHashMap<u32, SavedBib>>>,
+    listener: Option<UpdateListener>,
+    updates_pending: bool,
+    /// Clusters that have been inserted and not removed, for validating transactions.
+    known_clusters: FnvHashSet<ClusterId>,
+}
+
+/// The main bibliography, one section's, or a named one.
+enum WhichBibliography {
+    Main,
+    Section(u32),
+    Named(Atom),
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "parallel")] {
+        /// Receives the changes to the document as they happen. See
+        /// `Processor::set_update_listener`.
+        pub type UpdateListener = Arc<dyn Fn(&UpdateSummary) + Send + Sync>;
+    } else {
+        /// Receives the changes to the document as they happen. See
+        /// `Processor::set_update_listener`.
+        pub type UpdateListener = std::rc::Rc<dyn Fn(&UpdateSummary)>;
+    }
 }
 
 /// This impl tells salsa where to find the salsa runtime.
@@ -107,10 +145,19 @@ impl ParallelDatabase for Processor {
         Snapshot::new(Processor {
             runtime: self.runtime.snapshot(self),
             fetcher: self.fetcher.clone(),
+            style_fetcher: self.style_fetcher.clone(),
             queue: self.queue.clone(),
             save_updates: self.save_updates,
             formatter: self.formatter.clone(),
+            style_text: self.style_text.clone(),
+            last_clusters: self.last_clusters.clone(),
             last_bibliography: self.last_bibliography.clone(),
+            last_named_bibliographies: self.last_named_bibliographies.clone(),
+            last_section_bibliographies: self.last_section_bibliographies.clone(),
+            // Snapshots can't edit anything, so they have nothing to announce.
+            listener: None,
+            updates_pending: false,
+            known_clusters: Default::default(),
         })
     }
 }
@@ -141,9 +188,12 @@ impl Clone for Snap {
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum SupportedFormat {
     Html,
+    /// HTML with cites and bibliography entries linked to each other.
+    LinkedHtml,
     Rtf,
     Plain,
     TestHtml,
+    Ansi,
 }
 
 impl FromStr for SupportedFormat {
@@ -151,13 +201,28 @@ impl FromStr for SupportedFormat {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
             "html" => Ok(SupportedFormat::Html),
+            "html-linked" => Ok(SupportedFormat::LinkedHtml),
             "rtf" => Ok(SupportedFormat::Rtf),
             "plain" => Ok(SupportedFormat::Plain),
+            "ansi" => Ok(SupportedFormat::Ansi),
             _ => Err(()),
         }
     }
 }
 
+impl SupportedFormat {
+    fn markup(self) -> Markup {
+        match self {
+            SupportedFormat::Html => Markup::html(),
+            SupportedFormat::LinkedHtml => Markup::linked_html(),
+            SupportedFormat::Rtf => Markup::rtf(),
+            SupportedFormat::Plain => Markup::plain(),
+            SupportedFormat::TestHtml => Markup::test_html(),
+            SupportedFormat::Ansi => Markup::ansi(),
+        }
+    }
+}
+
 impl<'de> serde::de::Deserialize<'de> for SupportedFormat {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -170,15 +235,33 @@ impl<'de> serde::de::Deserialize<'de> for SupportedFormat {
     }
 }
 
+/// For problems loading a style that aren't in its text, so have no source range.
+fn style_load_error(message: String) -> StyleError {
+    StyleError::Invalid(CslError(vec![InvalidCsl {
+        severity: Severity::Error,
+        range: 0..0,
+        message,
+        hint: "".into(),
+    }]))
+}
+
 impl Processor {
     pub(crate) fn safe_default(fetcher: Arc<dyn LocaleFetcher>) -> Self {
         let mut db = Processor {
             runtime: Default::default(),
             fetcher,
+            style_fetcher: None,
             queue: Arc::new(Mutex::new(Default::default())),
             save_updates: false,
             formatter: Markup::default(),
+            style_text: Default::default(),
+            last_clusters: Default::default(),
             last_bibliography: Arc::new(Mutex::new(SavedBib::new())),
+            last_named_bibliographies: Default::default(),
+            last_section_bibliographies: Default::default(),
+            listener: None,
+            updates_pending: false,
+            known_clusters: Default::default(),
         };
         citeproc_db::safe_default(&mut db);
         db
@@ -192,23 +275,177 @@ impl Processor {
     ) -> Result<Self, StyleError> {
         let mut db = Processor::safe_default(fetcher);
         db.save_updates = save_updates;
-        db.formatter = match format {
-            SupportedFormat::Html => Markup::html(),
-            SupportedFormat::Rtf => Markup::rtf(),
-            SupportedFormat::Plain => Markup::plain(),
-            SupportedFormat::TestHtml => Markup::test_html(),
-        };
-        let style = Arc::new(Style::from_str(style_string)?);
-        db.set_style_with_durability(style, Durability::MEDIUM);
+        db.formatter = format.markup();
+        db.load_style(style_string)?;
+        Ok(db)
+    }
+
+    /// Like `new`, but `style_string` can also be a dependent style, whose parent is fetched
+    /// from `style_fetcher` by the id in its `<link rel="independent-parent">`.
+    pub fn with_style_fetcher(
+        style_string: &str,
+        fetcher: Arc<dyn LocaleFetcher>,
+        style_fetcher: Arc<dyn StyleFetcher>,
+        save_updates: bool,
+        format: SupportedFormat,
+    ) -> Result<Self, StyleError> {
+        let mut db = Processor::safe_default(fetcher);
+        db.save_updates = save_updates;
+        db.formatter = format.markup();
+        db.style_fetcher = Some(style_fetcher);
+        db.load_style(style_string)?;
         Ok(db)
     }
 
+    /// Loads the style with id `style_id` from `style_fetcher`, which is also used to fetch its
+    /// parent if it is a dependent style. With a `StyleRepository`, this opens any style in it.
+    pub fn with_style_id(
+        style_id: &str,
+        fetcher: Arc<dyn LocaleFetcher>,
+        style_fetcher: Arc<dyn StyleFetcher>,
+        save_updates: bool,
+        format: SupportedFormat,
+    ) -> Result<Self, StyleError> {
+        let style_string = match style_fetcher.fetch_style(style_id) {
+            Ok(Some(text)) => text,
+            Ok(None) => {
+                return Err(style_load_error(format!(
+                    "style {} was not found",
+                    style_id
+                )))
+            }
+            Err(e) => {
+                return Err(style_load_error(format!(
+                    "could not fetch style {}: {:?}",
+                    style_id, e
+                )))
+            }
+        };
+        Processor::with_style_fetcher(&style_string, fetcher, style_fetcher, save_updates, format)
+    }
+
+    /// The metadata in the current style's `<info>`: its title, id, authors, categories and so
+    /// on. For a dependent style, this is the dependent style's metadata, not the parent's.
+    pub fn style_info(&self) -> Info {
+        self.style().info.clone()
+    }
+
+    /// Allows `set_style_text` to accept dependent styles from now on.
+    pub fn set_style_fetcher(&mut self, style_fetcher: Arc<dyn StyleFetcher>) {
+        self.style_fetcher = Some(style_fetcher);
+    }
+
     pub fn set_style_text(&mut self, style_text: &str) -> Result<(), StyleError> {
-        let style = Style::from_str(style_text)?;
+        self.load_style(style_text)?;
+        self.notify();
+        Ok(())
+    }
+
+    fn load_style(&mut self, style_text: &str) -> Result<(), StyleError> {
+        let style = AnyStyle::from_str(style_text)?;
+        self.use_style(style, Some(style_text))
+    }
+
+    /// `source` is the text `style` was parsed from, if parsing it again gives the same style.
+    fn use_style(&mut self, style: AnyStyle, source: Option<&str>) -> Result<(), StyleError> {
+        let (style, source) = match style {
+            AnyStyle::Independent(style) => (style, source),
+            AnyStyle::Dependent(dependent) => {
+                let parent = self.fetch_parent_style(dependent.parent_id())?;
+                (dependent.apply_to(parent), None)
+            }
+        };
+        // Otherwise keep the style as written out, so snapshots can restore it without the
+        // fetcher, or without the parts that were skipped.
+        self.style_text = Arc::new(match source {
+            Some(text) => text.to_owned(),
+            None => style.to_xml(),
+        });
         self.set_style_with_durability(Arc::new(style), Durability::MEDIUM);
         Ok(())
     }
 
+    fn fetch_parent_style(&self, id: &str) -> Result<Style, StyleError> {
+        let fetcher = self.style_fetcher.as_ref().ok_or_else(|| {
+            style_load_error(format!(
+                "dependent style needs its parent {}, but there is no StyleFetcher to get it from",
+                id
+            ))
+        })?;
+        let text = match fetcher.fetch_style(id) {
+            Ok(Some(text)) => text,
+            Ok(None) => {
+                return Err(style_load_error(format!(
+                    "parent style {} was not found",
+                    id
+                )))
+            }
+            Err(e) => {
+                return Err(style_load_error(format!(
+                    "could not fetch parent style {}: {:?}",
+                    id, e
+                )))
+            }
+        };
+        match AnyStyle::from_str(&text)? {
+            AnyStyle::Independent(style) => Ok(style),
+            AnyStyle::Dependent(_) => Err(style_load_error(format!(
+                "parent style {} is itself a dependent style",
+                id
+            ))),
+        }
+    }
+
+    /// Like `set_style_text`, but a style with mistakes in it is still used, minus the parts
+    /// that are invalid. Returns the errors that were skipped over. See
+    /// `Style::parse_with_recovery`.
+    pub fn set_style_text_recovering(
+        &mut self,
+        style_text: &str,
+    ) -> Result<Vec<InvalidCsl>, StyleError> {
+        let (style, errors) = AnyStyle::parse_with_recovery(style_text)?;
+        let source = if errors.is_empty() {
+            Some(style_text)
+        } else {
+            None
+        };
+        self.use_style(style, source)?;
+        self.notify();
+        Ok(errors)
+    }
+
+    /// Turns URL, DOI and PMID variables, and bare URLs in titles and notes, into hyperlinks in
+    /// HTML and RTF output.
+    pub fn set_link_urls(&mut self, link_urls: bool) {
+        let formatter = self.formatter.clone().with_link_urls(link_urls);
+        self.set_formatter(formatter);
+    }
+
+    /// Embeds COinS or schema.org metadata in each entry of an HTML bibliography.
+    pub fn set_html_metadata(&mut self, metadata: HtmlMetadata) {
+        let formatter = self.formatter.clone().with_html_metadata(metadata);
+        self.set_formatter(formatter);
+    }
+
+    fn set_formatter(&mut self, formatter: Markup) {
+        if self.swap_formatter(formatter) {
+            self.notify();
+        }
+    }
+
+    /// Returns whether the formatter changed.
+    fn swap_formatter(&mut self, formatter: Markup) -> bool {
+        if formatter == self.formatter {
+            return false;
+        }
+        self.formatter = formatter;
+        // The formatter isn't a salsa input, so nothing knows it changed. Everything rendered
+        // depends on the style, so setting it again makes it all recompute.
+        let style = self.style();
+        self.set_style_with_durability(style, Durability::MEDIUM);
+        true
+    }
+
     #[cfg(test)]
     pub fn test_db() -> Self {
         use citeproc_db::PredefinedLocales;
@@ -257,7 +494,9 @@ impl Processor {
         }
         self.compute();
         let mut queue = self.queue.lock().unwrap();
-        let mut summary = UpdateSummary::summarize(self, &*queue);
+        let mut delivered = self.last_clusters.lock().unwrap();
+        let mut summary = UpdateSummary::summarize(self, &*queue, &mut delivered);
+        drop(delivered);
         queue.clear();
         // Technically, you should probably have a lock over save_and_diff_bibliography as well, so
         // you get a point-in-time shapshot, but at the moment, that would mean recursively locking
@@ -266,50 +505,160 @@ impl Processor {
         if self.get_style().bibliography.is_some() {
             let bib = self.save_and_diff_bibliography();
             summary.bibliography = bib;
+            summary.named_bibliographies = self.save_and_diff_named_bibliographies();
+            summary.section_bibliographies = self.save_and_diff_section_bibliographies();
         }
         summary
     }
 
+    /// Throws away the pending updates, as if they had been delivered.
     pub fn drain(&mut self) {
         self.compute();
         let mut queue = self.queue.lock().unwrap();
+        if self.save_updates {
+            let mut delivered = self.last_clusters.lock().unwrap();
+            UpdateSummary::summarize(self, &*queue, &mut delivered);
+        }
         queue.clear();
+        self.updates_pending = false;
+    }
+
+    /// Calls `listener` with the changes whenever `flush_updates` is called after an edit,
+    /// instead of waiting to be asked with `batched_updates`. Edits themselves only mark that
+    /// there is something to announce, so a burst of them is announced as one `UpdateSummary`,
+    /// and nothing is recomputed until the caller flushes. Only edits that change some output
+    /// are announced. Needs a processor created with `save_updates`.
+    pub fn set_update_listener(&mut self, listener: Option<UpdateListener>) {
+        if listener.is_none() {
+            self.updates_pending = false;
+        }
+        self.listener = listener;
+    }
+
+    /// Whether there are edits that have not been announced yet.
+    pub fn has_pending_updates(&self) -> bool {
+        self.updates_pending
+    }
+
+    /// Announces the changes made by any edits since the last flush.
+    pub fn flush_updates(&mut self) {
+        if !self.updates_pending {
+            return;
+        }
+        self.updates_pending = false;
+        if let Some(listener) = self.listener.clone() {
+            let summary = self.batched_updates();
+            if !summary.is_empty() {
+                listener(&summary);
+            }
+        }
+    }
+
+    fn notify(&mut self) {
+        if self.listener.is_some() {
+            self.updates_pending = true;
+        }
     }
 
-    // // TODO: make this use a function exported from citeproc_proc
-    // pub fn single(&self, ref_id: &Atom) -> <Markup as OutputFormat>::Output {
-    //     let fmt = Markup::default();
-    //     let refr = match self.reference(ref_id.clone()) {
-    //         None => return fmt.output(fmt.plain("Reference not found")),
-    //         Some(r) => r,
-    //     };
-    //     let ctx = CiteContext {
-    //         reference: &refr,
-    //         cite: &Cite::basic("ok"),
-    //         position: Position::First,
-    //         format: Markup::default(),
-    //         citation_number: 1,
-    //         disamb_pass: None,
-    //     };
-    //     let style = self.style();
-    //     let mut state = IrState::new();
-    //     use crate::proc::Proc;
-    //     let ir = style.intermediate(self, &mut state, &ctx).0;
-    //     ir.flatten(&fmt)
-    //         .map(|flat| fmt.output(flat))
-    //         .unwrap_or(<Markup as OutputFormat>::Output::default())
-    // }
+    /// Renders `refr` on its own, both as a first-position cite and as a bibliography entry,
+    /// without adding it to the document. It doesn't have to be in the library. See
+    /// `citeproc_proc::standalone`.
+    pub fn render_reference(&self, refr: &Reference) -> StandaloneReference {
+        citeproc_proc::standalone::standalone_reference(self, refr)
+    }
 
+    /// Replaces the whole library with `refs`. Any reference not in `refs` is removed.
     pub fn set_references(&mut self, refs: Vec<Reference>) {
         let keys: HashSet<Atom> = refs.iter().map(|r| r.id.clone()).collect();
+        let old_keys = self.all_keys();
+        self.set_reference_inputs(refs);
+        if *old_keys != keys {
+            self.set_all_keys(Arc::new(keys.clone()));
+            for id in old_keys.difference(&keys) {
+                self.clear_reference_input(id.clone());
+            }
+        }
+        self.notify();
+    }
+
+    /// Inserts or overwrites each of `refs`, keeping the rest of the library.
+    pub fn upsert_references(&mut self, refs: Vec<Reference>) {
+        let mut keys = (*self.all_keys()).clone();
+        let before = keys.len();
+        keys.extend(refs.iter().map(|r| r.id.clone()));
+        self.set_reference_inputs(refs);
+        if keys.len() != before {
+            self.set_all_keys(Arc::new(keys));
+        }
+        self.notify();
+    }
+
+    pub fn insert_reference(&mut self, refr: Reference) {
+        self.upsert_references(vec![refr])
+    }
+
+    /// Removes a reference from the library. Cites of it will render as if it had never been
+    /// added. Returns false if there was no such reference.
+    pub fn remove_reference(&mut self, id: Atom) -> bool {
+        let mut keys = (*self.all_keys()).clone();
+        if !keys.remove(&id) {
+            return false;
+        }
+        self.set_all_keys(Arc::new(keys));
+        self.clear_reference_input(id);
+        self.notify();
+        true
+    }
+
+    /// Salsa can't remove an input, so a removed reference's is emptied instead, to free its
+    /// fields. Nothing reads it again: `reference` checks `all_keys` first, and a reference
+    /// added back with the same id is always set, as it isn't in `all_keys`.
+    fn clear_reference_input(&mut self, id: Atom) {
+        let empty = Reference::empty(id.clone(), CslType::Article);
+        self.set_reference_input(id, Arc::new(empty));
+    }
+
+    /// Changes some of the fields of a reference in the library. Returns false if there was no
+    /// such reference.
+    pub fn patch_reference(&mut self, id: Atom, patch: &ReferencePatch) -> bool {
+        let mut refr = match self.reference(id) {
+            Some(refr) => (*refr).clone(),
+            None => return false,
+        };
+        patch.apply(&mut refr);
+        self.set_reference_inputs(vec![refr]);
+        self.notify();
+        true
+    }
+
+    /// Only sets references that changed, so the clusters and bibliography entries that use the
+    /// others aren't recomputed.
+    fn set_reference_inputs(&mut self, refs: Vec<Reference>) {
+        let keys = self.all_keys();
         for r in refs {
+            if keys.contains(&r.id) && *self.reference_input(r.id.clone()) == r {
+                continue;
+            }
             self.set_reference_input(r.id.clone(), Arc::new(r));
         }
-        self.set_all_keys(Arc::new(keys));
     }
 
-    pub fn insert_reference(&mut self, refr: Reference) {
-        self.set_references(vec![refr])
+    /// Puts these references in the bibliography even if they are never cited. Replaces any
+    /// previous list. Ids not in the library are ignored until a reference with that id is added.
+    pub fn set_uncited(&mut self, ref_ids: Vec<Atom>) {
+        let set: HashSet<Atom> = ref_ids.into_iter().collect();
+        if *self.all_uncited() != set {
+            self.set_all_uncited(Arc::new(set));
+            self.notify();
+        }
+    }
+
+    /// Puts every reference in the library in the bibliography, like pandoc's `nocite: @*`.
+    pub fn include_all_uncited(&mut self, include: bool) {
+        if self.nocite_all() != include {
+            self.set_nocite_all(include);
+            self.notify();
+        }
     }
 
     pub fn init_clusters(&mut self, clusters: Vec<Cluster<Markup>>) {
@@ -328,7 +677,9 @@ impl Processor {
             self.set_cluster_note_number(cluster_id, None);
             cluster_ids.push(cluster_id);
         }
+        self.known_clusters = cluster_ids.iter().cloned().collect();
         self.set_cluster_ids(Arc::new(cluster_ids));
+        self.notify();
     }
 
     // cluster_ids is maintained manually
@@ -344,6 +695,8 @@ impl Processor {
             .cloned()
             .collect();
         self.set_cluster_ids(Arc::new(cluster_ids));
+        self.known_clusters.remove(&cluster_id);
+        self.notify();
     }
 
     pub fn insert_cluster(&mut self, cluster: Cluster<Markup>) {
@@ -365,6 +718,8 @@ impl Processor {
         }
         self.set_cluster_cites(cluster_id, Arc::new(ids));
         self.set_cluster_note_number(cluster_id, None);
+        self.known_clusters.insert(cluster_id);
+        self.notify();
     }
 
     pub fn renumber_clusters(&mut self, mappings: &[(u32, ClusterNumber)]) {
@@ -373,6 +728,7 @@ impl Processor {
             let n = chunk.1;
             self.set_cluster_note_number(cluster_id, Some(n));
         }
+        self.notify();
     }
 
     // Getters, because the query groups have too much exposed to publish.
@@ -390,18 +746,34 @@ impl Processor {
         }
     }
 
+    /// Like `get_cluster`, but returns a tree of spans for each cite, annotated with the
+    /// variables that produced them. See `citeproc_proc::semantic`.
+    pub fn get_cluster_spans(&self, cluster_id: ClusterId) -> Option<Vec<CiteSpans>> {
+        if self.cluster_note_number(cluster_id).is_some() {
+            Some(citeproc_proc::semantic::cluster_spans(self, cluster_id))
+        } else {
+            None
+        }
+    }
+
     pub fn get_bib_item(&self, ref_id: Atom) -> Arc<MarkupOutput> {
         self.bib_item(ref_id)
     }
 
-    fn get_bibliography_map(&self) -> FnvHashMap<Atom, Arc<MarkupOutput>> {
-        let sorted_refs = self.sorted_refs();
-        let mut m = FnvHashMap::with_capacity_and_hasher(
-            sorted_refs.0.len(),
-            fnv::FnvBuildHasher::default(),
-        );
-        for key in sorted_refs.0.iter() {
-            m.insert(key.clone(), self.bib_item(key.clone()));
+    fn get_bibliography_map(
+        &self,
+        keys: &[Atom],
+        which: &WhichBibliography,
+    ) -> FnvHashMap<Atom, Arc<MarkupOutput>> {
+        let mut m =
+            FnvHashMap::with_capacity_and_hasher(keys.len(), fnv::FnvBuildHasher::default());
+        for key in keys.iter() {
+            let item = match which {
+                WhichBibliography::Main => self.bib_item(key.clone()),
+                WhichBibliography::Section(section) => self.section_bib_item(*section, key.clone()),
+                WhichBibliography::Named(name) => self.named_bib_item(name.clone(), key.clone()),
+            };
+            m.insert(key.clone(), item);
         }
         m
     }
@@ -427,20 +799,83 @@ impl Processor {
 
     fn save_and_diff_bibliography(&self) -> Option<BibliographyUpdate> {
         let mut last_bibliography = self.last_bibliography.lock().unwrap();
-        let new = self.get_bibliography_map();
-        let old = std::mem::replace(&mut *last_bibliography, SavedBib::new());
+        self.diff_bibliography(
+            &mut last_bibliography,
+            self.sorted_refs(),
+            &WhichBibliography::Main,
+        )
+    }
+
+    /// Diffs each named bibliography against what was last delivered for it. Bibliographies that
+    /// were removed are forgotten, so they start from scratch if they are added again.
+    fn save_and_diff_named_bibliographies(&self) -> Vec<(Atom, BibliographyUpdate)> {
+        let mut last = self.last_named_bibliographies.lock().unwrap();
+        let names = self.bibliography_names();
+        last.retain(|name, _| names.contains(name));
+        let mut updates = Vec::new();
+        for name in names.iter() {
+            let saved = last.entry(name.clone()).or_insert_with(SavedBib::new);
+            let filtered = self.filtered_refs(name.clone());
+            let which = WhichBibliography::Named(name.clone());
+            if let Some(update) = self.diff_bibliography(saved, filtered, &which) {
+                updates.push((name.clone(), update));
+            }
+        }
+        updates
+    }
+
+    /// Like `save_and_diff_named_bibliographies`, for each section of the document.
+    fn save_and_diff_section_bibliographies(&self) -> Vec<(u32, BibliographyUpdate)> {
+        let mut last = self.last_section_bibliographies.lock().unwrap();
+        let count = self.section_count();
+        last.retain(|&section, _| section < count);
+        let mut updates = Vec::new();
+        for section in 0..count {
+            let saved = last.entry(section).or_insert_with(SavedBib::new);
+            let refs = self.section_refs(section);
+            let which = WhichBibliography::Section(section);
+            if let Some(update) = self.diff_bibliography(saved, refs, &which) {
+                updates.push((section, update));
+            }
+        }
+        updates
+    }
+
+    fn diff_bibliography(
+        &self,
+        last_bibliography: &mut SavedBib,
+        sorted_refs: Arc<(Vec<Atom>, FnvHashMap<Atom, u32>)>,
+        which: &WhichBibliography,
+    ) -> Option<BibliographyUpdate> {
+        let new = self.get_bibliography_map(&sorted_refs.0, which);
+        let old = std::mem::replace(last_bibliography, SavedBib::new());
         let mut update = BibliographyUpdate::new();
-        for (k, v) in new.iter() {
-            let old_v = old.bib_entries.get(k);
-            if Some(v) != old_v {
-                update.updated_entries.insert(k.clone(), v.clone());
+        let cited_keys = self.cited_keys();
+        for k in sorted_refs.0.iter() {
+            let v = new.get(k);
+            if v.is_some() && v != old.bib_entries.get(k) {
+                update.updated_entries.push(self.bib_entry(
+                    k.clone(),
+                    which,
+                    &sorted_refs.1,
+                    &cited_keys,
+                ));
+            }
+        }
+        // New entries are already in updated_entries, with their numbers.
+        for k in sorted_refs.0.iter() {
+            if let (Some(&number), Some(&old_number)) =
+                (sorted_refs.1.get(k), old.sorted_refs.1.get(k))
+            {
+                if number != old_number {
+                    update.renumbered.push((k.clone(), number));
+                }
             }
         }
         last_bibliography.bib_entries = new;
-        let sorted_refs = self.sorted_refs();
+        last_bibliography.sorted_refs = sorted_refs.clone();
         if sorted_refs != old.sorted_refs {
             update.entry_ids = Some(sorted_refs.0.clone());
-            last_bibliography.sorted_refs = sorted_refs;
             Some(update)
         } else if update.updated_entries.is_empty() {
             None
@@ -457,6 +892,118 @@ impl Processor {
             .collect()
     }
 
+    /// Like `get_bibliography`, but each entry comes with its reference id, its number, whether
+    /// it was cited, and the first-field split for styles that use second-field-align.
+    pub fn get_bibliography_entries(&self) -> Vec<BibEntry> {
+        let sorted_refs = self.sorted_refs();
+        let cited_keys = self.cited_keys();
+        sorted_refs
+            .0
+            .iter()
+            .map(|k| {
+                self.bib_entry(
+                    k.clone(),
+                    &WhichBibliography::Main,
+                    &sorted_refs.1,
+                    &cited_keys,
+                )
+            })
+            .collect()
+    }
+
+    fn bib_entry(
+        &self,
+        ref_id: Atom,
+        which: &WhichBibliography,
+        numbers: &FnvHashMap<Atom, u32>,
+        cited_keys: &HashSet<Atom>,
+    ) -> BibEntry {
+        let (value, first_field) = match which {
+            WhichBibliography::Main => (
+                self.bib_item(ref_id.clone()),
+                self.bib_item_first_field(ref_id.clone()),
+            ),
+            WhichBibliography::Section(section) => (
+                self.section_bib_item(*section, ref_id.clone()),
+                self.section_bib_item_first_field(*section, ref_id.clone()),
+            ),
+            WhichBibliography::Named(name) => (
+                self.named_bib_item(name.clone(), ref_id.clone()),
+                self.named_bib_item_first_field(name.clone(), ref_id.clone()),
+            ),
+        };
+        let first_field = first_field.map(|split| FirstField {
+            first: split.0.clone(),
+            rest: split.1.clone(),
+        });
+        BibEntry {
+            value,
+            number: numbers.get(&ref_id).cloned().unwrap_or(0),
+            first_field,
+            cited: cited_keys.contains(&ref_id),
+            id: ref_id,
+        }
+    }
+
+    /// How many sections the document has. Zero if it has no section starts, in which case there
+    /// is only the main bibliography.
+    pub fn section_count(&self) -> u32 {
+        if self.section_starts().is_empty() {
+            return 0;
+        }
+        self.clusters_sorted()
+            .last()
+            .map_or(0, |cluster| cluster.section + 1)
+    }
+
+    /// The bibliography for one section of the document: the references cited in it, numbered
+    /// from 1.
+    pub fn get_section_bibliography(&self, section: u32) -> Vec<BibEntry> {
+        let refs = self.section_refs(section);
+        let cited_keys = self.cited_keys();
+        refs.0
+            .iter()
+            .map(|k| {
+                let which = WhichBibliography::Section(section);
+                self.bib_entry(k.clone(), &which, &refs.1, &cited_keys)
+            })
+            .collect()
+    }
+
+    /// Replaces the set of named bibliographies. Each one holds the entries of the main
+    /// bibliography that pass its filter, in the same order, but numbered from 1.
+    pub fn set_bibliography_filters(&mut self, filters: Vec<(Atom, BibliographyFilter)>) {
+        let names: Vec<Atom> = filters.iter().map(|(name, _)| name.clone()).collect();
+        let old_names = self.bibliography_names();
+        for (name, filter) in filters {
+            if old_names.contains(&name) && *self.bibliography_filter(name.clone()) == filter {
+                continue;
+            }
+            self.set_bibliography_filter(name, Arc::new(filter));
+        }
+        if *old_names != names {
+            self.set_bibliography_names(Arc::new(names));
+        }
+        self.notify();
+    }
+
+    /// The entries of a named bibliography, or None if there is no bibliography by that name.
+    pub fn get_named_bibliography(&self, name: Atom) -> Option<Vec<BibEntry>> {
+        if !self.bibliography_names().contains(&name) {
+            return None;
+        }
+        let filtered = self.filtered_refs(name.clone());
+        let cited_keys = self.cited_keys();
+        let which = WhichBibliography::Named(name);
+        Some(
+            filtered
+                .0
+                .iter()
+                .map(|k| self.bib_entry(k.clone(), &which, &filtered.1, &cited_keys))
+                .collect(),
+        )
+    }
+
     pub fn get_reference(&self, ref_id: Atom) -> Option<Arc<Reference>> {
         self.reference(ref_id)
     }
@@ -472,6 +1019,7 @@ impl Processor {
             self.set_locale_input_xml_with_durability(lang, Arc::new(xml), Durability::MEDIUM);
         }
         self.set_locale_input_langs(Arc::new(langs));
+        self.notify();
     }
 
     pub fn get_langs_in_use(&self) -> Vec<Lang> {
@@ -492,7 +1040,7 @@ impl Processor {
     }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ClusterPosition {
     pub id: ClusterId,
     /// If this is None, the piece is an in-text cluster. If it is Some, it is a note cluster.
@@ -506,6 +1054,12 @@ pub enum ErrorKind {
         "set_cluster_order called with a note number {0} that was out of order (e.g. [1, 2, 3, 1])"
     )]
     NonMonotonicNoteNumber(u32),
+    #[error("cluster id {0} appeared more than once")]
+    DuplicateClusterId(ClusterId),
+    #[error("reference id {0} appeared more than once")]
+    DuplicateReferenceId(Atom),
+    #[error("no cluster with id {0} has been inserted")]
+    UnknownCluster(ClusterId),
 }
 
 impl Processor {
@@ -531,8 +1085,27 @@ impl Processor {
     /// determining cite positions (ibid, subsequent, etc). But the position:first cites within
     /// them will all have the same first-reference-note-number if FRNN is used in later cites.
     ///
-    /// May error without having set_cluster_ids, but with some set_cluster_note_number-s executed.
+    /// Errors if the note numbers go backwards or a cluster appears twice, in which case nothing
+    /// is changed. Use a `Transaction` to change the order along with other edits.
     pub fn set_cluster_order(&mut self, pieces: &[ClusterPosition]) -> Result<(), ErrorKind> {
+        self::transaction::validate_cluster_order(pieces)?;
+        self.apply_cluster_order(pieces);
+        self.notify();
+        Ok(())
+    }
+
+    /// Starts a new section (e.g. a chapter) at each of these clusters. Positions and citation
+    /// numbers start again in each section, and each one gets its own bibliography. Clusters that
+    /// aren't in the document are ignored, so this can be set before or after the cluster order.
+    pub fn set_sections(&mut self, section_starts: Vec<ClusterId>) {
+        if *self.section_starts() != section_starts {
+            self.set_section_starts(Arc::new(section_starts));
+            self.notify();
+        }
+    }
+
+    /// `set_cluster_order` for pieces that have already been validated, without announcing it.
+    fn apply_cluster_order(&mut self, pieces: &[ClusterPosition]) {
         let mut cluster_ids = Vec::with_capacity(pieces.len());
         let mut intext_number = 1u32;
         // (note number, next index)
@@ -540,9 +1113,7 @@ impl Processor {
         for piece in pieces {
             if let Some(nn) = piece.note {
                 if let Some(ref mut note) = this_note {
-                    if nn < note.0 {
-                        return Err(ErrorKind::NonMonotonicNoteNumber(nn));
-                    } else if nn == note.0 {
+                    if nn == note.0 {
                         // This note number ended up having more than one index in it;
                         let (num, ref mut index) = *note;
                         let i = *index;
@@ -551,7 +1122,7 @@ impl Processor {
                             piece.id,
                             Some(ClusterNumber::Note(IntraNote::Multi(num, i))),
                         );
-                    } else if nn > note.0 {
+                    } else {
                         self.set_cluster_note_number(
                             piece.id,
                             Some(ClusterNumber::Note(IntraNote::Multi(nn, 0))),
@@ -576,6 +1147,112 @@ impl Processor {
         }
         // This removes any clusters that did not appear.
         self.set_cluster_ids(Arc::new(cluster_ids));
-        Ok(())
+    }
+
+    /// Renders `cluster` as if it were in the document, without modifying the document or
+    /// queueing any updates. Like citeproc-js' `previewCitationCluster`.
+    ///
+    /// If `cluster.id` is already in the document, the preview replaces it; otherwise it is a new
+    /// cluster. Either way it is placed just before `before`, or, if that is `None`, where the
+    /// existing cluster is or at the end of the document. `note` is its note number, as in a
+    /// `ClusterPosition`, and has to fit between its neighbours' ones. The rest of the document
+    /// keeps its order and note numbers. `format` defaults to the processor's own.
+    ///
+    /// The preview is rendered on a fork of this processor, so nothing computed for it is kept,
+    /// and nothing already computed here is reused.
+    pub fn preview_cluster(
+        &self,
+        cluster: Cluster<Markup>,
+        note: Option<u32>,
+        before: Option<ClusterId>,
+        format: Option<SupportedFormat>,
+    ) -> Result<Arc<MarkupOutput>, ErrorKind> {
+        let id = cluster.id;
+        let cluster_ids = self.cluster_ids();
+        let mut pieces: Vec<ClusterPosition> = cluster_ids
+            .iter()
+            .map(|&cluster_id| ClusterPosition {
+                id: cluster_id,
+                note: self.cluster_note_number(cluster_id).and_then(|n| match n {
+                    ClusterNumber::Note(intra) => Some(intra.note_number()),
+                    ClusterNumber::InText(_) => None,
+                }),
+            })
+            .collect();
+        let existing = pieces.iter().position(|piece| piece.id == id);
+        let index = match before {
+            Some(before) => {
+                if let Some(i) = existing {
+                    pieces.remove(i);
+                }
+                pieces
+                    .iter()
+                    .position(|piece| piece.id == before)
+                    .ok_or(ErrorKind::UnknownCluster(before))?
+            }
+            None => match existing {
+                Some(i) => {
+                    pieces.remove(i);
+                    i
+                }
+                None => pieces.len(),
+            },
+        };
+        pieces.insert(index, ClusterPosition { id, note });
+        self::transaction::validate_cluster_order(&pieces)?;
+
+        let formatter = format.map_or_else(|| self.formatter.clone(), SupportedFormat::markup);
+        let mut fork = self.fork(formatter);
+        let cites = cluster
+            .cites
+            .into_iter()
+            .enumerate()
+            .map(|(index, cite)| fork.cite(id, index as u32, Arc::new(cite)))
+            .collect();
+        fork.set_cluster_cites(id, Arc::new(cites));
+        fork.apply_cluster_order(&pieces);
+        Ok(fork.built_cluster(id))
+    }
+
+    /// A processor with the same inputs as this one and the given formatter, but none of what
+    /// has been computed from them. Edits to it don't touch this one or queue any updates.
+    fn fork(&self, formatter: Markup) -> Processor {
+        let mut db = Processor::safe_default(self.fetcher.clone());
+        db.formatter = formatter;
+        db.style_text = self.style_text.clone();
+        db.set_style_with_durability(self.style(), Durability::MEDIUM);
+        let langs = self.locale_input_langs();
+        for lang in langs.iter() {
+            let xml = self.locale_input_xml(lang.clone());
+            db.set_locale_input_xml_with_durability(lang.clone(), xml, Durability::MEDIUM);
+        }
+        db.set_locale_input_langs(langs);
+        let keys = self.all_keys();
+        for key in keys.iter() {
+            db.set_reference_input(key.clone(), self.reference_input(key.clone()));
+        }
+        db.set_all_keys(keys);
+        db.set_all_uncited(self.all_uncited());
+        db.set_nocite_all(self.nocite_all());
+        let names = self.bibliography_names();
+        for name in names.iter() {
+            db.set_bibliography_filter(name.clone(), self.bibliography_filter(name.clone()));
+        }
+        db.set_bibliography_names(names);
+        // Cites are interned, so each has to be interned again in the fork.
+        let cluster_ids = self.cluster_ids();
+        for &id in cluster_ids.iter() {
+            let cites = self
+                .cluster_cites(id)
+                .iter()
+                .enumerate()
+                .map(|(index, cite_id)| db.cite(id, index as u32, cite_id.lookup(self)))
+                .collect();
+            db.set_cluster_cites(id, Arc::new(cites));
+            db.set_cluster_note_number(id, self.cluster_note_number(id));
+        }
+        db.set_cluster_ids(cluster_ids);
+        db.set_section_starts(self.section_starts());
+        db
     }
 }
@@ -0,0 +1,174 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright © 2019 Corporation for Digital Scholarship
+
+//! Saving everything a `Processor` was given, so an equivalent one can be made later without
+//! feeding it the whole library and document again.
+
+use super::{Processor, SupportedFormat};
+use crate::prelude::*;
+use csl::{Lang, StyleError};
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// The `version` written by `Processor::take_snapshot`. Bumped whenever the encoding changes.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// All the inputs to a `Processor`: style, locales, references and the document. Serialize it
+/// with any serde format; references and cites are stored as CSL-JSON.
+///
+/// The output format settings aren't included, as they aren't part of the document. Pass them to
+/// `Processor::from_snapshot` like you would to `Processor::new`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProcessorSnapshot {
+    pub version: u32,
+    pub style: String,
+    /// Pairs of language tag and locale XML
+    pub locales: Vec<(String, String)>,
+    pub references: Vec<Reference>,
+    pub uncited: Vec<Atom>,
+    pub nocite_all: bool,
+    pub bibliography_filters: Vec<(Atom, BibliographyFilter)>,
+    /// Every cluster that has been inserted, in or out of the document
+    pub clusters: Vec<SnapshotCluster>,
+    /// The clusters in the document, in order
+    pub cluster_ids: Vec<ClusterId>,
+    pub section_starts: Vec<ClusterId>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SnapshotCluster {
+    pub id: ClusterId,
+    pub cites: Vec<Cite<Markup>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub number: Option<ClusterNumber>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RestoreError {
+    #[error("snapshot version {0} is not supported")]
+    UnsupportedVersion(u32),
+    #[error("the snapshot's style could not be parsed: {0:?}")]
+    Style(StyleError),
+    #[error("the snapshot has a locale for an invalid language tag `{0}`")]
+    InvalidLang(String),
+}
+
+impl Processor {
+    /// Saves the style, locales, references and document, for `Processor::from_snapshot`.
+    pub fn take_snapshot(&self) -> ProcessorSnapshot {
+        let mut langs: Vec<Lang> = self.locale_input_langs().iter().cloned().collect();
+        langs.sort_by_key(|lang| lang.to_string());
+        let locales = langs
+            .into_iter()
+            .map(|lang| {
+                let xml = self.locale_input_xml(lang.clone());
+                (lang.to_string(), (*xml).clone())
+            })
+            .collect();
+
+        let mut keys: Vec<Atom> = self.all_keys().iter().cloned().collect();
+        keys.sort();
+        let references = keys
+            .into_iter()
+            .map(|key| (*self.reference_input(key)).clone())
+            .collect();
+
+        let mut uncited: Vec<Atom> = self.all_uncited().iter().cloned().collect();
+        uncited.sort();
+
+        let bibliography_filters = self
+            .bibliography_names()
+            .iter()
+            .map(|name| {
+                let filter = self.bibliography_filter(name.clone());
+                (name.clone(), (*filter).clone())
+            })
+            .collect();
+
+        let cluster_ids = self.cluster_ids();
+        let mut all_clusters: Vec<ClusterId> = self
+            .known_clusters
+            .iter()
+            .chain(cluster_ids.iter())
+            .cloned()
+            .collect();
+        all_clusters.sort();
+        all_clusters.dedup();
+        let clusters = all_clusters
+            .into_iter()
+            .map(|id| SnapshotCluster {
+                id,
+                cites: self
+                    .cluster_cites(id)
+                    .iter()
+                    .map(|&cite_id| (*cite_id.lookup(self)).clone())
+                    .collect(),
+                number: self.cluster_note_number(id),
+            })
+            .collect();
+
+        ProcessorSnapshot {
+            version: SNAPSHOT_VERSION,
+            style: (*self.style_text).clone(),
+            locales,
+            references,
+            uncited,
+            nocite_all: self.nocite_all(),
+            bibliography_filters,
+            clusters,
+            cluster_ids: (*cluster_ids).clone(),
+            section_starts: (*self.section_starts()).clone(),
+        }
+    }
+
+    /// Creates a processor with everything in `snapshot`, which renders exactly like the one it
+    /// was taken from. The other arguments are as for `Processor::new`.
+    pub fn from_snapshot(
+        snapshot: ProcessorSnapshot,
+        fetcher: Arc<dyn LocaleFetcher>,
+        save_updates: bool,
+        format: SupportedFormat,
+    ) -> Result<Self, RestoreError> {
+        let ProcessorSnapshot {
+            version,
+            style,
+            locales,
+            references,
+            uncited,
+            nocite_all,
+            bibliography_filters,
+            clusters,
+            cluster_ids,
+            section_starts,
+        } = snapshot;
+        if version != SNAPSHOT_VERSION {
+            return Err(RestoreError::UnsupportedVersion(version));
+        }
+        let mut db =
+            Processor::new(&style, fetcher, save_updates, format).map_err(RestoreError::Style)?;
+        let locales = locales
+            .into_iter()
+            .map(|(tag, xml)| match Lang::from_str(&tag) {
+                Ok(lang) => Ok((lang, xml)),
+                Err(_) => Err(RestoreError::InvalidLang(tag)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        db.store_locales(locales);
+        db.set_references(references);
+        db.set_uncited(uncited);
+        db.include_all_uncited(nocite_all);
+        db.set_bibliography_filters(bibliography_filters);
+        for SnapshotCluster { id, cites, number } in clusters {
+            db.insert_cluster(Cluster { id, cites });
+            db.set_cluster_note_number(id, number);
+        }
+        db.set_cluster_ids(Arc::new(cluster_ids));
+        db.set_section_starts(Arc::new(section_starts));
+        Ok(db)
+    }
+}
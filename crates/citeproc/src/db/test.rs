@@ -11,6 +11,66 @@ use csl::*;
 
 use super::Processor;
 
+/// Setup shared by the tests below, so each only spells out what it is testing.
+mod fixtures {
+    use super::*;
+    use crate::prelude::*;
+    use citeproc_db::PredefinedLocales;
+
+    /// No locales, so only the bundled en-US is available.
+    pub fn locales() -> Arc<dyn LocaleFetcher> {
+        Arc::new(PredefinedLocales(Default::default()))
+    }
+
+    /// A style with the given `<layout>`s for its citation and, if there is one, bibliography.
+    pub fn style(class: &str, citation: &str, bibliography: Option<&str>) -> String {
+        let bibliography = bibliography
+            .map(|layout| format!("<bibliography>{}</bibliography>", layout))
+            .unwrap_or_default();
+        format!(
+            r#"<?xml version="1.0" encoding="utf-8"?>
+            <style class="{}" version="1.0"><citation>{}</citation>{}</style>"#,
+            class, citation, bibliography
+        )
+    }
+
+    /// Only tests of what `batched_updates` reports need `save_updates`.
+    pub fn processor(style: &str, format: SupportedFormat, save_updates: bool) -> Processor {
+        Processor::new(style, locales(), save_updates, format).unwrap()
+    }
+
+    pub fn book(id: &str, title: &str) -> Reference {
+        let mut refr = Reference::empty(id.into(), CslType::Book);
+        refr.ordinary.insert(Variable::Title, title.into());
+        refr
+    }
+
+    pub fn cluster(id: ClusterId, ref_ids: &[&str]) -> Cluster<Markup> {
+        Cluster {
+            id,
+            cites: ref_ids.iter().map(|&ref_id| Cite::basic(ref_id)).collect(),
+        }
+    }
+
+    pub fn note(id: ClusterId, note: u32) -> ClusterPosition {
+        ClusterPosition {
+            id,
+            note: Some(note),
+        }
+    }
+
+    /// Puts each of `clusters` in its own footnote, in order.
+    pub fn cite_in_notes(db: &mut Processor, clusters: Vec<Cluster<Markup>>) {
+        let order: Vec<_> = clusters
+            .iter()
+            .enumerate()
+            .map(|(i, cluster)| note(cluster.id, i as u32 + 1))
+            .collect();
+        db.init_clusters(clusters);
+        db.set_cluster_order(&order).unwrap();
+    }
+}
+
 mod position {
     use super::*;
     use crate::prelude::*;
@@ -257,3 +317,1433 @@ mod terms {
         )
     }
 }
+
+mod semantic {
+    use super::*;
+    use crate::prelude::*;
+    use crate::{SemanticSpan, SpanKind};
+
+    fn leaves(span: &SemanticSpan, out: &mut Vec<(SpanKind, Option<String>, String)>) {
+        if span.children.is_empty() {
+            out.push((span.kind, span.variable.clone(), span.output.clone()));
+        }
+        for child in &span.children {
+            leaves(child, out);
+        }
+    }
+
+    #[test]
+    fn variables_are_annotated() {
+        let style = fixtures::style(
+            "note",
+            r#"<layout>
+                <group delimiter=", ">
+                    <text variable="title" />
+                    <text value="published by" />
+                    <text variable="publisher" />
+                </group>
+            </layout>"#,
+            None,
+        );
+        let mut db = fixtures::processor(&style, SupportedFormat::Plain, false);
+        let mut refr = fixtures::book("one", "The Title");
+        refr.ordinary
+            .insert(Variable::Publisher, "Publisher".into());
+        db.insert_reference(refr);
+        fixtures::cite_in_notes(&mut db, vec![fixtures::cluster(1, &["one"])]);
+        let spans = db.get_cluster_spans(1).unwrap();
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].cite_id, "1-0");
+        assert_eq!(spans[0].ref_id, Atom::from("one"));
+        let span = spans[0].span.as_ref().unwrap();
+        assert_eq!(span.output, "The Title, published by, Publisher");
+        let mut got = Vec::new();
+        leaves(span, &mut got);
+        assert_eq!(
+            got,
+            vec![
+                (
+                    SpanKind::Variable,
+                    Some("title".to_owned()),
+                    "The Title".to_owned()
+                ),
+                (SpanKind::Text, None, "published by".to_owned()),
+                (
+                    SpanKind::Variable,
+                    Some("publisher".to_owned()),
+                    "Publisher".to_owned()
+                ),
+            ]
+        );
+    }
+}
+
+mod linked_html {
+    use super::*;
+    use crate::prelude::*;
+
+    #[test]
+    fn cites_and_entries_link_both_ways() {
+        let layout = r#"<layout><text variable="title" /></layout>"#;
+        let style = fixtures::style("in-text", layout, Some(layout));
+        let mut db = fixtures::processor(&style, SupportedFormat::LinkedHtml, false);
+        db.insert_reference(fixtures::book("one", "Title"));
+        fixtures::cite_in_notes(
+            &mut db,
+            vec![
+                fixtures::cluster(1, &["one"]),
+                fixtures::cluster(2, &["one"]),
+            ],
+        );
+        assert_eq!(
+            &*db.get_cluster(1).unwrap(),
+            r##"<span class="csl-cite" id="cite-1-0" data-cite-id="1-0" data-ref-id="one"><a href="#ref-one">Title</a></span>"##
+        );
+        assert_eq!(
+            &*db.get_bib_item("one".into()),
+            "<div class=\"csl-entry\" id=\"ref-one\">Title <span class=\"csl-backrefs\">\
+             <a href=\"#cite-1-0\">\u{21A9}</a> <a href=\"#cite-2-0\">\u{21A9}</a></span></div>"
+        );
+    }
+
+    #[test]
+    fn cites_with_urls_are_not_linked_twice() {
+        let layout = r#"<layout><text variable="title" /></layout>"#;
+        let style = fixtures::style("in-text", layout, Some(layout));
+        let mut db = fixtures::processor(&style, SupportedFormat::LinkedHtml, false);
+        db.set_link_urls(true);
+        db.insert_reference(fixtures::book("one", "See https://example.com/a"));
+        fixtures::cite_in_notes(&mut db, vec![fixtures::cluster(1, &["one"])]);
+        assert_eq!(
+            &*db.get_cluster(1).unwrap(),
+            r#"<span class="csl-cite" id="cite-1-0" data-cite-id="1-0" data-ref-id="one">See <a href="https://example.com/a">https://example.com/a</a></span>"#
+        );
+    }
+}
+
+mod links {
+    use super::*;
+    use crate::prelude::*;
+
+    fn render_with_links(link_urls: bool) -> String {
+        let style = fixtures::style(
+            "in-text",
+            r#"<layout delimiter=". ">
+                <group delimiter=". ">
+                    <text variable="title" />
+                    <text variable="DOI" />
+                </group>
+            </layout>"#,
+            None,
+        );
+        let mut db = fixtures::processor(&style, SupportedFormat::Html, false);
+        let mut refr = fixtures::book("one", "See https://example.com/a here");
+        refr.ordinary
+            .insert(Variable::DOI, "doi:10.1000/xyz".into());
+        db.insert_reference(refr);
+        fixtures::cite_in_notes(&mut db, vec![fixtures::cluster(1, &["one"])]);
+        // Render once before changing the option, to check it invalidates
+        let _ = db.get_cluster(1);
+        db.set_link_urls(link_urls);
+        (*db.get_cluster(1).unwrap()).clone()
+    }
+
+    #[test]
+    fn only_variables_linked_by_default() {
+        assert_eq!(
+            render_with_links(false),
+            "See https://example.com/a here. <a href=\"doi:10.1000/xyz\">doi:10.1000/xyz</a>"
+        );
+    }
+
+    #[test]
+    fn dois_and_bare_urls() {
+        assert_eq!(
+            render_with_links(true),
+            "See <a href=\"https://example.com/a\">https://example.com/a</a> here. \
+             <a href=\"https://doi.org/10.1000/xyz\">doi:10.1000/xyz</a>"
+        );
+    }
+}
+
+mod preview {
+    use super::*;
+    use crate::prelude::*;
+
+    #[test]
+    fn preview_ibid_leaves_document_alone() {
+        let style = fixtures::style(
+            "note",
+            r#"<layout>
+                <choose>
+                    <if position="ibid">
+                        <text value="ibid" />
+                    </if>
+                    <else>
+                        <text variable="title" />
+                    </else>
+                </choose>
+            </layout>"#,
+            None,
+        );
+        let mut db = fixtures::processor(&style, SupportedFormat::Plain, true);
+        db.insert_reference(fixtures::book("one", "Title"));
+        fixtures::cite_in_notes(&mut db, vec![fixtures::cluster(1, &["one"])]);
+        db.batched_updates();
+
+        let preview = fixtures::cluster(2, &["one"]);
+        let built = db
+            .preview_cluster(preview.clone(), Some(2), None, None)
+            .unwrap();
+        assert_eq!(&*built, "ibid");
+
+        // Placed before cluster 1, it's the first cite instead.
+        let built = db
+            .preview_cluster(preview, Some(1), Some(1), Some(SupportedFormat::Html))
+            .unwrap();
+        assert_eq!(&*built, "Title");
+
+        // Editing an existing cluster in place
+        let edit = fixtures::cluster(1, &["missing"]);
+        let built = db.preview_cluster(edit, Some(1), None, None).unwrap();
+        assert_eq!(&*built, "???");
+
+        let unknown = fixtures::cluster(3, &[]);
+        assert!(db.preview_cluster(unknown, None, Some(9), None).is_err());
+
+        assert_eq!(*db.cluster_ids(), vec![1]);
+        assert_eq!(db.get_cluster(2), None);
+        assert_eq!(&*db.get_cluster(1).unwrap(), "Title");
+        assert_eq!(db.batched_updates().clusters, vec![]);
+    }
+}
+
+mod standalone {
+    use super::*;
+    use crate::prelude::*;
+
+    #[test]
+    fn renders_without_touching_document() {
+        let style = fixtures::style(
+            "in-text",
+            r#"<layout prefix="(" suffix=")"><text variable="title" /></layout>"#,
+            Some(r#"<layout suffix="."><text variable="title" font-style="italic" /></layout>"#),
+        );
+        let db = fixtures::processor(&style, SupportedFormat::Html, false);
+        let refr = fixtures::book("search-result", "Title");
+        let rendered = db.render_reference(&refr);
+        assert_eq!(rendered.citation, "(Title)");
+        assert_eq!(rendered.bib_entry, Some("<i>Title</i>.".to_owned()));
+        assert!(db.get_reference("search-result".into()).is_none());
+        assert!(db.get_bibliography().is_empty());
+    }
+}
+
+mod bibliography_entries {
+    use super::*;
+    use crate::prelude::*;
+
+    #[test]
+    fn entries_with_numbers_and_first_field() {
+        let style = fixtures::style(
+            "in-text",
+            r#"<layout><text variable="citation-number" /></layout>"#,
+            Some(
+                r#"<layout suffix=".">
+                    <text variable="citation-number" prefix="[" suffix="]" />
+                    <text variable="title" />
+                </layout>"#,
+            ),
+        )
+        .replace(
+            "<bibliography>",
+            r#"<bibliography second-field-align="flush">"#,
+        );
+        let mut db = fixtures::processor(&style, SupportedFormat::Html, false);
+        db.insert_reference(fixtures::book("one", "First"));
+        db.insert_reference(fixtures::book("two", "Second"));
+        fixtures::cite_in_notes(&mut db, vec![fixtures::cluster(1, &["two", "one"])]);
+        let entries = db.get_bibliography_entries();
+        let summary: Vec<_> = entries
+            .iter()
+            .map(|e| {
+                let split = e.first_field.as_ref().unwrap();
+                (
+                    e.id.to_string(),
+                    e.number,
+                    e.cited,
+                    (*e.value).clone(),
+                    split.first.clone(),
+                    split.rest.clone(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (
+                    "two".to_owned(),
+                    1,
+                    true,
+                    "[1]Second.".to_owned(),
+                    "[1]".to_owned(),
+                    "Second.".to_owned()
+                ),
+                (
+                    "one".to_owned(),
+                    2,
+                    true,
+                    "[2]First.".to_owned(),
+                    "[2]".to_owned(),
+                    "First.".to_owned()
+                ),
+            ]
+        );
+    }
+}
+
+mod uncited {
+    use super::*;
+    use crate::prelude::*;
+
+    fn processor() -> Processor {
+        let style = fixtures::style(
+            "in-text",
+            r#"<layout><text variable="citation-number" /></layout>"#,
+            Some(
+                r#"<layout>
+                    <text variable="citation-number" suffix=". " />
+                    <text variable="title" />
+                </layout>"#,
+            ),
+        );
+        let mut db = fixtures::processor(&style, SupportedFormat::Html, true);
+        db.set_references(
+            ["a", "b", "c"]
+                .iter()
+                .map(|id| fixtures::book(id, &id.to_uppercase()))
+                .collect(),
+        );
+        fixtures::cite_in_notes(&mut db, vec![fixtures::cluster(1, &["c"])]);
+        db
+    }
+
+    fn entries(db: &Processor) -> Vec<(String, u32, bool)> {
+        db.get_bibliography_entries()
+            .iter()
+            .map(|e| (e.id.to_string(), e.number, e.cited))
+            .collect()
+    }
+
+    #[test]
+    fn uncited_come_after_cited() {
+        let mut db = processor();
+        db.batched_updates();
+        db.set_uncited(vec!["b".into(), "not-in-library".into()]);
+        assert_eq!(
+            entries(&db),
+            vec![("c".to_owned(), 1, true), ("b".to_owned(), 2, false)]
+        );
+        let update = db.batched_updates().bibliography.unwrap();
+        assert_eq!(
+            update.entry_ids,
+            Some(vec![Atom::from("c"), Atom::from("b")])
+        );
+        assert_eq!(update.updated_entries.len(), 1);
+        assert_eq!(&*update.updated_entries[0].value, "2. B");
+    }
+
+    #[test]
+    fn include_all() {
+        let mut db = processor();
+        db.include_all_uncited(true);
+        assert_eq!(
+            entries(&db),
+            vec![
+                ("c".to_owned(), 1, true),
+                ("a".to_owned(), 2, false),
+                ("b".to_owned(), 3, false),
+            ]
+        );
+        db.include_all_uncited(false);
+        assert_eq!(entries(&db), vec![("c".to_owned(), 1, true)]);
+    }
+}
+
+mod named_bibliographies {
+    use super::*;
+    use crate::prelude::*;
+    use crate::BibEntry;
+
+    fn keyword(kw: &str) -> BibliographyFilter {
+        BibliographyFilter {
+            select: vec![FieldMatch::new("keyword", kw)],
+            ..Default::default()
+        }
+    }
+
+    fn ids(entries: &[BibEntry]) -> Vec<(String, u32)> {
+        entries
+            .iter()
+            .map(|e| (e.id.to_string(), e.number))
+            .collect()
+    }
+
+    #[test]
+    fn split_by_keyword() {
+        let style = fixtures::style(
+            "in-text",
+            r#"<layout><text variable="citation-number" /></layout>"#,
+            Some(
+                r#"<layout>
+                    <text variable="citation-number" suffix=". " />
+                    <text variable="title" />
+                </layout>"#,
+            ),
+        );
+        let mut db = fixtures::processor(&style, SupportedFormat::Plain, true);
+        db.set_references(
+            [("a", "primary"), ("b", "secondary"), ("c", "primary")]
+                .iter()
+                .map(|(id, kw)| {
+                    let mut refr = fixtures::book(id, &id.to_uppercase());
+                    refr.ordinary.insert(Variable::Keyword, (*kw).into());
+                    refr
+                })
+                .collect(),
+        );
+        fixtures::cite_in_notes(&mut db, vec![fixtures::cluster(1, &["a", "b", "c"])]);
+        db.set_bibliography_filters(vec![
+            ("primary".into(), keyword("primary")),
+            ("secondary".into(), keyword("secondary")),
+        ]);
+        // Each named bibliography is numbered from 1
+        let primary = db.get_named_bibliography("primary".into()).unwrap();
+        assert_eq!(
+            ids(&primary),
+            vec![("a".to_owned(), 1), ("c".to_owned(), 2)]
+        );
+        assert_eq!(&*primary[1].value, "2. C");
+        let secondary = db.get_named_bibliography("secondary".into()).unwrap();
+        assert_eq!(ids(&secondary), vec![("b".to_owned(), 1)]);
+        assert_eq!(&*secondary[0].value, "1. B");
+        assert!(db.get_named_bibliography("other".into()).is_none());
+
+        let summary = db.batched_updates();
+        let names: Vec<_> = summary
+            .named_bibliographies
+            .iter()
+            .map(|(name, update)| (name.to_string(), update.entry_ids.clone()))
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                (
+                    "primary".to_owned(),
+                    Some(vec![Atom::from("a"), Atom::from("c")])
+                ),
+                ("secondary".to_owned(), Some(vec![Atom::from("b")])),
+            ]
+        );
+
+        // Changing one filter only updates that bibliography
+        db.set_bibliography_filters(vec![
+            ("primary".into(), keyword("primary")),
+            ("secondary".into(), keyword("primary")),
+        ]);
+        let summary = db.batched_updates();
+        assert_eq!(summary.named_bibliographies.len(), 1);
+        assert_eq!(&*summary.named_bibliographies[0].0, "secondary");
+    }
+}
+
+mod sections {
+    use super::*;
+    use crate::prelude::*;
+
+    #[test]
+    fn positions_and_numbers_restart() {
+        let style = fixtures::style(
+            "note",
+            r#"<layout>
+                <choose>
+                    <if position="ibid">
+                        <text value="ibid" />
+                    </if>
+                    <else>
+                        <text variable="citation-number" prefix="[" suffix="]" />
+                    </else>
+                </choose>
+            </layout>"#,
+            Some(
+                r#"<layout>
+                    <text variable="citation-number" suffix=". " />
+                    <text variable="title" />
+                </layout>"#,
+            ),
+        );
+        let mut db = fixtures::processor(&style, SupportedFormat::Html, true);
+        db.set_references(vec![fixtures::book("a", "A"), fixtures::book("b", "B")]);
+        fixtures::cite_in_notes(
+            &mut db,
+            vec![
+                fixtures::cluster(1, &["a"]),
+                fixtures::cluster(2, &["b"]),
+                fixtures::cluster(3, &["b"]),
+            ],
+        );
+        assert_eq!(db.section_count(), 0);
+        assert_eq!(&*db.get_cluster(2).unwrap(), "[2]");
+        assert_eq!(&*db.get_cluster(3).unwrap(), "ibid");
+
+        db.set_sections(vec![2]);
+        assert_eq!(db.section_count(), 2);
+        assert_eq!(&*db.get_cluster(2).unwrap(), "[1]");
+        assert_eq!(&*db.get_cluster(3).unwrap(), "ibid");
+        let section: Vec<_> = db
+            .get_section_bibliography(1)
+            .iter()
+            .map(|e| (e.id.to_string(), (*e.value).clone()))
+            .collect();
+        assert_eq!(section, vec![("b".to_owned(), "1. B".to_owned())]);
+        // The main bibliography still numbers the whole document
+        assert_eq!(db.get_bibliography(), vec!["1. A", "2. B"]);
+
+        let summary = db.batched_updates();
+        let sections: Vec<_> = summary
+            .section_bibliographies
+            .iter()
+            .map(|(section, update)| (*section, update.entry_ids.clone()))
+            .collect();
+        assert_eq!(
+            sections,
+            vec![
+                (0, Some(vec![Atom::from("a")])),
+                (1, Some(vec![Atom::from("b")])),
+            ]
+        );
+    }
+
+    #[test]
+    fn section_on_first_cluster() {
+        let style = fixtures::style(
+            "in-text",
+            r#"<layout><text variable="citation-number" /></layout>"#,
+            Some(r#"<layout><text variable="title" /></layout>"#),
+        );
+        let mut db = fixtures::processor(&style, SupportedFormat::Plain, true);
+        db.set_references(vec![fixtures::book("a", "A"), fixtures::book("b", "B")]);
+        fixtures::cite_in_notes(
+            &mut db,
+            vec![fixtures::cluster(1, &["a"]), fixtures::cluster(2, &["b"])],
+        );
+        db.set_sections(vec![1, 2]);
+        // No empty section before the first cluster
+        assert_eq!(db.section_count(), 2);
+        assert_eq!(db.cluster_section(1), Some(0));
+        assert_eq!(&*db.get_cluster(2).unwrap(), "1");
+        let sections: Vec<_> = db
+            .batched_updates()
+            .section_bibliographies
+            .iter()
+            .map(|(section, update)| (*section, update.entry_ids.clone()))
+            .collect();
+        assert_eq!(
+            sections,
+            vec![
+                (0, Some(vec![Atom::from("a")])),
+                (1, Some(vec![Atom::from("b")])),
+            ]
+        );
+    }
+
+    #[test]
+    fn first_cite_in_section_is_first() {
+        let style = fixtures::style(
+            "note",
+            r#"<layout>
+                <choose>
+                    <if position="first">
+                        <text value="first" />
+                    </if>
+                    <else>
+                        <text value="subsequent" />
+                    </else>
+                </choose>
+            </layout>"#,
+            None,
+        );
+        let mut db = fixtures::processor(&style, SupportedFormat::Html, false);
+        db.insert_reference(Reference::empty("a".into(), CslType::Book));
+        db.init_clusters(vec![
+            fixtures::cluster(1, &["a"]),
+            fixtures::cluster(2, &["a"]),
+        ]);
+        db.set_cluster_order(&[fixtures::note(1, 1), fixtures::note(2, 20)])
+            .unwrap();
+        db.set_sections(vec![2]);
+        assert_eq!(&*db.get_cluster(2).unwrap(), "first");
+    }
+}
+
+mod update_summary {
+    use super::*;
+    use crate::prelude::*;
+
+    fn book(id: &str, title: &str, publisher: &str) -> Reference {
+        let mut refr = fixtures::book(id, title);
+        refr.ordinary.insert(Variable::Publisher, publisher.into());
+        refr
+    }
+
+    fn order(ids: &[ClusterId]) -> Vec<ClusterPosition> {
+        ids.iter()
+            .enumerate()
+            .map(|(i, &id)| fixtures::note(id, i as u32 + 1))
+            .collect()
+    }
+
+    #[test]
+    fn only_real_changes() {
+        let style = fixtures::style(
+            "note",
+            r#"<layout><text variable="title" /></layout>"#,
+            Some(
+                r#"<layout>
+                    <text variable="citation-number" suffix=". " />
+                    <text variable="title" />
+                </layout>"#,
+            ),
+        );
+        let mut db = fixtures::processor(&style, SupportedFormat::Plain, true);
+        db.set_references(vec![book("a", "A", "P"), book("b", "B", "P")]);
+        db.init_clusters(vec![
+            fixtures::cluster(1, &["b"]),
+            fixtures::cluster(2, &["a"]),
+        ]);
+        db.set_cluster_order(&order(&[2, 1])).unwrap();
+        let summary = db.batched_updates();
+        // Document order, not id order
+        let ids: Vec<_> = summary.clusters.iter().map(|(id, _)| *id).collect();
+        assert_eq!(ids, vec![2, 1]);
+        // Every entry is new, so none of them was renumbered
+        let bibliography = summary.bibliography.unwrap();
+        assert_eq!(bibliography.updated_entries.len(), 2);
+        assert_eq!(bibliography.renumbered, vec![]);
+
+        // Recomputed, but the output is the same
+        db.set_references(vec![book("a", "A", "Q"), book("b", "B", "P")]);
+        let summary = db.batched_updates();
+        assert_eq!(summary.clusters, vec![]);
+        assert!(summary.bibliography.is_none());
+
+        db.set_cluster_order(&order(&[1, 2])).unwrap();
+        let summary = db.batched_updates();
+        assert_eq!(summary.clusters, vec![]);
+        assert_eq!(
+            summary.bibliography.unwrap().renumbered,
+            vec![(Atom::from("b"), 1), (Atom::from("a"), 2)]
+        );
+
+        db.remove_cluster(2);
+        let summary = db.batched_updates();
+        assert_eq!(summary.clusters, vec![]);
+        assert_eq!(summary.removed_clusters, vec![2]);
+    }
+}
+
+mod listener {
+    use super::fixtures::book;
+    use super::*;
+    use crate::prelude::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn announced_when_flushed() {
+        let style = fixtures::style(
+            "note",
+            r#"<layout><text variable="title" /></layout>"#,
+            None,
+        );
+        let mut db = fixtures::processor(&style, SupportedFormat::Plain, true);
+        let received: Arc<Mutex<Vec<UpdateSummary>>> = Default::default();
+        let sink = received.clone();
+        db.set_update_listener(Some(Arc::new(move |summary: &UpdateSummary| {
+            sink.lock().unwrap().push(summary.clone())
+        })));
+
+        db.set_references(vec![book("a", "A")]);
+        assert!(db.has_pending_updates());
+        db.flush_updates();
+        // Nothing is cited yet, so nothing changed.
+        assert_eq!(received.lock().unwrap().len(), 0);
+        fixtures::cite_in_notes(&mut db, vec![fixtures::cluster(1, &["a"])]);
+        db.flush_updates();
+        {
+            let received = received.lock().unwrap();
+            assert_eq!(received.len(), 1);
+            assert_eq!(received[0].clusters, vec![(1, Arc::new("A".to_owned()))]);
+        }
+
+        db.set_references(vec![book("a", "B")]);
+        db.set_references(vec![book("a", "C")]);
+        assert!(db.has_pending_updates());
+        // The edits alone don't render anything
+        assert!(db.queue.lock().unwrap().is_empty());
+        assert_eq!(received.lock().unwrap().len(), 1);
+        db.flush_updates();
+        assert!(!db.has_pending_updates());
+        {
+            let received = received.lock().unwrap();
+            assert_eq!(received.len(), 2);
+            assert_eq!(received[1].clusters, vec![(1, Arc::new("C".to_owned()))]);
+        }
+
+        // Without a listener, edits are left for batched_updates.
+        db.set_update_listener(None);
+        db.set_references(vec![book("a", "D")]);
+        assert!(!db.has_pending_updates());
+        assert_eq!(received.lock().unwrap().len(), 2);
+        assert_eq!(
+            db.batched_updates().clusters,
+            vec![(1, Arc::new("D".to_owned()))]
+        );
+    }
+}
+
+mod transactions {
+    use super::fixtures::{book, note};
+    use super::*;
+    use crate::prelude::*;
+    use crate::ErrorKind;
+    use std::collections::HashSet;
+
+    fn cluster(id: ClusterId, ref_id: &str) -> Cluster<Markup> {
+        fixtures::cluster(id, &[ref_id])
+    }
+
+    fn setup() -> Processor {
+        let style = fixtures::style(
+            "note",
+            r#"<layout><text variable="title" /></layout>"#,
+            None,
+        );
+        let mut db = fixtures::processor(&style, SupportedFormat::Plain, false);
+        db.set_references(vec![book("a", "A"), book("b", "B")]);
+        fixtures::cite_in_notes(&mut db, vec![cluster(1, "a")]);
+        db
+    }
+
+    #[test]
+    fn applies_everything() {
+        let mut db = setup();
+        db.commit(
+            Transaction::new()
+                .insert_cluster(cluster(2, "b"))
+                .set_cluster_order(vec![note(2, 1), note(1, 2)]),
+        )
+        .unwrap();
+        assert_eq!(db.get_cluster(2), Some(Arc::new("B".to_owned())));
+        assert_eq!(*db.cluster_ids(), vec![2, 1]);
+    }
+
+    #[test]
+    fn applies_nothing() {
+        let mut db = setup();
+        let invalid = |db: &mut Processor, txn: Transaction| {
+            let err = db.commit(txn).unwrap_err();
+            // Still just the one cluster, where it was
+            assert_eq!(*db.cluster_ids(), vec![1]);
+            let keys: HashSet<Atom> = ["a", "b"].iter().map(|&k| k.into()).collect();
+            assert_eq!(*db.all_keys(), keys);
+            err
+        };
+        let err = invalid(
+            &mut db,
+            Transaction::new()
+                .insert_cluster(cluster(2, "b"))
+                .set_references(vec![book("a", "A")])
+                .set_cluster_order(vec![note(1, 2), note(2, 1)]),
+        );
+        assert!(matches!(err, ErrorKind::NonMonotonicNoteNumber(1)));
+        let err = invalid(
+            &mut db,
+            Transaction::new()
+                .insert_cluster(cluster(2, "b"))
+                .set_cluster_order(vec![note(1, 1), note(2, 2), note(1, 3)]),
+        );
+        assert!(matches!(err, ErrorKind::DuplicateClusterId(1)));
+        let err = invalid(
+            &mut db,
+            Transaction::new()
+                .remove_cluster(1)
+                .set_cluster_order(vec![note(1, 1)]),
+        );
+        assert!(matches!(err, ErrorKind::UnknownCluster(1)));
+        let err = invalid(
+            &mut db,
+            Transaction::new().set_references(vec![book("c", "C"), book("c", "D")]),
+        );
+        assert!(matches!(err, ErrorKind::DuplicateReferenceId(_)));
+
+        // The old set_cluster_order footgun leaves nothing behind either
+        assert!(db.set_cluster_order(&[note(1, 2), note(2, 1)]).is_err());
+        assert_eq!(
+            db.cluster_note_number(1),
+            Some(ClusterNumber::Note(IntraNote::Multi(1, 0)))
+        );
+    }
+
+    /// The clusters a transaction may refer to are tracked outside salsa, so check they follow
+    /// the document through snapshots, previews, and edits outside transactions.
+    #[test]
+    fn known_clusters_stay_consistent() {
+        let mut db = setup();
+        // Inserted, but not in the document
+        db.insert_cluster(cluster(2, "b"));
+        db.set_cluster_order(&[note(1, 1)]).unwrap();
+        db.preview_cluster(cluster(3, "b"), Some(2), None, None)
+            .unwrap();
+
+        let mut restored = Processor::from_snapshot(
+            db.take_snapshot(),
+            fixtures::locales(),
+            false,
+            SupportedFormat::Plain,
+        )
+        .unwrap();
+        for db in [&mut db, &mut restored].iter_mut() {
+            let err = db
+                .commit(Transaction::new().set_cluster_order(vec![note(1, 1), note(3, 2)]))
+                .unwrap_err();
+            assert!(matches!(err, ErrorKind::UnknownCluster(3)));
+            db.commit(Transaction::new().set_cluster_order(vec![note(1, 1), note(2, 2)]))
+                .unwrap();
+            assert_eq!(db.get_cluster(2), Some(Arc::new("B".to_owned())));
+
+            db.remove_cluster(2);
+            let err = db
+                .commit(Transaction::new().set_cluster_order(vec![note(2, 1)]))
+                .unwrap_err();
+            assert!(matches!(err, ErrorKind::UnknownCluster(2)));
+        }
+    }
+}
+
+mod reference_edits {
+    use super::fixtures::book;
+    use super::*;
+    use crate::prelude::*;
+
+    #[test]
+    fn upsert_remove_patch() {
+        let style = fixtures::style(
+            "note",
+            r#"<layout delimiter="; ">
+                <text variable="title" />
+                <text variable="publisher" prefix=", " />
+            </layout>"#,
+            None,
+        );
+        let mut db = fixtures::processor(&style, SupportedFormat::Plain, true);
+        db.insert_reference(book("a", "A"));
+        db.insert_reference(book("b", "B"));
+        fixtures::cite_in_notes(
+            &mut db,
+            vec![fixtures::cluster(1, &["a"]), fixtures::cluster(2, &["b"])],
+        );
+        // Both survived the second insert_reference
+        assert_eq!(db.get_cluster(1), Some(Arc::new("A".to_owned())));
+        assert_eq!(db.get_cluster(2), Some(Arc::new("B".to_owned())));
+        db.drain();
+
+        // Only the cluster citing the patched reference changes
+        let mut patch = ReferencePatch::default();
+        patch.ordinary.insert(Variable::Publisher, Some("P".into()));
+        assert!(db.patch_reference("b".into(), &patch));
+        assert_eq!(
+            db.batched_updates().clusters,
+            vec![(2, Arc::new("B, P".to_owned()))]
+        );
+        patch.ordinary.insert(Variable::Publisher, None);
+        patch.ordinary.insert(Variable::Title, Some("C".into()));
+        assert!(db.patch_reference("b".into(), &patch));
+        assert_eq!(db.get_cluster(2), Some(Arc::new("C".to_owned())));
+        assert!(!db.patch_reference("z".into(), &patch));
+
+        // Unchanged references are left alone
+        db.drain();
+        db.upsert_references(vec![book("a", "A"), book("c", "C")]);
+        assert_eq!(db.batched_updates().clusters, vec![]);
+        assert_eq!(db.all_keys().len(), 3);
+
+        assert!(db.remove_reference("a".into()));
+        assert!(!db.remove_reference("a".into()));
+        assert_eq!(db.all_keys().len(), 2);
+        assert_ne!(db.get_cluster(1), Some(Arc::new("A".to_owned())));
+        // The removed reference's fields aren't kept around, or seen again when it comes back
+        assert!(db.reference_input("a".into()).ordinary.is_empty());
+        db.insert_reference(book("a", "A"));
+        assert_eq!(db.get_cluster(1), Some(Arc::new("A".to_owned())));
+        db.set_references(vec![book("a", "A")]);
+        assert!(db.reference_input("b".into()).ordinary.is_empty());
+        assert!(db.reference_input("c".into()).ordinary.is_empty());
+    }
+}
+
+mod diagnostics {
+    use super::*;
+    use crate::prelude::*;
+    use crate::DiagnosticKind::*;
+    use citeproc_io::{DateOrRange, NumericValue};
+    use std::str::FromStr;
+
+    #[test]
+    fn collects_warnings() {
+        let style = fixtures::style(
+            "note",
+            r#"<layout>
+                <text variable="title" />
+                <number variable="edition" />
+                <text variable="volume" />
+            </layout>"#,
+            None,
+        )
+        .replace("<style ", r#"<style default-locale="de-DE" "#);
+        let mut db = fixtures::processor(&style, SupportedFormat::Plain, false);
+        let mut refr = Reference::empty("a".into(), CslType::Book);
+        refr.date.insert(
+            DateVariable::Issued,
+            DateOrRange::Literal("spring, maybe".into()),
+        );
+        refr.number.insert(
+            NumberVariable::Edition,
+            NumericValue::Str("2nd edition".into()),
+        );
+        // Only printed with <text>, which prints any value as written
+        refr.number
+            .insert(NumberVariable::Volume, NumericValue::Str("two".into()));
+        db.set_references(vec![refr]);
+        let located: Cite<Markup> =
+            serde_yaml::from_str("{ id: a, locator: \"5\", label: leaf }").unwrap();
+        let cluster = Cluster {
+            id: 1,
+            cites: vec![Cite::basic("a"), located, Cite::basic("missing")],
+        };
+        fixtures::cite_in_notes(&mut db, vec![cluster]);
+
+        let diagnostics = db.diagnostics();
+        let found: Vec<_> = diagnostics
+            .iter()
+            .map(|d| {
+                (
+                    d.kind,
+                    d.cluster_id,
+                    d.cite_index,
+                    d.ref_id.as_ref().map(|r| r.to_string()),
+                )
+            })
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (UnknownLocatorLabel, Some(1), Some(1), Some("a".to_owned())),
+                (
+                    MissingReference,
+                    Some(1),
+                    Some(2),
+                    Some("missing".to_owned())
+                ),
+                (LiteralDate, None, None, Some("a".to_owned())),
+                (NonNumericValue, None, None, Some("a".to_owned())),
+                (MissingLocale, None, None, None),
+            ]
+        );
+
+        db.store_locales(vec![(Lang::from_str("de-DE").unwrap(), "<locale".into())]);
+        let kinds: Vec<_> = db.diagnostics().iter().map(|d| d.kind).collect();
+        assert_eq!(kinds[4..], [InvalidLocale, MissingLocale]);
+        assert!(serde_yaml::to_string(&NonNumericValue)
+            .unwrap()
+            .contains("nonNumericValue"));
+    }
+}
+
+mod snapshots {
+    use super::*;
+    use crate::prelude::*;
+    use crate::{ProcessorSnapshot, RestoreError};
+    use citeproc_io::{DateOrRange, Name, NumericValue, PersonName};
+    use std::str::FromStr;
+
+    fn processor() -> Processor {
+        let style = fixtures::style(
+            "note",
+            r#"<layout delimiter="; ">
+                <group delimiter=", ">
+                    <names variable="author" />
+                    <text variable="title" />
+                    <date variable="issued" form="numeric" />
+                    <number variable="volume" />
+                    <text variable="locator" />
+                </group>
+            </layout>"#,
+            Some(r#"<layout><text variable="title" /></layout>"#),
+        )
+        .replace("<style ", r#"<style default-locale="en-AU" "#);
+        fixtures::processor(&style, SupportedFormat::Plain, true)
+    }
+
+    fn restore(snapshot: ProcessorSnapshot) -> Result<Processor, RestoreError> {
+        Processor::from_snapshot(snapshot, fixtures::locales(), true, SupportedFormat::Plain)
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut db = processor();
+        let mut refr = fixtures::book("a", "Title");
+        refr.name.insert(
+            NameVariable::Author,
+            vec![Name::Person(PersonName {
+                family: Some("Smith".into()),
+                given: Some("Jo".into()),
+                non_dropping_particle: None,
+                dropping_particle: None,
+                suffix: None,
+            })],
+        );
+        refr.date
+            .insert(DateVariable::Issued, DateOrRange::new(2001, 2, 0));
+        refr.number
+            .insert(NumberVariable::Volume, NumericValue::num(3));
+        let uncited = Reference::empty("b".into(), CslType::Book);
+        db.set_references(vec![refr, uncited]);
+        db.set_uncited(vec!["b".into()]);
+        db.store_locales(vec![(
+            Lang::from_str("en-AU").unwrap(),
+            r#"<?xml version="1.0" encoding="utf-8"?>
+            <locale xmlns="http://purl.org/net/xbiblio/csl" version="1.0" xml:lang="en-AU">
+            <terms><term name="and">und</term></terms></locale>"#
+                .into(),
+        )]);
+        let located: Cite<Markup> =
+            serde_yaml::from_str("{ id: a, locator: \"5\", label: chapter }").unwrap();
+        db.init_clusters(vec![
+            Cluster {
+                id: 1,
+                cites: vec![located],
+            },
+            fixtures::cluster(2, &["a"]),
+            fixtures::cluster(3, &["b"]),
+        ]);
+        db.set_cluster_order(&[fixtures::note(2, 1), fixtures::note(1, 2)])
+            .unwrap();
+        db.set_sections(vec![1]);
+
+        let snapshot = db.take_snapshot();
+        let encoded = serde_yaml::to_string(&snapshot).unwrap();
+        let restored = restore(serde_yaml::from_str(&encoded).unwrap()).unwrap();
+
+        for id in 1..=3 {
+            assert_eq!(restored.get_cluster(id), db.get_cluster(id));
+        }
+        assert_eq!(restored.get_bibliography(), db.get_bibliography());
+        assert_eq!(restored.take_snapshot().references, snapshot.references);
+        assert_eq!(restored.take_snapshot().locales, snapshot.locales);
+        assert_eq!(*restored.section_starts(), vec![1]);
+        // Cluster 3 was inserted but isn't in the document
+        assert_eq!(restored.take_snapshot().clusters.len(), 3);
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let mut snapshot = processor().take_snapshot();
+        snapshot.version += 1;
+        assert!(matches!(
+            restore(snapshot),
+            Err(RestoreError::UnsupportedVersion(_))
+        ));
+    }
+}
+
+mod style_usage {
+    use super::*;
+    use crate::prelude::*;
+
+    #[test]
+    fn follows_style_changes() {
+        let style = fixtures::style(
+            "note",
+            r#"<layout>
+                <choose>
+                    <if type="book">
+                        <text variable="publisher" />
+                    </if>
+                    <else>
+                        <text variable="container-title" />
+                    </else>
+                </choose>
+            </layout>"#,
+            None,
+        )
+        .replace(
+            "<citation>",
+            r#"<macro name="unused"><text variable="note" /></macro><citation>"#,
+        );
+        let mut db = fixtures::processor(&style, SupportedFormat::Plain, false);
+        assert_eq!(db.style_usage().unused_macros, vec![Atom::from("unused")]);
+        let book = db.variables_for_type(CslType::Book);
+        assert!(book.contains(&AnyVariable::Ordinary(Variable::Publisher)));
+        assert!(!book.contains(&AnyVariable::Ordinary(Variable::ContainerTitle)));
+
+        db.set_style_text(&style.replace(r#"type="book""#, r#"type="chapter""#))
+            .unwrap();
+        let book = db.variables_for_type(CslType::Book);
+        assert!(!book.contains(&AnyVariable::Ordinary(Variable::Publisher)));
+        assert!(book.contains(&AnyVariable::Ordinary(Variable::ContainerTitle)));
+    }
+}
+
+mod incomplete_references {
+    use super::*;
+    use crate::prelude::*;
+    use citeproc_io::Name;
+
+    #[test]
+    fn reports_missing_variables() {
+        let style = fixtures::style(
+            "in-text",
+            r#"<layout>
+                <names variable="author">
+                    <substitute>
+                        <names variable="editor" />
+                    </substitute>
+                </names>
+                <choose>
+                    <if type="article-journal">
+                        <text variable="container-title" />
+                    </if>
+                </choose>
+                <choose>
+                    <if variable="DOI">
+                        <text variable="DOI" />
+                    </if>
+                </choose>
+                <date variable="issued" form="numeric" />
+            </layout>"#,
+            None,
+        );
+        let mut db = fixtures::processor(&style, SupportedFormat::Plain, false);
+        let article = Reference::empty("article".into(), CslType::ArticleJournal);
+        let mut book = Reference::empty("book".into(), CslType::Book);
+        book.name.insert(
+            NameVariable::Editor,
+            vec![Name::Literal {
+                literal: "Editor".into(),
+            }],
+        );
+        book.date.insert(
+            DateVariable::Issued,
+            citeproc_io::DateOrRange::new(2000, 0, 0),
+        );
+        db.set_references(vec![article, book]);
+        db.include_all_uncited(true);
+
+        assert_eq!(
+            db.incomplete_references(),
+            vec![crate::IncompleteReference {
+                ref_id: "article".into(),
+                missing: vec![
+                    AnyVariable::Name(NameVariable::Author),
+                    AnyVariable::Ordinary(Variable::ContainerTitle),
+                    AnyVariable::Date(DateVariable::Issued),
+                ],
+            }]
+        );
+        assert!(db.missing_variables("book".into()).is_empty());
+        assert!(db.missing_variables("nonexistent".into()).is_empty());
+    }
+}
+
+mod recovering_style {
+    use super::*;
+    use crate::prelude::*;
+
+    #[test]
+    fn renders_with_partial_style() {
+        let style = fixtures::style(
+            "note",
+            r#"<layout><text variable="title" /></layout>"#,
+            None,
+        );
+        let mut db = fixtures::processor(&style, SupportedFormat::Plain, false);
+        db.set_references(vec![fixtures::book("a", "Title")]);
+        fixtures::cite_in_notes(&mut db, vec![fixtures::cluster(1, &["a"])]);
+
+        let broken = style.replace(
+            r#"<text variable="title" />"#,
+            r#"<text variable="title" prefix="See " /><text variable="nonsense" />"#,
+        );
+        assert!(db.set_style_text(&broken).is_err());
+        let errors = db.set_style_text_recovering(&broken).unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(&*db.get_cluster(1).unwrap(), "See Title");
+
+        // The snapshot holds what was recovered, not the invalid text
+        let restored = Processor::from_snapshot(
+            db.take_snapshot(),
+            fixtures::locales(),
+            false,
+            SupportedFormat::Plain,
+        )
+        .unwrap();
+        assert_eq!(restored.style(), db.style());
+    }
+}
+
+mod dependent_styles {
+    use super::*;
+    use crate::prelude::*;
+    use std::str::FromStr;
+
+    const PARENT: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+    <style class="note" version="1.0.1" default-locale="en-US">
+        <citation>
+            <layout>
+                <text variable="title" prefix="Parent: " />
+            </layout>
+        </citation>
+    </style>"#;
+
+    const DEPENDENT: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+    <style class="note" version="1.0" default-locale="fr-FR">
+        <info>
+            <link href="http://example.com/dependent" rel="self"/>
+            <link href="http://example.com/parent" rel="independent-parent"/>
+            <category field="history"/>
+        </info>
+    </style>"#;
+
+    fn render(db: &mut Processor) -> Arc<String> {
+        db.set_references(vec![fixtures::book("a", "Title")]);
+        fixtures::cite_in_notes(db, vec![fixtures::cluster(1, &["a"])]);
+        db.get_cluster(1).unwrap()
+    }
+
+    #[test]
+    fn resolves_parent() {
+        let fetcher = fixtures::locales();
+        let independent_only =
+            Processor::new(DEPENDENT, fetcher.clone(), false, SupportedFormat::Plain);
+        assert!(independent_only.is_err());
+
+        let mut parents = HashMap::new();
+        parents.insert("http://example.com/parent".to_owned(), PARENT.to_owned());
+        let mut db = Processor::with_style_fetcher(
+            DEPENDENT,
+            fetcher.clone(),
+            Arc::new(PredefinedStyles(parents)),
+            false,
+            SupportedFormat::Plain,
+        )
+        .unwrap();
+        let style = db.style();
+        assert_eq!(
+            style.info.independent_parent(),
+            Some("http://example.com/parent")
+        );
+        assert_eq!(style.info.categories, vec![Category::History]);
+        assert_eq!(style.default_locale, Lang::from_str("fr-FR").unwrap());
+        assert_eq!(&*render(&mut db), "Parent: Title");
+
+        // The snapshot holds the resolved style, so restoring it needs no StyleFetcher
+        let restored =
+            Processor::from_snapshot(db.take_snapshot(), fetcher, false, SupportedFormat::Plain)
+                .unwrap();
+        assert_eq!(restored.style(), style);
+    }
+
+    #[test]
+    fn resolves_parent_when_recovering() {
+        let mut parents = HashMap::new();
+        parents.insert("http://example.com/parent".to_owned(), PARENT.to_owned());
+        let mut db = Processor::with_style_fetcher(
+            PARENT,
+            fixtures::locales(),
+            Arc::new(PredefinedStyles(parents)),
+            false,
+            SupportedFormat::Plain,
+        )
+        .unwrap();
+        let errors = db.set_style_text_recovering(DEPENDENT).unwrap();
+        assert!(errors.is_empty());
+        assert_eq!(db.style().default_locale, Lang::from_str("fr-FR").unwrap());
+        assert_eq!(&*render(&mut db), "Parent: Title");
+    }
+
+    #[test]
+    fn missing_parent() {
+        let result = Processor::with_style_fetcher(
+            DEPENDENT,
+            fixtures::locales(),
+            Arc::new(PredefinedStyles(Default::default())),
+            false,
+            SupportedFormat::Plain,
+        );
+        assert!(result.is_err());
+    }
+}
+
+mod style_repository {
+    use super::*;
+    use crate::prelude::*;
+    use crate::StyleRepository;
+    use std::fs;
+
+    fn write_styles(dir: &std::path::Path) {
+        fs::create_dir_all(dir.join("dependent")).unwrap();
+        fs::write(
+            dir.join("chicago-note.csl"),
+            r#"<style class="note" version="1.0">
+                <info>
+                    <title>Chicago Manual of Style (note)</title>
+                    <id>http://example.com/chicago-note</id>
+                    <category citation-format="note"/>
+                    <category field="generic-base"/>
+                </info>
+                <citation><layout><text variable="title" prefix="Chicago: "/></layout></citation>
+            </style>"#,
+        )
+        .unwrap();
+        fs::write(
+            dir.join("dependent").join("history-journal.csl"),
+            r#"<style class="note" version="1.0">
+                <info>
+                    <title>Journal of History</title>
+                    <title-short>JH</title-short>
+                    <id>http://example.com/history-journal</id>
+                    <link href="http://example.com/chicago-note" rel="independent-parent"/>
+                    <category field="history"/>
+                </info>
+            </style>"#,
+        )
+        .unwrap();
+        fs::write(dir.join("broken.csl"), "<style").unwrap();
+        fs::write(dir.join("README.md"), "not a style").unwrap();
+    }
+
+    #[test]
+    fn index_and_load() {
+        let dir = std::env::temp_dir().join(format!("citeproc-style-repo-{}", std::process::id()));
+        write_styles(&dir);
+        let repo = StyleRepository::index(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(repo.entries().len(), 2);
+        assert_eq!(repo.skipped(), &[dir.join("broken.csl")][..]);
+        let journal = repo.get("http://example.com/history-journal").unwrap();
+        assert!(journal.is_dependent());
+        assert_eq!(
+            repo.independent(journal.id()).unwrap().title(),
+            "Chicago Manual of Style (note)"
+        );
+        let history: Vec<_> = repo.with_category(Category::History).collect();
+        assert_eq!(history.len(), 1);
+
+        let titles = |query| -> Vec<&str> {
+            repo.search(query, 10)
+                .into_iter()
+                .map(|e| e.title())
+                .collect()
+        };
+        assert_eq!(
+            titles("chicago note"),
+            vec!["Chicago Manual of Style (note)"]
+        );
+        assert_eq!(titles("jh"), vec!["Journal of History"]);
+        assert_eq!(titles("history"), vec!["Journal of History"]);
+        assert_eq!(titles("man"), vec!["Chicago Manual of Style (note)"]);
+        assert!(titles("zotero").is_empty());
+    }
+
+    #[test]
+    fn first_found_duplicate_wins() {
+        let dir = std::env::temp_dir().join(format!("citeproc-style-dup-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let style = |title: &str| {
+            format!(
+                r#"<style class="note" version="1.0">
+                    <info><title>{}</title><id>http://example.com/dup</id></info>
+                    <citation><layout/></citation>
+                </style>"#,
+                title
+            )
+        };
+        // Found first, but sorts after the other by title
+        fs::write(dir.join("a.csl"), style("Zeta")).unwrap();
+        fs::write(dir.join("b.csl"), style("Alpha")).unwrap();
+        fs::write(dir.join("latin1.csl"), b"<style>\xe9</style>").unwrap();
+        let repo = StyleRepository::index(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(repo.entries().len(), 2);
+        assert_eq!(repo.get("http://example.com/dup").unwrap().title(), "Zeta");
+        assert_eq!(repo.skipped(), &[dir.join("latin1.csl")][..]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unreadable_and_looping_dirs() {
+        use std::os::unix::fs::{symlink, PermissionsExt};
+        let dir = std::env::temp_dir().join(format!("citeproc-style-dirs-{}", std::process::id()));
+        write_styles(&dir);
+        symlink(&dir, dir.join("dependent").join("loop")).unwrap();
+        let locked = dir.join("locked");
+        fs::create_dir_all(&locked).unwrap();
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o000)).unwrap();
+        // Permissions don't stop root
+        let can_lock = fs::read_dir(&locked).is_err();
+        let repo = StyleRepository::index(&dir);
+        fs::set_permissions(&locked, fs::Permissions::from_mode(0o755)).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let repo = repo.unwrap();
+        assert_eq!(repo.entries().len(), 2);
+        if can_lock {
+            assert_eq!(repo.skipped(), &[dir.join("broken.csl"), locked][..]);
+        } else {
+            assert_eq!(repo.skipped(), &[dir.join("broken.csl")][..]);
+        }
+    }
+
+    #[test]
+    fn processor_with_style_id() {
+        let dir = std::env::temp_dir().join(format!("citeproc-style-id-{}", std::process::id()));
+        write_styles(&dir);
+        let repo = StyleRepository::index(&dir).unwrap();
+        let db = Processor::with_style_id(
+            "http://example.com/history-journal",
+            fixtures::locales(),
+            Arc::new(repo),
+            false,
+            SupportedFormat::Plain,
+        );
+        fs::remove_dir_all(&dir).unwrap();
+        let mut db = db.unwrap();
+        assert_eq!(db.style_info().title_short.unwrap(), "JH");
+
+        let mut refr = Reference::empty("a".into(), CslType::Book);
+        refr.ordinary.insert(Variable::Title, "Title".into());
+        db.set_references(vec![refr]);
+        db.init_clusters(vec![Cluster {
+            id: 1,
+            cites: vec![Cite::basic("a")],
+        }]);
+        db.set_cluster_order(&[ClusterPosition {
+            id: 1,
+            note: Some(1),
+        }])
+        .unwrap();
+        assert_eq!(&*db.get_cluster(1).unwrap(), "Chicago: Title");
+    }
+}
@@ -0,0 +1,169 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright © 2019 Corporation for Digital Scholarship
+
+//! Batches of edits that are checked as a whole before any of them touch the document. See
+//! `Processor::commit`.
+
+use super::{ClusterPosition, ErrorKind, Processor};
+use citeproc_io::output::markup::Markup;
+use citeproc_io::{Cluster, ClusterId, ClusterNumber, Reference};
+use fnv::FnvHashSet;
+
+/// One of the edits a `Transaction` can hold. Each does what the `Processor` method of the same
+/// name does.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "camelCase")]
+pub enum Edit {
+    InsertCluster(Cluster<Markup>),
+    RemoveCluster(ClusterId),
+    SetReferences(Vec<Reference>),
+    SetClusterOrder(Vec<ClusterPosition>),
+    RenumberClusters(Vec<(ClusterId, ClusterNumber)>),
+}
+
+/// A list of edits, applied in order by `Processor::commit`, all together or not at all.
+#[derive(Debug, Clone, Default)]
+pub struct Transaction {
+    pub(crate) edits: Vec<Edit>,
+}
+
+impl Transaction {
+    pub fn new() -> Self {
+        Transaction::default()
+    }
+
+    pub fn insert_cluster(mut self, cluster: Cluster<Markup>) -> Self {
+        self.edits.push(Edit::InsertCluster(cluster));
+        self
+    }
+
+    pub fn remove_cluster(mut self, cluster_id: ClusterId) -> Self {
+        self.edits.push(Edit::RemoveCluster(cluster_id));
+        self
+    }
+
+    pub fn set_references(mut self, refs: Vec<Reference>) -> Self {
+        self.edits.push(Edit::SetReferences(refs));
+        self
+    }
+
+    pub fn set_cluster_order(mut self, pieces: Vec<ClusterPosition>) -> Self {
+        self.edits.push(Edit::SetClusterOrder(pieces));
+        self
+    }
+
+    pub fn renumber_clusters(mut self, mappings: Vec<(ClusterId, ClusterNumber)>) -> Self {
+        self.edits.push(Edit::RenumberClusters(mappings));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.edits.is_empty()
+    }
+}
+
+impl From<Vec<Edit>> for Transaction {
+    fn from(edits: Vec<Edit>) -> Self {
+        Transaction { edits }
+    }
+}
+
+/// Checks that the note numbers in a document order never go backwards, and that no cluster
+/// appears twice.
+pub(crate) fn validate_cluster_order(pieces: &[ClusterPosition]) -> Result<(), ErrorKind> {
+    let mut seen = FnvHashSet::default();
+    let mut last_note = None;
+    for piece in pieces {
+        if !seen.insert(piece.id) {
+            return Err(ErrorKind::DuplicateClusterId(piece.id));
+        }
+        if let Some(nn) = piece.note {
+            if last_note.map_or(false, |last| nn < last) {
+                return Err(ErrorKind::NonMonotonicNoteNumber(nn));
+            }
+            last_note = Some(nn);
+        }
+    }
+    Ok(())
+}
+
+impl Transaction {
+    /// Runs through the edits against the clusters `known` to the processor, without applying
+    /// any of them.
+    pub(crate) fn validate(&self, known: &FnvHashSet<ClusterId>) -> Result<(), ErrorKind> {
+        let mut known = known.clone();
+        let check_known = |known: &FnvHashSet<ClusterId>, id: ClusterId| {
+            if known.contains(&id) {
+                Ok(())
+            } else {
+                Err(ErrorKind::UnknownCluster(id))
+            }
+        };
+        for edit in &self.edits {
+            match edit {
+                Edit::InsertCluster(cluster) => {
+                    known.insert(cluster.id);
+                }
+                Edit::RemoveCluster(id) => {
+                    check_known(&known, *id)?;
+                    known.remove(id);
+                }
+                Edit::SetReferences(refs) => {
+                    let mut seen = FnvHashSet::default();
+                    for refr in refs {
+                        if !seen.insert(&refr.id) {
+                            return Err(ErrorKind::DuplicateReferenceId(refr.id.clone()));
+                        }
+                    }
+                }
+                Edit::SetClusterOrder(pieces) => {
+                    validate_cluster_order(pieces)?;
+                    for piece in pieces {
+                        check_known(&known, piece.id)?;
+                    }
+                }
+                Edit::RenumberClusters(mappings) => {
+                    let mut seen = FnvHashSet::default();
+                    for (id, _) in mappings {
+                        check_known(&known, *id)?;
+                        if !seen.insert(*id) {
+                            return Err(ErrorKind::DuplicateClusterId(*id));
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Processor {
+    /// Checks every edit in `transaction`, then applies them all in order. If any of them is
+    /// invalid, returns the first problem found and leaves the processor exactly as it was. An
+    /// update listener hears about the whole transaction at once.
+    ///
+    /// A cluster is known to the processor once it has been inserted (or passed to
+    /// `init_clusters`), until it is removed. Setting an order or renumbering with any other
+    /// cluster id is an error.
+    pub fn commit(&mut self, transaction: Transaction) -> Result<(), ErrorKind> {
+        transaction.validate(&self.known_clusters)?;
+        let listener = self.listener.take();
+        for edit in transaction.edits {
+            match edit {
+                Edit::InsertCluster(cluster) => self.insert_cluster(cluster),
+                Edit::RemoveCluster(id) => self.remove_cluster(id),
+                Edit::SetReferences(refs) => self.set_references(refs),
+                Edit::SetClusterOrder(pieces) => self
+                    .set_cluster_order(&pieces)
+                    .expect("cluster order was validated"),
+                Edit::RenumberClusters(mappings) => self.renumber_clusters(&mappings),
+            }
+        }
+        self.listener = listener;
+        self.notify();
+        Ok(())
+    }
+}
@@ -48,16 +48,44 @@ pub struct BibliographyMeta<O: OutputFormat = Markup> {
 }
 
 use csl::Atom;
-use fnv::FnvHashMap;
+use fnv::{FnvHashMap, FnvHashSet};
+
+/// A bibliography entry with enough context to build a custom bibliography UI around it.
+#[derive(Clone, Serialize, Debug)]
+#[serde(rename_all = "camelCase", bound(serialize = ""))]
+pub struct BibEntry<O: OutputFormat = Markup> {
+    /// The reference id
+    pub id: Atom,
+    /// The whole entry, as it appears in `get_bibliography`
+    pub value: Arc<O::Output>,
+    /// The entry's position in the sorted bibliography, starting from 1. This is the number
+    /// `citation-number` renders.
+    pub number: u32,
+    /// When the style sets second-field-align, the first field and the rest of the entry,
+    /// rendered separately. Otherwise None.
+    pub first_field: Option<FirstField<O>>,
+    /// False if the reference is only in the bibliography because it was included uncited.
+    pub cited: bool,
+}
+
+#[derive(Clone, Serialize, Debug)]
+#[serde(rename_all = "camelCase", bound(serialize = ""))]
+pub struct FirstField<O: OutputFormat = Markup> {
+    pub first: O::Output,
+    pub rest: O::Output,
+}
 
 #[derive(Clone, Serialize, Default, Debug)]
-#[serde(rename_all = "camelCase")]
+#[serde(rename_all = "camelCase", bound(serialize = ""))]
 pub struct BibliographyUpdate<O: OutputFormat = Markup> {
-    /// Contains Reference Ids mapped to their bibliography outputs
-    pub updated_entries: FnvHashMap<Atom, Arc<O::Output>>,
+    /// The entries whose output changed, in bibliography order
+    pub updated_entries: Vec<BibEntry<O>>,
     /// None if the sort is the same, otherwise contains all entries in order
     /// Entries that cease to be present in the list between updates are considered to have been removed.
     pub entry_ids: Option<Vec<Atom>>,
+    /// References already in the bibliography whose citation number changed, paired with the new
+    /// number. In bibliography order. New entries are only in `updated_entries`.
+    pub renumbered: Vec<(Atom, u32)>,
 }
 
 impl BibliographyUpdate {
@@ -69,27 +97,72 @@ impl BibliographyUpdate {
 #[derive(Default, Debug, Clone, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct UpdateSummary<O: OutputFormat = Markup> {
-    /// A list of clusters that were updated, paired with the formatted output for each
+    /// The clusters whose output changed since the last summary, in document order, paired with
+    /// the formatted output for each
     pub clusters: Vec<(ClusterId, Arc<O::Output>)>,
+    /// Clusters that were delivered before but are no longer in the document, in ascending order
+    pub removed_clusters: Vec<ClusterId>,
     pub bibliography: Option<BibliographyUpdate>,
+    /// Updates to the named bibliographies, for those that changed
+    pub named_bibliographies: Vec<(Atom, BibliographyUpdate)>,
+    /// Updates to the per-section bibliographies, for those that changed
+    pub section_bibliographies: Vec<(u32, BibliographyUpdate)>,
 }
 
+/// The cluster outputs most recently handed out in an `UpdateSummary`.
+pub(crate) type DeliveredClusters = FnvHashMap<ClusterId, Arc<<Markup as OutputFormat>::Output>>;
+
 impl UpdateSummary {
-    pub fn summarize(db: &impl IrDatabase, updates: &[DocUpdate]) -> Self {
-        let ids = updates.iter().map(|&u| match u {
-            DocUpdate::Cluster(x) => x,
-        });
-        let mut set = fnv::FnvHashSet::default();
-        for id in ids {
-            set.insert(id);
+    /// True if nothing changed.
+    pub fn is_empty(&self) -> bool {
+        self.clusters.is_empty()
+            && self.removed_clusters.is_empty()
+            && self.bibliography.is_none()
+            && self.named_bibliographies.is_empty()
+            && self.section_bibliographies.is_empty()
+    }
+
+    /// `updates` only says which clusters were recomputed. A recomputed cluster is only reported
+    /// if its output differs from what is in `delivered`, which is then brought up to date.
+    pub(crate) fn summarize(
+        db: &impl IrDatabase,
+        updates: &[DocUpdate],
+        delivered: &mut DeliveredClusters,
+    ) -> Self {
+        let recomputed: FnvHashSet<ClusterId> = updates
+            .iter()
+            .map(|&u| match u {
+                DocUpdate::Cluster(x) => x,
+            })
+            .collect();
+        let sorted = db.clusters_sorted();
+        let mut clusters = Vec::new();
+        for cluster in sorted.iter() {
+            if !recomputed.contains(&cluster.id) && delivered.contains_key(&cluster.id) {
+                continue;
+            }
+            let built = db.built_cluster(cluster.id);
+            if delivered.get(&cluster.id) != Some(&built) {
+                delivered.insert(cluster.id, built.clone());
+                clusters.push((cluster.id, built));
+            }
         }
-        let mut clusters = Vec::with_capacity(set.len());
-        for id in set {
-            clusters.push((id, db.built_cluster(id)));
+        let in_document: FnvHashSet<ClusterId> = sorted.iter().map(|c| c.id).collect();
+        let mut removed_clusters: Vec<ClusterId> = delivered
+            .keys()
+            .filter(|id| !in_document.contains(id))
+            .cloned()
+            .collect();
+        removed_clusters.sort();
+        for id in &removed_clusters {
+            delivered.remove(id);
         }
         UpdateSummary {
             clusters,
+            removed_clusters,
             bibliography: None,
+            named_bibliographies: Vec::new(),
+            section_bibliographies: Vec::new(),
         }
     }
 }
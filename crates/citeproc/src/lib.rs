@@ -10,17 +10,29 @@ extern crate serde_derive;
 // extern crate log;
 
 pub(crate) mod db;
-pub use self::db::update::{DocUpdate, UpdateSummary};
-pub use self::db::{ClusterPosition, ErrorKind, Processor};
+pub mod repository;
+pub use self::db::diagnostics::{Diagnostic, DiagnosticKind, IncompleteReference};
+pub use self::db::snapshot::{ProcessorSnapshot, RestoreError, SnapshotCluster, SNAPSHOT_VERSION};
+pub use self::db::transaction::{Edit, Transaction};
+pub use self::db::update::{BibEntry, DocUpdate, FirstField, UpdateSummary};
+pub use self::db::{ClusterPosition, ErrorKind, Processor, UpdateListener};
+pub use self::repository::{StyleEntry, StyleRepository};
+pub use citeproc_proc::semantic::{CiteSpans, SemanticSpan, SpanKind};
+pub use citeproc_proc::standalone::StandaloneReference;
 
 pub mod prelude {
+    pub use crate::db::transaction::Transaction;
     pub use crate::db::update::{DocUpdate, UpdateSummary};
     pub use crate::db::{ClusterPosition, Processor, SupportedFormat};
     pub use citeproc_db::{
         CiteDatabase, CiteId, LocaleDatabase, LocaleFetchError, LocaleFetcher, StyleDatabase,
+        PredefinedStyles, StyleFetchError, StyleFetcher,
+    };
+    pub use citeproc_io::output::{markup::HtmlMetadata, markup::Markup, OutputFormat};
+    pub use citeproc_io::{
+        BibliographyFilter, Cite, Cluster, ClusterId, ClusterNumber, FieldMatch, IntraNote,
+        Reference, ReferencePatch,
     };
-    pub use citeproc_io::output::{markup::Markup, OutputFormat};
-    pub use citeproc_io::{Cite, Cluster, ClusterId, ClusterNumber, IntraNote, Reference};
     pub use citeproc_proc::db::{HasFormatter, IrDatabase};
     pub use csl::Atom;
 }
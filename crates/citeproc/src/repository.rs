@@ -0,0 +1,208 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright © 2019 Corporation for Digital Scholarship
+
+//! An index of a local directory of `.csl` files, like a checkout of the [CSL styles
+//! repository](https://github.com/citation-style-language/styles), which has its dependent
+//! styles in a `dependent` subdirectory.
+
+use citeproc_db::{StyleFetchError, StyleFetcher};
+use csl::{Category, Info};
+use fnv::FnvHashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// One style in a `StyleRepository`
+#[derive(Debug, Clone)]
+pub struct StyleEntry {
+    pub path: PathBuf,
+    pub info: Info,
+}
+
+impl StyleEntry {
+    /// The `<id>` of the style, or its path if it doesn't have one.
+    pub fn id(&self) -> &str {
+        match &self.info.id {
+            Some(id) => id,
+            None => self.path.to_str().unwrap_or(""),
+        }
+    }
+
+    /// The `<title>` of the style, or its id if it doesn't have one.
+    pub fn title(&self) -> &str {
+        match &self.info.title {
+            Some(title) => title,
+            None => self.id(),
+        }
+    }
+
+    pub fn is_dependent(&self) -> bool {
+        self.info.independent_parent().is_some()
+    }
+}
+
+/// Styles found on disk, indexed by id, title and category. It is also a `StyleFetcher`, so
+/// `Processor::with_style_id` can load any style in it, and resolve dependent styles' parents.
+#[derive(Debug, Default)]
+pub struct StyleRepository {
+    entries: Vec<StyleEntry>,
+    by_id: FnvHashMap<String, usize>,
+    skipped: Vec<PathBuf>,
+}
+
+impl StyleRepository {
+    /// Reads the `<info>` of every `.csl` file in `dir` and its subdirectories. Symlinked
+    /// directories aren't followed. Files and subdirectories that can't be read, or files that
+    /// aren't valid XML, are skipped; see `skipped`. When two files have the same id, the first
+    /// one found wins.
+    pub fn index(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let mut repo = StyleRepository::default();
+        repo.index_dir(dir.as_ref())?;
+        // Pick the winning duplicates while the entries are still in the order they were found
+        let mut first_found = FnvHashMap::default();
+        for (found, entry) in repo.entries.iter().enumerate() {
+            first_found.entry(entry.id().to_owned()).or_insert(found);
+        }
+        let mut entries: Vec<_> = repo.entries.drain(..).enumerate().collect();
+        entries
+            .sort_by(|(_, a), (_, b)| a.title().cmp(b.title()).then_with(|| a.path.cmp(&b.path)));
+        for (i, (found, entry)) in entries.iter().enumerate() {
+            if first_found[entry.id()] == *found {
+                repo.by_id.insert(entry.id().to_owned(), i);
+            }
+        }
+        repo.entries = entries.into_iter().map(|(_, entry)| entry).collect();
+        Ok(repo)
+    }
+
+    fn index_dir(&mut self, dir: &Path) -> io::Result<()> {
+        // file_type doesn't follow symlinks, so a link back up the tree can't recurse forever
+        let mut paths = fs::read_dir(dir)?
+            .map(|entry| entry.and_then(|e| Ok((e.path(), e.file_type()?.is_dir()))))
+            .collect::<io::Result<Vec<_>>>()?;
+        // read_dir's order is platform-dependent, and decides which duplicate id wins
+        paths.sort();
+        for (path, is_dir) in paths {
+            if is_dir {
+                if self.index_dir(&path).is_err() {
+                    self.skipped.push(path);
+                }
+            } else if path.extension().map_or(false, |ext| ext == "csl") {
+                let info = fs::read_to_string(&path)
+                    .ok()
+                    .and_then(|text| Info::from_style_text(&text).ok());
+                match info {
+                    Some(info) => self.entries.push(StyleEntry { path, info }),
+                    None => self.skipped.push(path),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Every style, sorted by title.
+    pub fn entries(&self) -> &[StyleEntry] {
+        &self.entries
+    }
+
+    /// The `.csl` files that couldn't be read, or whose `<info>` couldn't be parsed, and the
+    /// subdirectories that couldn't be read.
+    pub fn skipped(&self) -> &[PathBuf] {
+        &self.skipped
+    }
+
+    pub fn get(&self, id: &str) -> Option<&StyleEntry> {
+        self.by_id.get(id).map(|&i| &self.entries[i])
+    }
+
+    /// The style itself if it is independent, or otherwise its independent parent, if that is
+    /// in the repository too. This is the one with the rendering rules.
+    pub fn independent(&self, id: &str) -> Option<&StyleEntry> {
+        let entry = self.get(id)?;
+        match entry.info.independent_parent() {
+            Some(parent) => self.get(parent).filter(|p| !p.is_dependent()),
+            None => Some(entry),
+        }
+    }
+
+    pub fn with_category(&self, category: Category) -> impl Iterator<Item = &StyleEntry> {
+        self.entries
+            .iter()
+            .filter(move |e| e.info.categories.contains(&category))
+    }
+
+    /// Styles whose title or short title fuzzily matches `query`, best match first. The letters
+    /// of the query have to appear in order, but not necessarily together, so `"chicago note"`
+    /// finds "Chicago Manual of Style 17th edition (note)".
+    pub fn search(&self, query: &str, limit: usize) -> Vec<&StyleEntry> {
+        let mut scored: Vec<(u32, &StyleEntry)> = self
+            .entries
+            .iter()
+            .filter_map(|entry| {
+                let title = fuzzy_score(query, entry.title());
+                let short = entry
+                    .info
+                    .title_short
+                    .as_ref()
+                    .and_then(|t| fuzzy_score(query, t));
+                Some((title.max(short)?, entry))
+            })
+            .collect();
+        // Stable sort, so equal scores stay in title order
+        scored.sort_by(|a, b| b.0.cmp(&a.0));
+        scored.into_iter().take(limit).map(|(_, e)| e).collect()
+    }
+
+    /// The source of a style, by id.
+    pub fn read_style(&self, id: &str) -> io::Result<Option<String>> {
+        match self.get(id) {
+            Some(entry) => fs::read_to_string(&entry.path).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+impl StyleFetcher for StyleRepository {
+    fn fetch_style(&self, id: &str) -> Result<Option<String>, StyleFetchError> {
+        Ok(self.read_style(id)?)
+    }
+}
+
+/// Scores `candidate` against `query` if it contains the query's letters in order, ignoring case
+/// and whitespace in the query. Letters at the start of words, runs of consecutive letters, and
+/// containing the whole query score higher.
+fn fuzzy_score(query: &str, candidate: &str) -> Option<u32> {
+    let query = query.trim().to_lowercase();
+    let letters: Vec<char> = query.chars().filter(|c| !c.is_whitespace()).collect();
+    let candidate = candidate.to_lowercase();
+    let mut score = 0;
+    let mut next = 0;
+    let mut prev: Option<char> = None;
+    let mut prev_matched = false;
+    for c in candidate.chars() {
+        if next < letters.len() && c == letters[next] {
+            score += 1;
+            if prev_matched {
+                score += 2;
+            }
+            if prev.map_or(true, |p| !p.is_alphanumeric()) {
+                score += 3;
+            }
+            next += 1;
+            prev_matched = true;
+        } else {
+            prev_matched = false;
+        }
+        prev = Some(c);
+    }
+    if next < letters.len() {
+        return None;
+    }
+    if !query.is_empty() && candidate.contains(&query) {
+        score += 10;
+    }
+    Some(score)
+}
@@ -23,10 +23,26 @@ fn convert_sev(csl: CslSeverity) -> Severity {
 pub fn file_diagnostics<'a>(err: &StyleError, filename: &'a str, document: &'a str) {
     let mut code_map = CodeMap::new();
     let file_map = code_map.add_filemap(filename.to_owned().into(), document.to_string());
+    let diags = diagnostics(err, &file_map);
+    emit_all(&code_map, diags);
+}
+
+/// Prints the results of `csl::lint`.
+pub fn lint_diagnostics<'a>(lints: &[InvalidCsl], filename: &'a str, document: &'a str) {
+    let mut code_map = CodeMap::new();
+    code_map.add_filemap(filename.to_owned().into(), document.to_string());
+    let diags = lints
+        .iter()
+        .map(|e| to_diagnostic(e).ok_or_else(|| e.message.clone()))
+        .collect();
+    emit_all(&code_map, diags);
+}
+
+fn emit_all(code_map: &CodeMap, diags: Vec<Result<Diagnostic, String>>) {
     let writer = StandardStream::stderr(ColorChoice::Auto);
-    for diag in diagnostics(err, &file_map) {
+    for diag in diags {
         if let Ok(d) = diag {
-            emit(&mut writer.lock(), &code_map, &d).unwrap();
+            emit(&mut writer.lock(), code_map, &d).unwrap();
             eprintln!();
         } else if let Err(emsg) = diag {
             eprintln!("{}", emsg);
@@ -17,7 +17,7 @@ cfg_if! {
     }
 }
 
-use citeproc::input::Reference;
+use citeproc::prelude::{Atom, Cite, Cluster, ClusterPosition, Markup, Reference};
 use clap::{App, Arg, SubCommand};
 use directories::ProjectDirs;
 use std::fs;
@@ -29,7 +29,8 @@ mod error;
 mod pandoc;
 use pandoc_types::definition::{Inline, MetaValue, Pandoc as PandocDocument};
 
-use citeproc::{LocaleFetchError, LocaleFetcher, Processor};
+use citeproc::prelude::{LocaleFetchError, LocaleFetcher, SupportedFormat};
+use citeproc::{Processor, StyleRepository};
 use csl::{Lang, Locale};
 
 fn main() {
@@ -59,17 +60,40 @@ fn main() {
             "Force Pandoc JSON filter mode. Operates on stdin > stdout.\
              \nNormally, you can just use `pandoc -F citeproc-rs`.",
         ))
+        .subcommand(
+            SubCommand::with_name("lint")
+                .about("Checks the --csl style for likely mistakes, beyond parse errors"),
+        )
+        .subcommand(
+            SubCommand::with_name("styles")
+                .about("Works with a directory of CSL styles, given with --styles-dir")
+                .subcommand(
+                    SubCommand::with_name("search")
+                        .about("Finds styles by title")
+                        .arg(Arg::with_name("query").required(true).index(1))
+                        .arg(
+                            Arg::with_name("limit")
+                                .short("n")
+                                .long("limit")
+                                .value_name("N")
+                                .help("How many results to show. Defaults to 20.")
+                                .takes_value(true),
+                        ),
+                ),
+        )
         .subcommand(
             SubCommand::with_name("disamb-index")
                 .about("Prints the inverted disambiguation index for the reference library"),
         )
-        // .arg(
-        //     Arg::with_name("format")
-        //         .short("f")
-        //         .long("format")
-        //         .value_name("FORMAT")
-        //         .takes_value(true),
-        // )
+        .arg(
+            Arg::with_name("format")
+                .short("f")
+                .long("format")
+                .value_name("FORMAT")
+                .help("Output format. Defaults to ansi in a terminal, plain otherwise.")
+                .possible_values(&["html", "html-linked", "rtf", "plain", "ansi"])
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("library")
                 .short("l")
@@ -101,6 +125,13 @@ fn main() {
                 .help("Directory with locales-xx-XX.xml files in it")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("styles-dir")
+                .long("styles-dir")
+                .value_name("DIR")
+                .help("Directory of .csl files, like a clone of the CSL styles repository")
+                .takes_value(true),
+        )
         .get_matches();
 
     let lib_text = r#"
@@ -177,6 +208,67 @@ fn main() {
         return;
     }
 
+    if matches.subcommand_matches("lint").is_some() {
+        let csl_path = match matches.value_of("csl") {
+            Some(path) => path,
+            None => {
+                eprintln!("lint needs a style, given with --csl");
+                return;
+            }
+        };
+        let text = fs::read_to_string(&csl_path).expect("No CSL file found at that path");
+        match csl::lint(&text) {
+            Ok(lints) if lints.is_empty() => eprintln!("no problems found"),
+            Ok(lints) => self::error::lint_diagnostics(&lints, &csl_path, &text),
+            Err(e) => self::error::file_diagnostics(&e, &csl_path, &text),
+        }
+        return;
+    }
+
+    if let Some(styles_matches) = matches.subcommand_matches("styles") {
+        let styles_dir = matches
+            .value_of("styles-dir")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| {
+                let pd = ProjectDirs::from("net", "cormacrelf", "citeproc-rs")
+                    .expect("No home directory found.");
+                let mut styles_dir = pd.cache_dir().to_owned();
+                styles_dir.push("styles");
+                styles_dir
+            });
+        let repo = match StyleRepository::index(&styles_dir) {
+            Ok(repo) => repo,
+            Err(e) => {
+                eprintln!("could not read styles from {}: {}", styles_dir.display(), e);
+                return;
+            }
+        };
+        if let Some(search) = styles_matches.subcommand_matches("search") {
+            let query = search.value_of("query").expect("query is required");
+            let limit = match search.value_of("limit").map(usize::from_str) {
+                Some(Ok(n)) => n,
+                Some(Err(_)) => {
+                    eprintln!("--limit must be a number");
+                    return;
+                }
+                None => 20,
+            };
+            for entry in repo.search(query, limit) {
+                match &entry.info.citation_format {
+                    Some(format) => println!("{} [{}]", entry.title(), format.as_ref()),
+                    None => println!("{}", entry.title()),
+                }
+                println!("    {}", entry.id());
+                if let Some(parent) = entry.info.independent_parent() {
+                    println!("    dependent on {}", parent);
+                }
+            }
+        } else {
+            eprintln!("styles needs a subcommand, like `styles search QUERY`");
+        }
+        return;
+    }
+
     // if let Some(_) = matches.subcommand_matches("disamb-index") {
     //     let mut db = Processor::new(filesystem_fetcher);
     //     db.set_references(refs);
@@ -191,14 +283,20 @@ fn main() {
     // }
 
     if let Some(csl_path) = matches.value_of("csl") {
-        let key = matches
-            .value_of("key")
-            .map(citeproc::Atom::from)
-            .unwrap_or("quagmire2018".into());
+        let format = matches
+            .value_of("format")
+            .map(|f| SupportedFormat::from_str(f).expect("format was checked by clap"))
+            .unwrap_or_else(|| {
+                if atty::is(atty::Stream::Stdout) {
+                    SupportedFormat::Ansi
+                } else {
+                    SupportedFormat::Plain
+                }
+            });
 
         let text = fs::read_to_string(&csl_path).expect("No CSL file found at that path");
 
-        match Processor::new(&text, filesystem_fetcher, false) {
+        match Processor::new(&text, filesystem_fetcher, false, format) {
             Ok(mut db) => {
                 let refs = if let Some(library_path) = matches.value_of("library") {
                     expect_refs(library_path)
@@ -206,19 +304,44 @@ fn main() {
                     serde_json::from_str(&lib_text).expect("sample lib_text not parseable")
                 };
 
-                db.init_clusters(vec![citeproc::input::Cluster {
-                    id: 0,
-                    note_number: 1,
-                    cites: vec![citeproc::input::Cite::basic(key)],
-                }]);
+                // Preview either the one key asked for, or one cluster per reference.
+                let keys: Vec<Atom> = match matches.value_of("key") {
+                    Some(key) => vec![Atom::from(key)],
+                    None => refs.iter().map(|r| r.id.clone()).collect(),
+                };
                 db.set_references(refs);
+                let clusters: Vec<Cluster<Markup>> = keys
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, key)| Cluster {
+                        id: i as u32,
+                        cites: vec![Cite::basic(key)],
+                    })
+                    .collect();
+                let positions: Vec<_> = clusters
+                    .iter()
+                    .map(|c| ClusterPosition {
+                        id: c.id,
+                        note: Some(c.id + 1),
+                    })
+                    .collect();
+                db.init_clusters(clusters);
+                db.set_cluster_order(&positions)
+                    .expect("note numbers are ascending");
+                db.compute();
 
-                let inlines = db.get_cluster(0).to_vec();
-
-                use pandoc_types::definition::{Block, Meta, Pandoc};
-                let doc = Pandoc(Meta::null(), vec![Block::Para(inlines)]);
-                let out = serde_json::to_string(&doc).unwrap();
-                println!("{}", out);
+                for pos in &positions {
+                    if let Some(built) = db.get_cluster(pos.id) {
+                        println!("{}", built);
+                    }
+                }
+                let bib = db.get_bibliography();
+                if !bib.is_empty() {
+                    println!();
+                    for entry in bib {
+                        println!("{}", entry);
+                    }
+                }
             }
             Err(e) => {
                 self::error::file_diagnostics(&e, &csl_path, &text);
@@ -261,12 +384,16 @@ fn do_pandoc() {
     let csl_path = pandoc_meta_str(&doc, "csl").expect("No csl path provided through metadata");
     let text = fs::read_to_string(&csl_path).expect("No CSL file found at that path");
 
-    match Processor::new(&text, Arc::new(Filesystem::default()), false) {
+    let fetcher = Arc::new(Filesystem::default());
+    match Processor::new(&text, fetcher, false, SupportedFormat::Plain) {
         Ok(mut db) => {
             if let Some(library_path) = pandoc_meta_str(&doc, "bibliography") {
                 db.set_references(expect_refs(library_path));
             }
-            db.init_clusters(pandoc::get_clusters(&mut doc));
+            let (clusters, positions) = pandoc::get_clusters(&mut doc);
+            db.init_clusters(clusters);
+            db.set_cluster_order(&positions)
+                .expect("pandoc numbers clusters in ascending order");
             db.compute();
             pandoc::write_clusters(&mut doc, &db);
             serde_json::to_writer(output, &doc).expect("could not write pandoc json");
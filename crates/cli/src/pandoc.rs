@@ -14,88 +14,102 @@ pub fn suppression_from_pandoc_mode(mode: CitationMode) -> Option<Suppression> {
 }
 
 use pandoc_types::{
-    definition::{Block, Citation, Inline, Pandoc as PandocDocument},
+    definition::{Block, Inline, Pandoc as PandocDocument},
     walk::MutVisitor,
 };
 
-use citeproc::input::{Cite, CiteId, Cluster, ClusterId, Suppression};
-use citeproc::output::Pandoc;
+use citeproc::prelude::{Cite, Cluster, ClusterId, ClusterPosition, Markup};
 use citeproc::Processor;
+use citeproc_io::Suppression;
 use csl::StyleClass;
 
 struct GetClusters {
     next_cluster_id: ClusterId,
-    next_cite_id: CiteId,
-    clusters: Vec<Cluster<Pandoc>>,
+    clusters: Vec<Cluster<Markup>>,
+    positions: Vec<ClusterPosition>,
 }
 
-pub fn get_clusters(pandoc: &mut PandocDocument) -> Vec<Cluster<Pandoc>> {
+/// The clusters in the document, and their order for `Processor::set_cluster_order`.
+pub fn get_clusters(pandoc: &mut PandocDocument) -> (Vec<Cluster<Markup>>, Vec<ClusterPosition>) {
     // pandoc-citeproc starts at 1
     let mut gc = GetClusters {
         next_cluster_id: 1,
-        next_cite_id: 1,
         clusters: vec![],
+        positions: vec![],
     };
     gc.walk_pandoc(pandoc);
-    gc.clusters
+    (gc.clusters, gc.positions)
 }
 
 impl MutVisitor for GetClusters {
     fn walk_inline(&mut self, inline: &mut Inline) {
-        match *inline {
-            Inline::Note(_) => {
-                // just trying to mirror the cite hashes of pandoc-citeproc
-                self.next_cite_id += 1;
-            }
-            Inline::Cite(ref p_cites, ref _literal) => {
-                let mut note_number = 0;
-                let cites = p_cites
-                    .iter()
-                    .map(|p| {
-                        note_number = p.citation_note_num;
-                        let id = self.next_cite_id;
-                        self.next_cite_id += 1;
-                        Cite {
-                            id,
-                            ref_id: p.citation_id.clone().into(),
-                            suppression: suppression_from_pandoc_mode(p.citation_mode.clone()),
-                            prefix: p.citation_prefix.clone(),
-                            suffix: p.citation_suffix.clone(),
-                            // XXX: parse these out of the suffix, and drop the rest in "suffix"
-                            locators: vec![],
-                            locator_extra: None,
-                            locator_date: None,
-                        }
-                    })
-                    .collect();
-                let cluster = Cluster {
-                    // id == note_number for Pandoc, but not necessarily with user-supplied
-                    // ids.
-                    id: self.next_cluster_id,
-                    note_number: self.next_cluster_id as u32,
-                    cites,
-                };
-                self.clusters.push(cluster);
-                self.next_cluster_id += 1;
+        if let Inline::Cite(ref p_cites, ref _literal) = *inline {
+            let cites = p_cites
+                .iter()
+                .map(|p| Cite {
+                    ref_id: p.citation_id.as_str().into(),
+                    prefix: affix(&p.citation_prefix),
+                    suffix: affix(&p.citation_suffix),
+                    suppression: suppression_from_pandoc_mode(p.citation_mode.clone()),
+                    // XXX: parse these out of the suffix, and drop the rest in "suffix"
+                    locators: None,
+                })
+                .collect();
+            let id = self.next_cluster_id;
+            self.clusters.push(Cluster { id, cites });
+            // id == note_number for Pandoc, but not necessarily with user-supplied ids.
+            self.positions.push(ClusterPosition { id, note: Some(id) });
+            self.next_cluster_id += 1;
+        }
+    }
+}
+
+/// Pandoc's cite prefixes and suffixes are inlines; the processor takes plain text.
+fn affix(inlines: &[Inline]) -> Option<String> {
+    let text = stringify(inlines);
+    if text.is_empty() {
+        None
+    } else {
+        Some(text)
+    }
+}
+
+fn stringify(inlines: &[Inline]) -> String {
+    let mut text = String::new();
+    for inline in inlines {
+        match inline {
+            Inline::Str(s) | Inline::Code(_, s) | Inline::Math(_, s) | Inline::RawInline(_, s) => {
+                text.push_str(s)
             }
-            _ => {}
+            Inline::Space | Inline::SoftBreak | Inline::LineBreak => text.push(' '),
+            Inline::Emph(inner)
+            | Inline::Strong(inner)
+            | Inline::Strikeout(inner)
+            | Inline::Superscript(inner)
+            | Inline::Subscript(inner)
+            | Inline::SmallCaps(inner)
+            | Inline::Quoted(_, inner)
+            | Inline::Cite(_, inner)
+            | Inline::Link(_, inner, _)
+            | Inline::Image(_, inner, _)
+            | Inline::Span(_, inner) => text.push_str(&stringify(inner)),
+            Inline::Note(_) => {}
         }
     }
+    text
 }
 
 struct WriteClusters<'a> {
     next_cluster_id: ClusterId,
-    next_cite_id: CiteId,
     db: &'a Processor,
 }
 
 /// Only works if you run it on a PandocDocument that hasn't been modified since you ingested the
-/// clusters into the database. The Inline::Cite-s & Inline::Note-s have to be in the same order.
+/// clusters into the database. The Inline::Cite-s have to be in the same order.
 /// If you're adding a bibliography, do it after a get_clusters/write_clusters pair.
 pub fn write_clusters(pandoc: &mut PandocDocument, db: &Processor) {
     let mut wc = WriteClusters {
         next_cluster_id: 1,
-        next_cite_id: 1,
         db,
     };
     wc.walk_pandoc(pandoc);
@@ -103,36 +117,16 @@ pub fn write_clusters(pandoc: &mut PandocDocument, db: &Processor) {
 
 impl<'a> MutVisitor for WriteClusters<'a> {
     fn walk_inline(&mut self, inline: &mut Inline) {
-        match *inline {
-            Inline::Note(_) => {
-                // just trying to mirror the cite hashes of pandoc-citeproc
-                self.next_cite_id += 1;
-            }
-            Inline::Cite(ref mut p_cites, ref mut literal) => {
-                let cites = p_cites
-                    .iter()
-                    .map(|p| {
-                        let id = self.next_cite_id;
-                        self.next_cite_id += 1;
-                        let rust_cite = self.db.get_cite(id);
-                        Citation {
-                            citation_hash: id as i32,
-                            citation_prefix: rust_cite.prefix.clone(),
-                            citation_suffix: rust_cite.suffix.clone(),
-                            ..p.clone()
-                        }
-                    })
-                    .collect();
-                *p_cites = cites;
-                let built = (*self.db.get_cluster(self.next_cluster_id)).clone();
+        if let Inline::Cite(_, ref mut literal) = *inline {
+            if let Some(built) = self.db.get_cluster(self.next_cluster_id) {
+                let built = vec![Inline::Str((*built).clone())];
                 if self.db.get_style().class == StyleClass::Note {
                     *literal = vec![Inline::Note(vec![Block::Para(built)])];
                 } else {
                     *literal = built;
                 }
-                self.next_cluster_id += 1;
             }
-            _ => {}
+            self.next_cluster_id += 1;
         }
     }
 }
@@ -138,11 +138,14 @@ pub(crate) fn attribute_option<'a, 'd: 'a, T: GetAttribute>(
     match node.attribute(attr.clone()) {
         Some(a) => match T::get_attr(a, &info.features) {
             Ok(val) => Ok(Some(val)),
-            Err(e) => Err(InvalidCsl::attr_val(
-                node,
-                &format!("{:?}", attr.into()),
-                &e.value,
-            )),
+            Err(e) => info.recover(
+                Err(InvalidCsl::attr_val(
+                    node,
+                    &format!("{:?}", attr.into()),
+                    &e.value,
+                )),
+                || None,
+            ),
         },
         None => Ok(None),
     }
@@ -156,7 +159,7 @@ pub(crate) fn attribute_optional<T: Default + GetAttribute>(
     match node.attribute(attr) {
         Some(a) => match T::get_attr(a, &info.features) {
             Ok(val) => Ok(val),
-            Err(e) => Err(InvalidCsl::attr_val(node, attr, &e.value)),
+            Err(e) => info.recover(Err(InvalidCsl::attr_val(node, attr, &e.value)), T::default),
         },
         None => Ok(T::default()),
     }
@@ -177,13 +180,17 @@ pub(crate) fn attribute_array_var<T: GetAttribute>(
                 .collect();
             match split {
                 Ok(val) => Ok(val),
-                Err(e) => Err(InvalidCsl::wrong_var_type(
-                    node,
-                    attr,
-                    &e.value,
-                    need,
-                    AnyVariable::get_attr(&e.value, &info.features).ok(),
-                )),
+                Err(e) => info.recover(
+                    Err(InvalidCsl::wrong_var_type(
+                        node,
+                        attr,
+                        &e.value,
+                        need,
+                        AnyVariable::get_attr(&e.value, &info.features).ok(),
+                    )),
+                    // Keep the values that are valid
+                    || valid_values(array, info),
+                ),
             }
         }
         None => Ok(vec![]),
@@ -204,9 +211,18 @@ pub(crate) fn attribute_array<T: GetAttribute>(
                 .collect();
             match split {
                 Ok(val) => Ok(val),
-                Err(e) => Err(InvalidCsl::attr_val(node, attr, &e.value)),
+                Err(e) => info.recover(Err(InvalidCsl::attr_val(node, attr, &e.value)), || {
+                    valid_values(array, info)
+                }),
             }
         }
         None => Ok(vec![]),
     }
 }
+
+fn valid_values<T: GetAttribute>(array: &str, info: &ParseInfo) -> Vec<T> {
+    array
+        .split(' ')
+        .filter_map(|a| T::get_attr(a, &info.features).ok())
+        .collect()
+}
@@ -0,0 +1,558 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright © 2019 Corporation for Digital Scholarship
+
+//! Working out which variables, terms and macros a style uses, without rendering anything.
+
+use crate::style::*;
+use crate::terms::*;
+use crate::variables::*;
+use crate::{Atom, IsIndependent};
+use fnv::FnvHashSet;
+
+/// What a style refers to, across its citation, bibliography, sort keys and every macro they
+/// call. See `Style::usage`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct StyleUsage {
+    /// Variables that are rendered, tested in a condition or sorted by.
+    pub variables: FnvHashSet<AnyVariable>,
+    /// Terms from `<text term="...">`, plus those implied by `<label variable="...">`, the
+    /// labels on `<names>`, `and="text"`, et-al abbreviation, and the month and season names in
+    /// textual dates. The label for `locator` depends on the cite, so it isn't included.
+    pub terms: FnvHashSet<TextTermSelector>,
+    /// Whether a number or a day is rendered as an ordinal, which uses the locale's ordinal
+    /// terms. Those can't be selected by a `TextTermSelector`, so they aren't in `terms`.
+    pub ordinals: bool,
+    /// Macros that are defined but never called, sorted by name.
+    pub unused_macros: Vec<Atom>,
+}
+
+impl Style {
+    /// Walks the whole style to find the variables and terms it uses, and which macros are dead
+    /// code.
+    pub fn usage(&self) -> StyleUsage {
+        let mut walker = Walker::new(self, None, false);
+        walker.walk_style();
+        let mut unused_macros: Vec<Atom> = self
+            .macros
+            .keys()
+            .filter(|name| !walker.called.contains(*name))
+            .cloned()
+            .collect();
+        unused_macros.sort();
+        StyleUsage {
+            unused_macros,
+            ..walker.usage
+        }
+    }
+
+    /// The variables this style could render for an item of type `csl_type`. Branches of
+    /// `<choose>` that only depend on the type are decided up front, so a variable only rendered
+    /// for other types is left out. Variables that are only tested or sorted by are left out
+    /// too.
+    pub fn variables_for_type(&self, csl_type: CslType) -> FnvHashSet<AnyVariable> {
+        let mut walker = Walker::new(self, Some(csl_type), true);
+        walker.walk_style();
+        walker.usage.variables
+    }
+
+    /// The variables this style would render for an item of type `csl_type`, that the item
+    /// doesn't have. `has_variable` says which ones it does have.
+    ///
+    /// Like `variables_for_type`, but `variable` conditions are decided too, so a variable that
+    /// is only rendered when present isn't reported. Names are only reported when neither they
+    /// nor anything in their `<substitute>` would be rendered. Variables that come from the
+    /// cite or the document, like `locator` or `citation-number`, are never reported.
+    pub fn missing_variables(
+        &self,
+        csl_type: CslType,
+        has_variable: &dyn Fn(AnyVariable) -> bool,
+    ) -> FnvHashSet<AnyVariable> {
+        let mut walker = Walker::new(self, Some(csl_type), true);
+        walker.has_variable = Some(has_variable);
+        walker.walk_style();
+        walker.missing
+    }
+}
+
+struct Walker<'a> {
+    style: &'a Style,
+    /// When set, `type` conditions are folded to constants.
+    csl_type: Option<CslType>,
+    rendered_only: bool,
+    /// When set, `variable` conditions are folded too, and absent variables are collected in
+    /// `missing`.
+    has_variable: Option<&'a dyn Fn(AnyVariable) -> bool>,
+    usage: StyleUsage,
+    missing: FnvHashSet<AnyVariable>,
+    /// The `<name>` options inherited by the layout being walked.
+    name: Name,
+    called: FnvHashSet<Atom>,
+    /// Guards against macros that call themselves.
+    stack: Vec<Atom>,
+}
+
+impl<'a> Walker<'a> {
+    fn new(style: &'a Style, csl_type: Option<CslType>, rendered_only: bool) -> Self {
+        Walker {
+            style,
+            csl_type,
+            rendered_only,
+            has_variable: None,
+            usage: StyleUsage::default(),
+            missing: FnvHashSet::default(),
+            name: Name::default(),
+            called: FnvHashSet::default(),
+            stack: Vec::new(),
+        }
+    }
+
+    fn walk_style(&mut self) {
+        let style = self.style;
+        self.name = style.name_citation();
+        self.walk_elements(&style.citation.layout.elements);
+        if let Some(bib) = &style.bibliography {
+            self.name = style.name_bibliography();
+            for key in bib.sort.iter().flat_map(|sort| sort.keys.iter()) {
+                match &key.sort_source {
+                    SortSource::Variable(var) => {
+                        if !self.rendered_only {
+                            self.usage.variables.insert(*var);
+                        }
+                    }
+                    SortSource::Macro(name) => self.walk_macro(name),
+                }
+            }
+            self.walk_elements(&bib.layout.elements);
+        }
+    }
+
+    fn walk_macro(&mut self, name: &Atom) {
+        self.called.insert(name.clone());
+        if self.stack.contains(name) {
+            return;
+        }
+        let style = self.style;
+        if let Some(elements) = style.macros.get(name) {
+            self.stack.push(name.clone());
+            self.walk_elements(elements);
+            self.stack.pop();
+        }
+    }
+
+    fn walk_elements(&mut self, elements: &[Element]) {
+        for el in elements {
+            self.walk_element(el);
+        }
+    }
+
+    fn walk_element(&mut self, element: &Element) {
+        match element {
+            Element::Text(text) => match &text.source {
+                TextSource::Macro(name) => self.walk_macro(name),
+                TextSource::Variable(var, _) => self.render(var.into()),
+                TextSource::Term(selector, _) => {
+                    self.usage.terms.insert(*selector);
+                }
+                TextSource::Value(_) => {}
+            },
+            Element::Label(label) => {
+                if let Some(sel) =
+                    GenderedTermSelector::from_number_variable(None, label.variable, label.form)
+                {
+                    self.usage.terms.insert(TextTermSelector::Gendered(sel));
+                }
+            }
+            Element::Number(number) => {
+                if let NumericForm::Ordinal | NumericForm::LongOrdinal = number.form {
+                    self.usage.ordinals = true;
+                }
+                self.render(AnyVariable::Number(number.variable))
+            }
+            Element::Group(group) => self.walk_elements(&group.elements),
+            Element::Choose(choose) => self.walk_choose(choose),
+            Element::Names(names) => self.walk_names(names),
+            Element::Date(date) => {
+                self.walk_date(date);
+                self.render(AnyVariable::Date(date.variable()))
+            }
+        }
+    }
+
+    fn walk_date(&mut self, date: &BodyDate) {
+        // A textual localized date names the month unless its date-parts say otherwise.
+        let (parts, mut month) = match date {
+            BodyDate::Indep(date) => (&date.date_parts, None),
+            BodyDate::Local(date) => (
+                &date.date_parts,
+                match (date.form, date.parts_selector) {
+                    (DateForm::Text, DateParts::YearMonthDay)
+                    | (DateForm::Text, DateParts::YearMonth) => Some(MonthForm::Long),
+                    _ => None,
+                },
+            ),
+        };
+        for part in parts {
+            match part.form {
+                DatePartForm::Month(form, _) => month = Some(form),
+                DatePartForm::Day(DayForm::Ordinal) => self.usage.ordinals = true,
+                _ => {}
+            }
+        }
+        let form = match month {
+            Some(MonthForm::Long) => TermForm::Long,
+            Some(MonthForm::Short) => TermForm::Short,
+            _ => return,
+        };
+        let months = (1..=12).filter_map(MonthTerm::from_u32);
+        for month in months {
+            let sel = GenderedTermSelector::Month(month, form);
+            self.usage.terms.insert(TextTermSelector::Gendered(sel));
+        }
+        // Dates with a season instead of a month print the season in its place
+        for &season in &[
+            SeasonTerm::Season01,
+            SeasonTerm::Season02,
+            SeasonTerm::Season03,
+            SeasonTerm::Season04,
+        ] {
+            let sel = GenderedTermSelector::Season(season, form);
+            self.usage.terms.insert(TextTermSelector::Gendered(sel));
+        }
+    }
+
+    fn render(&mut self, var: AnyVariable) {
+        self.usage.variables.insert(var);
+        if let Some(has_variable) = self.has_variable {
+            if !var.is_independent() && !has_variable(var) {
+                self.missing.insert(var);
+            }
+        }
+    }
+
+    fn walk_names(&mut self, names: &Names) {
+        let name = match &names.name {
+            Some(name) => self.name.merge(name),
+            None => self.name.clone(),
+        };
+        let misc =
+            |term| TextTermSelector::Simple(SimpleTermSelector::Misc(term, TermFormExtended::Long));
+        if name.and == Some(NameAnd::Text) {
+            self.usage.terms.insert(misc(MiscTerm::And));
+        }
+        if name.et_al_min.is_some() || name.et_al_subsequent_min.is_some() {
+            let term = match &names.et_al {
+                Some(et_al) if et_al.term == "and others" => MiscTerm::AndOthers,
+                _ => MiscTerm::EtAl,
+            };
+            self.usage.terms.insert(misc(term));
+        }
+        let form = names.label.as_ref().map(|l| l.concrete().form);
+        for &var in &names.variables {
+            self.usage.variables.insert(AnyVariable::Name(var));
+            if let Some(sel) = form.and_then(|form| RoleTermSelector::from_name_variable(var, form))
+            {
+                self.usage.terms.insert(TextTermSelector::Role(sel));
+            }
+        }
+        let elements = names.substitute.as_ref().map_or(&[][..], |s| &s.0[..]);
+        let has_variable = match self.has_variable {
+            Some(has_variable) => has_variable,
+            None => {
+                self.walk_elements(elements);
+                return;
+            }
+        };
+        if names
+            .variables
+            .iter()
+            .any(|&var| has_variable(AnyVariable::Name(var)))
+        {
+            // The substitute won't be used
+            return;
+        }
+        // Only the substitutes are rendered, so only report the names if none of them can be.
+        let outer_variables = std::mem::take(&mut self.usage.variables);
+        let outer_missing = std::mem::take(&mut self.missing);
+        self.walk_elements(elements);
+        let substituted = std::mem::replace(&mut self.usage.variables, outer_variables);
+        self.missing = outer_missing;
+        self.usage.variables.extend(substituted.iter().cloned());
+        if !substituted.iter().any(|&var| has_variable(var)) {
+            self.missing
+                .extend(names.variables.iter().map(|&var| AnyVariable::Name(var)));
+        }
+    }
+
+    fn walk_choose(&mut self, choose: &Choose) {
+        let Choose(head, rest, Else(else_elements)) = choose;
+        for IfThen(conditions, elements) in std::iter::once(head).chain(rest.iter()) {
+            let folded = self.fold(conditions);
+            if folded == Some(false) {
+                continue;
+            }
+            self.walk_conditions(conditions);
+            self.walk_elements(elements);
+            if folded == Some(true) {
+                // No later branch can be taken
+                return;
+            }
+        }
+        self.walk_elements(else_elements);
+    }
+
+    fn walk_conditions(&mut self, conditions: &Conditions) {
+        if self.rendered_only {
+            return;
+        }
+        let Conditions(_, cond_sets) = conditions;
+        for cond in cond_sets.iter().flat_map(|set| set.conds.iter()) {
+            let var = match *cond {
+                Cond::Variable(var) | Cond::IsNumeric(var) => var,
+                Cond::IsUncertainDate(var)
+                | Cond::HasYearOnly(var)
+                | Cond::HasMonthOrSeason(var)
+                | Cond::HasDay(var) => AnyVariable::Date(var),
+                Cond::IsPlural(var) => AnyVariable::Name(var),
+                _ => continue,
+            };
+            self.usage.variables.insert(var);
+        }
+    }
+
+    /// `Some(_)` when the outcome of `conditions` is known without a cite.
+    fn fold(&self, conditions: &Conditions) -> Option<bool> {
+        let Conditions(match_type, cond_sets) = conditions;
+        combine(
+            *match_type,
+            cond_sets.iter().map(|set| {
+                combine(
+                    set.match_type,
+                    set.conds.iter().map(|cond| self.fold_cond(cond)),
+                )
+            }),
+        )
+    }
+
+    fn fold_cond(&self, cond: &Cond) -> Option<bool> {
+        match *cond {
+            Cond::Type(t) => self.csl_type.map(|csl_type| t == csl_type),
+            Cond::Variable(var) if !var.is_independent() => {
+                self.has_variable.map(|has_variable| has_variable(var))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Combines the known (`Some`) and unknown (`None`) results of several tests under `match_type`.
+fn combine(match_type: Match, results: impl Iterator<Item = Option<bool>>) -> Option<bool> {
+    let mut any_true = false;
+    let mut any_false = false;
+    let mut any_unknown = false;
+    for result in results {
+        match result {
+            Some(true) => any_true = true,
+            Some(false) => any_false = true,
+            None => any_unknown = true,
+        }
+    }
+    let decided = |known: bool| if any_unknown { None } else { Some(known) };
+    match match_type {
+        Match::Any if any_true => Some(true),
+        Match::Any => decided(false),
+        Match::All if any_false => Some(false),
+        Match::All => decided(true),
+        Match::None if any_true => Some(false),
+        Match::None => decided(true),
+        Match::Nand if any_false => Some(true),
+        Match::Nand => decided(false),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::str::FromStr;
+
+    const STYLE: &str = r#"
+        <style class="in-text" version="1.0.1">
+            <macro name="author">
+                <names variable="author">
+                    <label form="short" />
+                    <substitute>
+                        <names variable="editor" />
+                        <text variable="title" />
+                    </substitute>
+                </names>
+            </macro>
+            <macro name="unused">
+                <text variable="note" />
+            </macro>
+            <macro name="loops">
+                <text macro="loops" />
+            </macro>
+            <citation>
+                <layout>
+                    <text macro="author" />
+                    <text macro="loops" />
+                    <choose>
+                        <if type="book">
+                            <text variable="publisher" />
+                        </if>
+                        <else-if type="article-journal" variable="volume" match="all">
+                            <text variable="container-title" />
+                            <number variable="volume" />
+                        </else-if>
+                        <else-if type="chapter">
+                            <text term="in" />
+                            <text variable="container-title" />
+                        </else-if>
+                        <else>
+                            <date variable="issued" form="numeric" />
+                        </else>
+                    </choose>
+                    <label variable="page" />
+                </layout>
+            </citation>
+        </style>
+    "#;
+
+    fn style() -> Style {
+        Style::from_str(STYLE).unwrap()
+    }
+
+    #[test]
+    fn usage() {
+        let usage = style().usage();
+        for var in &[
+            AnyVariable::Name(NameVariable::Author),
+            AnyVariable::Name(NameVariable::Editor),
+            AnyVariable::Ordinary(Variable::Title),
+            AnyVariable::Ordinary(Variable::Publisher),
+            AnyVariable::Ordinary(Variable::ContainerTitle),
+            AnyVariable::Number(NumberVariable::Volume),
+            AnyVariable::Date(DateVariable::Issued),
+        ] {
+            assert!(usage.variables.contains(var), "{:?}", var);
+        }
+        assert!(!usage
+            .variables
+            .contains(&AnyVariable::Ordinary(Variable::Note)));
+        assert!(usage
+            .terms
+            .contains(&TextTermSelector::Simple(SimpleTermSelector::Misc(
+                MiscTerm::In,
+                TermFormExtended::Long,
+            ))));
+        assert!(usage
+            .terms
+            .contains(&TextTermSelector::Gendered(GenderedTermSelector::Number(
+                NumberVariable::Page,
+                TermForm::Long,
+            ))));
+        assert!(usage
+            .terms
+            .contains(&TextTermSelector::Role(RoleTermSelector(
+                RoleTerm::Author,
+                TermFormExtended::Short,
+            ))));
+        assert_eq!(usage.unused_macros, vec![Atom::from("unused")]);
+    }
+
+    #[test]
+    fn implied_terms() {
+        let style = Style::from_str(
+            r#"
+            <style class="in-text" version="1.0.1" et-al-min="3" et-al-use-first="1">
+                <citation>
+                    <layout>
+                        <names variable="author">
+                            <name and="text" />
+                        </names>
+                        <date variable="issued" form="text" />
+                        <number variable="edition" form="ordinal" />
+                    </layout>
+                </citation>
+            </style>
+        "#,
+        )
+        .unwrap();
+        let usage = style.usage();
+        let misc =
+            |term| TextTermSelector::Simple(SimpleTermSelector::Misc(term, TermFormExtended::Long));
+        assert!(usage.terms.contains(&misc(MiscTerm::And)));
+        assert!(usage.terms.contains(&misc(MiscTerm::EtAl)));
+        assert!(usage
+            .terms
+            .contains(&TextTermSelector::Gendered(GenderedTermSelector::Month(
+                MonthTerm::Month12,
+                TermForm::Long,
+            ))));
+        assert!(usage
+            .terms
+            .contains(&TextTermSelector::Gendered(GenderedTermSelector::Season(
+                SeasonTerm::Season03,
+                TermForm::Long,
+            ))));
+        assert!(usage.ordinals);
+
+        // Numeric dates and plain numbers don't need any
+        let usage = self::style().usage();
+        assert!(!usage.terms.iter().any(|term| match term {
+            TextTermSelector::Gendered(GenderedTermSelector::Month(..)) => true,
+            _ => false,
+        }));
+        assert!(!usage.ordinals);
+    }
+
+    #[test]
+    fn variables_for_type() {
+        let style = style();
+        let book = style.variables_for_type(CslType::Book);
+        assert!(book.contains(&AnyVariable::Ordinary(Variable::Publisher)));
+        assert!(book.contains(&AnyVariable::Name(NameVariable::Author)));
+        assert!(!book.contains(&AnyVariable::Ordinary(Variable::ContainerTitle)));
+        assert!(!book.contains(&AnyVariable::Date(DateVariable::Issued)));
+
+        // Also depends on `volume`, so the else branch is still possible
+        let article = style.variables_for_type(CslType::ArticleJournal);
+        assert!(article.contains(&AnyVariable::Number(NumberVariable::Volume)));
+        assert!(article.contains(&AnyVariable::Date(DateVariable::Issued)));
+        assert!(!article.contains(&AnyVariable::Ordinary(Variable::Publisher)));
+
+        let chapter = style.variables_for_type(CslType::Chapter);
+        assert!(chapter.contains(&AnyVariable::Ordinary(Variable::ContainerTitle)));
+        assert!(!chapter.contains(&AnyVariable::Number(NumberVariable::Volume)));
+        assert!(!chapter.contains(&AnyVariable::Date(DateVariable::Issued)));
+    }
+
+    #[test]
+    fn missing_variables() {
+        let style = style();
+        let has = |present: &'static [AnyVariable]| move |var: AnyVariable| present.contains(&var);
+
+        let missing = style.missing_variables(CslType::Book, &has(&[]));
+        assert!(missing.contains(&AnyVariable::Ordinary(Variable::Publisher)));
+        assert!(missing.contains(&AnyVariable::Name(NameVariable::Author)));
+        assert!(!missing.contains(&AnyVariable::Name(NameVariable::Editor)));
+
+        // The title can stand in for the author
+        let missing = style.missing_variables(
+            CslType::Book,
+            &has(&[AnyVariable::Ordinary(Variable::Title)]),
+        );
+        assert!(!missing.contains(&AnyVariable::Name(NameVariable::Author)));
+
+        // Without a volume, the article falls through to the else branch
+        let missing = style.missing_variables(
+            CslType::ArticleJournal,
+            &has(&[AnyVariable::Name(NameVariable::Author)]),
+        );
+        assert!(missing.contains(&AnyVariable::Date(DateVariable::Issued)));
+        assert!(!missing.contains(&AnyVariable::Number(NumberVariable::Volume)));
+        assert!(!missing.contains(&AnyVariable::Ordinary(Variable::ContainerTitle)));
+    }
+}
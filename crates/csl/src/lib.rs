@@ -15,18 +15,25 @@ extern crate strum_macros;
 #[macro_use]
 extern crate log;
 
+use std::cell::RefCell;
+use std::rc::Rc;
 use std::sync::Arc;
 
 pub(crate) mod attr;
 pub use self::attr::GetAttribute;
 pub mod error;
+pub mod introspect;
+pub mod lint;
 pub mod locale;
 pub mod style;
 pub mod terms;
 pub mod variables;
 pub mod version;
+mod write;
 
 pub use self::error::*;
+pub use self::introspect::*;
+pub use self::lint::*;
 pub use self::locale::*;
 pub use self::style::*;
 pub use self::terms::*;
@@ -47,6 +54,55 @@ pub trait IsIndependent {
 #[derive(Default)]
 pub(crate) struct ParseInfo {
     features: Features,
+    /// Set when parsing with `Style::parse_with_recovery`, to collect the errors that were
+    /// skipped over.
+    recovered: Option<Rc<RefCell<Vec<InvalidCsl>>>>,
+}
+
+impl ParseInfo {
+    /// When recovering, records the error in `res` and gives `fallback()` instead.
+    fn recover<T, E: Into<CslError>>(
+        &self,
+        res: Result<T, E>,
+        fallback: impl FnOnce() -> T,
+    ) -> Result<T, E> {
+        match (res, &self.recovered) {
+            (Err(e), Some(recovered)) => {
+                recovered.borrow_mut().extend(e.into().0);
+                Ok(fallback())
+            }
+            (res, _) => res,
+        }
+    }
+
+    /// Parses each of `nodes`. When recovering, the ones that fail are left out and their
+    /// errors recorded; otherwise any one failing fails them all.
+    fn parse_each<'a, 'd: 'a, T>(
+        &self,
+        nodes: impl Iterator<Item = Node<'a, 'd>>,
+        parse: impl Fn(&Node<'a, 'd>) -> FromNodeResult<T>,
+    ) -> FromNodeResult<Vec<T>> {
+        let results = nodes.map(|node| parse(&node));
+        match &self.recovered {
+            Some(recovered) => Ok(results
+                .filter_map(|res| match res {
+                    Ok(val) => Some(val),
+                    Err(e) => {
+                        recovered.borrow_mut().extend(e.0);
+                        None
+                    }
+                })
+                .collect()),
+            None => Ok(results.partition_results()?),
+        }
+    }
+
+    /// Parses each child element of `node` as an `Element`, like `parse_each`.
+    fn elements(&self, node: &Node) -> FromNodeResult<Vec<Element>> {
+        self.parse_each(node.children().filter(|n| n.is_element()), |el| {
+            Element::from_node(el, self)
+        })
+    }
 }
 
 pub(crate) type FromNodeResult<T> = Result<T, CslError>;
@@ -192,11 +248,9 @@ impl FromNode for Citation {
 impl FromNode for Sort {
     fn from_node(node: &Node, info: &ParseInfo) -> FromNodeResult<Self> {
         Ok(Sort {
-            keys: node
-                .children()
-                .filter(|n| n.has_tag_name("key"))
-                .map(|node| SortKey::from_node(&node, info))
-                .partition_results()?,
+            keys: info.parse_each(node.children().filter(|n| n.has_tag_name("key")), |el| {
+                SortKey::from_node(el, info)
+            })?,
         })
     }
 }
@@ -287,11 +341,7 @@ impl FromNode for Bibliography {
 
 impl FromNode for Layout {
     fn from_node(node: &Node, info: &ParseInfo) -> FromNodeResult<Self> {
-        let elements = node
-            .children()
-            .filter(|n| n.is_element())
-            .map(|el| Element::from_node(&el, info))
-            .partition_results()?;
+        let elements = info.elements(node)?;
         Ok(Layout {
             formatting: Option::from_node(node, info)?,
             affixes: Option::from_node(node, info)?,
@@ -459,11 +509,7 @@ impl FromNode for NumberElement {
 
 impl FromNode for Group {
     fn from_node(node: &Node, info: &ParseInfo) -> FromNodeResult<Self> {
-        let elements = node
-            .children()
-            .filter(|n| n.is_element())
-            .map(|el| Element::from_node(&el, info))
-            .partition_results()?;
+        let elements = info.elements(node)?;
         Ok(Group {
             elements,
             formatting: Option::from_node(node, info)?,
@@ -478,11 +524,7 @@ impl FromNode for Group {
 
 impl FromNode for Else {
     fn from_node(node: &Node, info: &ParseInfo) -> FromNodeResult<Self> {
-        let elements = node
-            .children()
-            .filter(|n| n.is_element())
-            .map(|el| Element::from_node(&el, info))
-            .partition_results()?;
+        let elements = info.elements(node)?;
         Ok(Else(elements))
     }
 }
@@ -650,11 +692,11 @@ impl FromNode for IfThen {
                 .into())
             }
         })?;
-        let elements = node
-            .children()
-            .filter(|n| n.is_element() && !n.has_tag_name("conditions"))
-            .map(|el| Element::from_node(&el, info))
-            .partition_results()?;
+        let elements = info.parse_each(
+            node.children()
+                .filter(|n| n.is_element() && !n.has_tag_name("conditions")),
+            |el| Element::from_node(el, info),
+        )?;
         Ok(IfThen(conditions, elements))
     }
 }
@@ -890,12 +932,7 @@ fn get_toplevel<'a, 'd: 'a>(
 
 impl FromNode for MacroMap {
     fn from_node(node: &Node, info: &ParseInfo) -> FromNodeResult<Self> {
-        // TODO: remove collect()
-        let elements: Result<Vec<_>, _> = node
-            .children()
-            .filter(|n| n.is_element())
-            .map(|el| Element::from_node(&el, info))
-            .collect();
+        let elements = info.elements(node);
         let name = match node.attribute("name") {
             Some(n) => n,
             None => {
@@ -1121,12 +1158,7 @@ impl FromNode for NameLabelInput {
 
 impl FromNode for Substitute {
     fn from_node(node: &Node, info: &ParseInfo) -> FromNodeResult<Self> {
-        let els = node
-            .children()
-            .filter(|n| n.is_element())
-            .map(|el| Element::from_node(&el, info))
-            .partition_results()?;
-        Ok(Substitute(els))
+        Ok(Substitute(info.elements(node)?))
     }
 }
 
@@ -1236,18 +1268,97 @@ impl FromNode for Features {
 
 impl FromNode for Info {
     fn from_node(node: &Node, info: &ParseInfo) -> FromNodeResult<Self> {
+        // Anything in here this version doesn't know of is left out, rather than making every
+        // style that uses it unusable. It is only metadata.
+        let citation_format = node
+            .children()
+            .filter(|el| el.has_tag_name("category"))
+            .find_map(|el| el.attribute("citation-format"))
+            .and_then(|format| CitationFormat::from_str(format).ok());
         let categories = node
             .children()
             .filter(|el| el.has_tag_name("category"))
-            .map(|el| Category::from_node(&el, info))
+            .filter_map(|el| el.attribute("field"))
+            .filter_map(|field| Category::from_str(field).ok())
+            .collect();
+        let links = node
+            .children()
+            .filter(|el| el.has_tag_name("link"))
+            .filter(|el| {
+                el.attribute("rel")
+                    .map_or(true, |rel| LinkRel::from_str(rel).is_ok())
+            })
+            .map(|el| InfoLink::from_node(&el, info))
             .partition_results()?;
-        Ok(Info { categories })
+        let persons = |tag: &str| {
+            node.children()
+                .filter(|el| el.has_tag_name(tag))
+                .filter_map(|el| info_person(&el))
+                .collect()
+        };
+        let rights = node.children().find(|el| el.has_tag_name("rights"));
+        Ok(Info {
+            id: child_text(node, "id"),
+            title: child_text(node, "title"),
+            title_short: child_text(node, "title-short"),
+            links,
+            authors: persons("author"),
+            contributors: persons("contributor"),
+            citation_format,
+            categories,
+            issn: node
+                .children()
+                .filter(|el| el.has_tag_name("issn"))
+                .filter_map(|el| el.text())
+                .map(|t| t.trim().to_owned())
+                .collect(),
+            eissn: child_text(node, "eissn"),
+            issnl: child_text(node, "issnl"),
+            summary: child_text(node, "summary"),
+            published: child_text(node, "published"),
+            updated: child_text(node, "updated"),
+            rights: rights.and_then(|el| el.text()).map(|t| t.trim().to_owned()),
+            license: rights
+                .and_then(|el| el.attribute("license"))
+                .map(String::from),
+        })
     }
 }
 
-impl FromNode for Category {
+/// `<author>` or `<contributor>`, if it has a `<name>`
+fn info_person(node: &Node) -> Option<InfoPerson> {
+    Some(InfoPerson {
+        name: child_text(node, "name")?,
+        email: child_text(node, "email"),
+        uri: child_text(node, "uri"),
+    })
+}
+
+impl FromNode for InfoLink {
     fn from_node(node: &Node, info: &ParseInfo) -> FromNodeResult<Self> {
-        Ok(attribute_required(node, "name", info)?)
+        let href = node
+            .attribute("href")
+            .ok_or_else(|| InvalidCsl::missing(node, "href"))?;
+        Ok(InfoLink {
+            href: href.to_owned(),
+            rel: attribute_required(node, "rel", info)?,
+        })
+    }
+}
+
+/// The trimmed text of the first child element named `tag`
+fn child_text(node: &Node, tag: &str) -> Option<String> {
+    node.children()
+        .find(|el| el.has_tag_name(tag))
+        .and_then(|el| el.text())
+        .map(|t| t.trim().to_owned())
+}
+
+/// The `<info>` block of a `<style>`, which is optional.
+fn style_info(node: &Node, info: &ParseInfo) -> FromNodeResult<Info> {
+    match node.children().find(|n| n.has_tag_name("info")) {
+        Some(info_node) => Info::from_node(&info_node, info),
+        None => Ok(Info::default()),
     }
 }
 
@@ -1291,38 +1402,39 @@ impl FromNode for Style {
         // Create our own info struct, ignoring the one passed in.
         let info = ParseInfo {
             features: features.clone(),
+            recovered: default_info.recovered.clone(),
         };
 
-        let locales_res = node
-            .children()
-            .filter(|n| n.is_element() && n.has_tag_name("locale"))
-            .map(|el| Locale::from_node(&el, &info))
-            .partition_results();
+        let locales_res = info.parse_each(
+            node.children()
+                .filter(|n| n.is_element() && n.has_tag_name("locale")),
+            |el| Locale::from_node(el, &info),
+        );
         match locales_res {
             Ok(locales) => {
                 for loc in locales {
                     locale_overrides.insert(loc.lang.clone(), loc);
                 }
             }
-            Err(mut errs) => {
-                errors.append(&mut errs);
+            Err(err) => {
+                errors.push(err);
             }
         }
         // TODO: output errors from macros, locales as well as citation and bibliography, if there are errors in
         // all
-        let macro_res = node
-            .children()
-            .filter(|n| n.is_element() && n.has_tag_name("macro"))
-            .map(|el| MacroMap::from_node(&el, &info))
-            .partition_results();
+        let macro_res = info.parse_each(
+            node.children()
+                .filter(|n| n.is_element() && n.has_tag_name("macro")),
+            |el| MacroMap::from_node(el, &info),
+        );
         match macro_res {
             Ok(macro_maps) => {
                 for mac in macro_maps {
                     macros.insert(mac.name, mac.elements);
                 }
             }
-            Err(mut errs) => {
-                errors.append(&mut errs);
+            Err(err) => {
+                errors.push(err);
             }
         }
         let citation = match Citation::from_node(&get_toplevel(&node, "citation")?, &info) {
@@ -1361,7 +1473,12 @@ impl FromNode for Style {
         };
 
         if !errors.is_empty() {
-            return Err(errors.into());
+            // When recovering, the citation is the only part a style can't do without
+            if citation.is_ok() {
+                info.recover(Err(CslError::from(errors)), || ())?;
+            } else {
+                return Err(errors.into());
+            }
         }
 
         Ok(Style {
@@ -1372,7 +1489,7 @@ impl FromNode for Style {
             citation: citation?,
             features,
             bibliography,
-            info: Info::from_node(&node, &info)?,
+            info: style_info(&node, &info)?,
             class: attribute_required(node, "class", &info)?,
             name_inheritance: Name::from_node(&node, &info)?,
             page_range_format: attribute_option(node, "page-range-format", &info)?,
@@ -1392,6 +1509,24 @@ impl FromNode for Style {
 
 use roxmltree::Document;
 use std::str::FromStr;
+impl Style {
+    /// Like `Style::from_str`, but invalid attributes and elements are skipped over rather than
+    /// failing the whole style. Returns what could be parsed, and the errors that were skipped.
+    ///
+    /// Still fails if the XML itself is malformed, or if the style has no usable `<citation>`.
+    pub fn parse_with_recovery(xml: &str) -> Result<(Self, Vec<InvalidCsl>), StyleError> {
+        let doc = Document::parse(&xml)?;
+        let recovered = Rc::new(RefCell::new(Vec::new()));
+        let info = ParseInfo {
+            recovered: Some(recovered.clone()),
+            ..ParseInfo::default()
+        };
+        let style = Style::from_node(&doc.root_element(), &info)?;
+        let errors = recovered.replace(Vec::new());
+        Ok((style, errors))
+    }
+}
+
 impl FromStr for Style {
     type Err = StyleError;
     fn from_str(xml: &str) -> Result<Self, Self::Err> {
@@ -1401,3 +1536,213 @@ impl FromStr for Style {
         Ok(style)
     }
 }
+
+impl FromNode for AnyStyle {
+    fn from_node(node: &Node, info: &ParseInfo) -> FromNodeResult<Self> {
+        let meta = style_info(node, info)?;
+        // Dependent styles have no <citation>. Checking for one as well means a resolved
+        // dependent style, which keeps its link to the parent, still parses as independent.
+        let has_citation = node.children().any(|n| n.has_tag_name("citation"));
+        if meta.independent_parent().is_some() && !has_citation {
+            CslVersionReq::from_node(node, info)?;
+            Ok(AnyStyle::Dependent(DependentStyle {
+                info: meta,
+                default_locale: attribute_option(node, "default-locale", info)?,
+            }))
+        } else {
+            Ok(AnyStyle::Independent(Style::from_node(node, info)?))
+        }
+    }
+}
+
+impl Info {
+    /// Reads only the `<info>` of a style, independent or dependent, which is much quicker than
+    /// parsing the whole thing. For listing many styles.
+    pub fn from_style_text(xml: &str) -> Result<Self, StyleError> {
+        let doc = Document::parse(&xml)?;
+        let info = ParseInfo::default();
+        Ok(style_info(&doc.root_element(), &info)?)
+    }
+}
+
+impl FromStr for AnyStyle {
+    type Err = StyleError;
+    fn from_str(xml: &str) -> Result<Self, Self::Err> {
+        let doc = Document::parse(&xml)?;
+        let info = ParseInfo::default();
+        let style = AnyStyle::from_node(&doc.root_element(), &info)?;
+        Ok(style)
+    }
+}
+
+impl AnyStyle {
+    /// See `Style::parse_with_recovery`.
+    pub fn parse_with_recovery(xml: &str) -> Result<(Self, Vec<InvalidCsl>), StyleError> {
+        let doc = Document::parse(&xml)?;
+        let recovered = Rc::new(RefCell::new(Vec::new()));
+        let info = ParseInfo {
+            recovered: Some(recovered.clone()),
+            ..ParseInfo::default()
+        };
+        let style = AnyStyle::from_node(&doc.root_element(), &info)?;
+        let errors = recovered.replace(Vec::new());
+        Ok((style, errors))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn recovers_from_invalid_parts() {
+        let xml = r#"
+        <style class="in-text" version="1.0.1">
+            <macro name="title">
+                <text variable="title" font-style="slanted" />
+                <typo />
+            </macro>
+            <citation>
+                <layout>
+                    <text macro="title" />
+                    <text variable="not-a-variable" />
+                    <group delimiter=" ">
+                        <text variable="volume" />
+                    </group>
+                </layout>
+            </citation>
+        </style>"#;
+        assert!(Style::from_str(xml).is_err());
+        let (style, errors) = Style::parse_with_recovery(xml).unwrap();
+        assert_eq!(errors.len(), 3);
+        assert_eq!(style.macros[&Atom::from("title")].len(), 1);
+        // The unknown variable's <text> is dropped, the others are kept
+        assert_eq!(style.citation.layout.elements.len(), 2);
+    }
+
+    #[test]
+    fn dependent_style() {
+        let xml = r#"
+        <style class="in-text" version="1.0" default-locale="de-DE">
+            <info>
+                <title>Journal</title>
+                <link href="http://www.zotero.org/styles/journal" rel="self"/>
+                <link href="http://www.zotero.org/styles/apa" rel="independent-parent"/>
+                <category citation-format="author-date"/>
+                <category field="psychology"/>
+            </info>
+        </style>"#;
+        let dependent = match AnyStyle::from_str(xml).unwrap() {
+            AnyStyle::Dependent(d) => d,
+            AnyStyle::Independent(_) => panic!("parsed as independent"),
+        };
+        assert_eq!(dependent.parent_id(), "http://www.zotero.org/styles/apa");
+        assert_eq!(dependent.info.categories, vec![Category::Psychology]);
+        let parent = Style::from_str(
+            r#"<style class="note" version="1.0.1"><citation><layout/></citation></style>"#,
+        )
+        .unwrap();
+        let style = dependent.apply_to(parent.clone());
+        assert_eq!(style.class, parent.class);
+        assert_eq!(style.default_locale, Lang::from_str("de-DE").unwrap());
+        assert_eq!(style.info, dependent.info);
+    }
+
+    #[test]
+    fn info_from_style_text() {
+        let xml = r#"
+        <style class="in-text" version="1.0">
+            <info>
+                <title>American Psychological Association 7th edition</title>
+                <title-short>APA</title-short>
+                <id>http://www.zotero.org/styles/apa</id>
+                <author>
+                    <name>Brenton M. Wiernik</name>
+                    <email>zotero@wiernik.org</email>
+                </author>
+                <category citation-format="author-date"/>
+                <category field="psychology"/>
+                <category field="generic-base"/>
+                <updated>2019-10-18T00:00:00+00:00</updated>
+                <rights license="http://creativecommons.org/licenses/by-sa/3.0/">
+                    This work is licensed under a Creative Commons Attribution-ShareAlike 3.0 License
+                </rights>
+            </info>
+            <citation><layout/></citation>
+        </style>"#;
+        let info = Info::from_style_text(xml).unwrap();
+        assert_eq!(
+            info.id.as_ref().unwrap(),
+            "http://www.zotero.org/styles/apa"
+        );
+        assert_eq!(info.title_short.as_ref().unwrap(), "APA");
+        assert_eq!(
+            info.authors,
+            vec![InfoPerson {
+                name: "Brenton M. Wiernik".into(),
+                email: Some("zotero@wiernik.org".into()),
+                uri: None,
+            }]
+        );
+        assert_eq!(info.citation_format, Some(CitationFormat::AuthorDate));
+        assert_eq!(
+            info.categories,
+            vec![Category::Psychology, Category::GenericBase]
+        );
+        assert_eq!(
+            info.license.as_ref().unwrap(),
+            "http://creativecommons.org/licenses/by-sa/3.0/"
+        );
+        assert!(info.rights.unwrap().starts_with("This work"));
+        assert_eq!(
+            Style::from_str(xml).unwrap().info.title_short.unwrap(),
+            "APA"
+        );
+    }
+
+    #[test]
+    fn skips_unknown_info_values() {
+        let xml = r#"
+        <style class="in-text" version="1.0">
+            <info>
+                <link href="http://www.zotero.org/styles/apa" rel="self"/>
+                <link href="http://example.com/preprint" rel="preprint"/>
+                <category field="psychology"/>
+                <category field="zoology"/>
+            </info>
+            <citation><layout/></citation>
+        </style>"#;
+        let info = Style::from_str(xml).unwrap().info;
+        assert_eq!(
+            info.links,
+            vec![InfoLink {
+                href: "http://www.zotero.org/styles/apa".into(),
+                rel: LinkRel::SelfLink,
+            }]
+        );
+        assert_eq!(info.categories, vec![Category::Psychology]);
+    }
+
+    #[test]
+    fn skips_incomplete_info() {
+        let xml = r#"
+        <style class="in-text" version="1.0">
+            <info>
+                <author><email>nameless@example.com</email></author>
+                <contributor><name>Named</name></contributor>
+                <category citation-format="footnote"/>
+            </info>
+            <citation><layout/></citation>
+        </style>"#;
+        let info = Style::from_str(xml).unwrap().info;
+        assert!(info.authors.is_empty());
+        assert_eq!(info.contributors.len(), 1);
+        assert_eq!(info.citation_format, None);
+    }
+
+    #[test]
+    fn needs_a_citation() {
+        let xml = r#"<style class="in-text" version="1.0.1"><citation /></style>"#;
+        assert!(Style::parse_with_recovery(xml).is_err());
+    }
+}
@@ -0,0 +1,557 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright © 2019 Corporation for Digital Scholarship
+
+//! Checks for likely mistakes in a style that parses fine. See `lint`.
+
+use crate::error::{InvalidCsl, Severity, StyleError};
+use crate::style::*;
+use crate::terms::Category;
+use crate::variables::*;
+use crate::version::CslVariant;
+use crate::{FromNode, ParseInfo};
+use fnv::{FnvHashMap, FnvHashSet};
+use roxmltree::{Document, Node};
+use std::collections::VecDeque;
+use std::ops::Range;
+use std::str::FromStr;
+use strum::EnumProperty;
+
+/// Attributes that plain CSL doesn't have.
+const CSL_M_ATTRIBUTES: &[&str] = &[
+    "jurisdiction",
+    "subjurisdictions",
+    "context",
+    "is-parallel",
+    "has-year-only",
+    "has-month-or-season",
+    "has-day",
+];
+
+/// Elements that plain CSL doesn't have.
+const CSL_M_ELEMENTS: &[&str] = &["institution", "with"];
+
+/// Parses a style, and then looks for things that are probably mistakes but aren't errors:
+/// undefined, unused or recursive macros, conditions that can never match, CSL-M features in a
+/// plain CSL style, sort keys that sort by macros the layout never renders, deprecated
+/// constructs, and category fields in the `<info>` block that are left out because they aren't
+/// known.
+///
+/// Each of the returned `InvalidCsl`s has a `range` in `xml`, and they are in order of where
+/// they are. Calling an undefined macro or recursing forever is a `Severity::Error`; everything
+/// else is a `Severity::Warning`. If the style doesn't parse, returns the parse errors instead.
+pub fn lint(xml: &str) -> Result<Vec<InvalidCsl>, StyleError> {
+    let doc = Document::parse(xml)?;
+    let root = doc.root_element();
+    let style = Style::from_node(&root, &ParseInfo::default())?;
+    let mut linter = Linter {
+        style: &style,
+        info: ParseInfo {
+            features: style.features.clone(),
+        },
+        definitions: root
+            .children()
+            .filter(|n| n.has_tag_name("macro"))
+            .filter_map(|n| n.attribute("name").map(|name| (name, n)))
+            .collect(),
+        lints: Vec::new(),
+    };
+    linter.macros(root);
+    linter.conditions(root);
+    if style.version_req.0 == CslVariant::Csl {
+        linter.csl_m_features(root);
+    }
+    linter.sort_keys(root);
+    linter.deprecated(root);
+    linter.info(root);
+    let mut lints = linter.lints;
+    lints.sort_by_key(|lint| lint.range.start);
+    Ok(lints)
+}
+
+struct Linter<'s, 'a, 'd: 'a> {
+    style: &'s Style,
+    info: ParseInfo,
+    /// Each `<macro>` by name
+    definitions: FnvHashMap<&'a str, Node<'a, 'd>>,
+    lints: Vec<InvalidCsl>,
+}
+
+/// The `<text macro="...">` and `<key macro="...">` elements under `node`, with the macro names.
+fn macro_calls<'a, 'd: 'a>(node: Node<'a, 'd>) -> impl Iterator<Item = (Node<'a, 'd>, &'a str)> {
+    node.descendants()
+        .filter(|n| n.has_tag_name("text") || n.has_tag_name("key"))
+        .filter_map(|n| n.attribute("macro").map(|name| (n, name)))
+}
+
+fn attribute_range(node: &Node, attr: &str) -> Range<usize> {
+    node.attribute_node(attr)
+        .map_or_else(|| node.range(), |at| at.range())
+}
+
+impl<'s, 'a, 'd: 'a> Linter<'s, 'a, 'd> {
+    fn push(&mut self, severity: Severity, range: Range<usize>, message: String, hint: &str) {
+        self.lints.push(InvalidCsl {
+            severity,
+            range,
+            message,
+            hint: hint.to_owned(),
+        });
+    }
+
+    /// Every macro reachable from `calls`, including those.
+    fn reachable(&self, calls: impl Iterator<Item = &'a str>) -> FnvHashSet<&'a str> {
+        let mut seen = FnvHashSet::default();
+        let mut stack: Vec<&'a str> = calls.collect();
+        while let Some(name) = stack.pop() {
+            if !seen.insert(name) {
+                continue;
+            }
+            if let Some(&def) = self.definitions.get(name) {
+                stack.extend(macro_calls(def).map(|(_, callee)| callee));
+            }
+        }
+        seen
+    }
+
+    /// The shortest chain of macro calls from `from` to `to`, including both.
+    fn call_path(&self, from: &'a str, to: &'a str) -> Option<Vec<&'a str>> {
+        let mut callers: FnvHashMap<&'a str, &'a str> = FnvHashMap::default();
+        let mut queue = VecDeque::new();
+        queue.push_back(from);
+        while let Some(name) = queue.pop_front() {
+            if name == to {
+                let mut path = vec![name];
+                while let Some(&caller) = callers.get(path[path.len() - 1]) {
+                    path.push(caller);
+                }
+                path.reverse();
+                return Some(path);
+            }
+            if let Some(&def) = self.definitions.get(name) {
+                for (_, callee) in macro_calls(def) {
+                    if callee != from && !callers.contains_key(callee) {
+                        callers.insert(callee, name);
+                        queue.push_back(callee);
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    fn macros(&mut self, root: Node<'a, 'd>) {
+        for (call, name) in macro_calls(root) {
+            if !self.definitions.contains_key(name) {
+                self.push(
+                    Severity::Error,
+                    attribute_range(&call, "macro"),
+                    format!("Macro `{}` is not defined", name),
+                    "",
+                );
+            }
+        }
+        for name in self.style.usage().unused_macros {
+            if let Some(&def) = self.definitions.get(&*name) {
+                self.push(
+                    Severity::Warning,
+                    attribute_range(&def, "name"),
+                    format!("Macro `{}` is never used", name),
+                    "",
+                );
+            }
+        }
+        let definitions: Vec<_> = self.definitions.iter().map(|(&k, &v)| (k, v)).collect();
+        for (name, def) in definitions {
+            for (call, callee) in macro_calls(def) {
+                let path = match self.call_path(callee, name) {
+                    Some(path) => path,
+                    None => continue,
+                };
+                let message = if callee == name {
+                    format!("Macro `{}` calls itself", name)
+                } else {
+                    let cycle: Vec<_> = std::iter::once(name)
+                        .chain(path)
+                        .map(|m| format!("`{}`", m))
+                        .collect();
+                    format!(
+                        "Macro `{}` calls itself through {}",
+                        name,
+                        cycle.join(" -> ")
+                    )
+                };
+                self.push(
+                    Severity::Error,
+                    attribute_range(&call, "macro"),
+                    message,
+                    "Hint: rendering it would never finish",
+                );
+            }
+        }
+    }
+
+    fn conditions(&mut self, root: Node<'a, 'd>) {
+        for choose in root.descendants().filter(|n| n.has_tag_name("choose")) {
+            let mut earlier: Vec<Conditions> = Vec::new();
+            let branches = choose
+                .children()
+                .filter(|n| n.has_tag_name("if") || n.has_tag_name("else-if"));
+            for branch in branches {
+                let IfThen(conditions, _) = match IfThen::from_node(&branch, &self.info) {
+                    Ok(if_then) => if_then,
+                    Err(_) => continue,
+                };
+                if never_matches(&conditions) {
+                    self.push(
+                        Severity::Warning,
+                        branch.range(),
+                        "This condition can never match".to_owned(),
+                        "Hint: with match=\"all\", an item can only have one `type`, one \
+                         `locator`, and can't be `first` and another `position`",
+                    );
+                } else if earlier.contains(&conditions) {
+                    self.push(
+                        Severity::Warning,
+                        branch.range(),
+                        "This branch can never be reached".to_owned(),
+                        "Hint: an earlier branch of this <choose> has the same conditions",
+                    );
+                }
+                earlier.push(conditions);
+            }
+        }
+    }
+
+    fn csl_m_features(&mut self, root: Node<'a, 'd>) {
+        for node in root.descendants().filter(|n| n.is_element()) {
+            let tag_name = node.tag_name();
+            let tag = tag_name.name();
+            if CSL_M_ELEMENTS.contains(&tag) {
+                self.push(
+                    Severity::Warning,
+                    node.range(),
+                    format!("<{}> is only available in CSL-M", tag),
+                    "Hint: this style is plain CSL, so other processors will reject it",
+                );
+            }
+            let layout_locale = Some("locale").filter(|_| tag == "layout");
+            for &attr in CSL_M_ATTRIBUTES.iter().chain(layout_locale.iter()) {
+                if node.attribute(attr).is_some() {
+                    self.push(
+                        Severity::Warning,
+                        attribute_range(&node, attr),
+                        format!("`{}` is only available in CSL-M", attr),
+                        "Hint: this style is plain CSL, so other processors will reject it",
+                    );
+                }
+            }
+            for &attr in &[
+                "variable",
+                "is-numeric",
+                "type",
+                "position",
+                "match",
+                "is-uncertain-date",
+                "is-plural",
+            ] {
+                let values = node.attribute(attr).unwrap_or("").split_whitespace();
+                for value in values.filter(|value| csl_m_value(attr, value)) {
+                    self.push(
+                        Severity::Warning,
+                        attribute_range(&node, attr),
+                        format!("{}=\"{}\" is only available in CSL-M", attr, value),
+                        "Hint: this style is plain CSL, so other processors will reject it",
+                    );
+                }
+            }
+        }
+    }
+
+    fn sort_keys(&mut self, root: Node<'a, 'd>) {
+        for sort in root.descendants().filter(|n| n.has_tag_name("sort")) {
+            let layout = sort
+                .parent()
+                .and_then(|p| p.children().find(|n| n.has_tag_name("layout")));
+            let rendered = match layout {
+                Some(layout) => self.reachable(macro_calls(layout).map(|(_, name)| name)),
+                None => continue,
+            };
+            for (key, name) in macro_calls(sort) {
+                if self.definitions.contains_key(name) && !rendered.contains(name) {
+                    self.push(
+                        Severity::Warning,
+                        attribute_range(&key, "macro"),
+                        format!("Sort key macro `{}` is never rendered in the layout", name),
+                        "Hint: entries will be sorted by something that doesn't appear in them",
+                    );
+                }
+            }
+        }
+    }
+
+    fn deprecated(&mut self, root: Node<'a, 'd>) {
+        for node in root.descendants().filter(|n| n.has_tag_name("text")) {
+            if node.attribute("term") == Some("sub verbo") {
+                self.push(
+                    Severity::Warning,
+                    attribute_range(&node, "term"),
+                    "The term `sub verbo` is deprecated".to_owned(),
+                    "Hint: use term=\"sub-verbo\"",
+                );
+            }
+            if node.attribute("variable") == Some("container-title-short") {
+                self.push(
+                    Severity::Warning,
+                    attribute_range(&node, "variable"),
+                    "The variable `container-title-short` is deprecated".to_owned(),
+                    "Hint: use variable=\"container-title\" form=\"short\"",
+                );
+            }
+        }
+    }
+
+    fn info(&mut self, root: Node<'a, 'd>) {
+        let categories = root
+            .children()
+            .filter(|n| n.has_tag_name("info"))
+            .flat_map(|info| info.children())
+            .filter(|n| n.has_tag_name("category"));
+        for category in categories {
+            let field = match category.attribute("field") {
+                Some(field) => field,
+                None => continue,
+            };
+            if Category::from_str(field).is_err() {
+                self.push(
+                    Severity::Warning,
+                    attribute_range(&category, "field"),
+                    format!("Unknown category field `{}`", field),
+                    "Hint: it is left out of the style's categories",
+                );
+            }
+        }
+    }
+}
+
+fn csl_m_only<T: FromStr + EnumProperty>(value: &str) -> bool {
+    T::from_str(value).map_or(false, |v| CslVariant::Csl.filter_arg(v).is_none())
+}
+
+fn csl_m_value(attr: &str, value: &str) -> bool {
+    match attr {
+        "variable" | "is-numeric" => {
+            csl_m_only::<Variable>(value)
+                || csl_m_only::<NameVariable>(value)
+                || csl_m_only::<DateVariable>(value)
+                || csl_m_only::<NumberVariable>(value)
+        }
+        "type" => csl_m_only::<CslType>(value),
+        "position" => csl_m_only::<Position>(value),
+        "match" => csl_m_only::<Match>(value),
+        "is-uncertain-date" => csl_m_only::<DateVariable>(value),
+        "is-plural" => csl_m_only::<NameVariable>(value),
+        _ => false,
+    }
+}
+
+/// Whether a `match="all"` set of tests contradicts itself.
+fn set_never_matches(set: &CondSet) -> bool {
+    if set.match_type != Match::All {
+        return false;
+    }
+    let mut types = 0;
+    let mut locators = 0;
+    let mut first = false;
+    let mut not_first = false;
+    for cond in &set.conds {
+        match cond {
+            Cond::Type(_) => types += 1,
+            Cond::Locator(_) => locators += 1,
+            Cond::Position(Position::First) => first = true,
+            Cond::Position(_) => not_first = true,
+            _ => {}
+        }
+    }
+    types > 1 || locators > 1 || (first && not_first)
+}
+
+fn never_matches(conditions: &Conditions) -> bool {
+    let Conditions(match_type, sets) = conditions;
+    match match_type {
+        Match::All => sets.iter().any(set_never_matches),
+        Match::Any => sets.iter().all(set_never_matches),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn messages(xml: &str) -> Vec<(Severity, String)> {
+        lint(xml)
+            .unwrap()
+            .into_iter()
+            .map(|l| (l.severity, l.message))
+            .collect()
+    }
+
+    fn style(body: &str) -> String {
+        format!(r#"<style class="in-text" version="1.0.1">{}</style>"#, body)
+    }
+
+    #[test]
+    fn macros() {
+        let xml = style(
+            r#"
+            <macro name="unused"><text value="x" /></macro>
+            <macro name="a"><text macro="b" /></macro>
+            <macro name="b"><text macro="a" /></macro>
+            <macro name="c"><text macro="c" /></macro>
+            <macro name="d"><text macro="e" /></macro>
+            <macro name="e"><text macro="f" /></macro>
+            <macro name="f"><text macro="d" /></macro>
+            <citation>
+                <layout>
+                    <text macro="a" />
+                    <text macro="c" />
+                    <text macro="d" />
+                    <text macro="undefined" />
+                </layout>
+            </citation>"#,
+        );
+        assert_eq!(
+            messages(&xml),
+            vec![
+                (Severity::Warning, "Macro `unused` is never used".to_owned()),
+                (
+                    Severity::Error,
+                    "Macro `a` calls itself through `a` -> `b` -> `a`".to_owned()
+                ),
+                (
+                    Severity::Error,
+                    "Macro `b` calls itself through `b` -> `a` -> `b`".to_owned()
+                ),
+                (Severity::Error, "Macro `c` calls itself".to_owned()),
+                (
+                    Severity::Error,
+                    "Macro `d` calls itself through `d` -> `e` -> `f` -> `d`".to_owned()
+                ),
+                (
+                    Severity::Error,
+                    "Macro `e` calls itself through `e` -> `f` -> `d` -> `e`".to_owned()
+                ),
+                (
+                    Severity::Error,
+                    "Macro `f` calls itself through `f` -> `d` -> `e` -> `f`".to_owned()
+                ),
+                (
+                    Severity::Error,
+                    "Macro `undefined` is not defined".to_owned()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn conditions() {
+        let xml = style(
+            r#"
+            <citation>
+                <layout>
+                    <choose>
+                        <if type="book chapter" match="all">
+                            <text value="never" />
+                        </if>
+                        <else-if type="book" variable="title" match="all">
+                            <text value="ok" />
+                        </else-if>
+                        <else-if type="book" variable="title" match="all">
+                            <text value="unreachable" />
+                        </else-if>
+                    </choose>
+                </layout>
+            </citation>"#,
+        );
+        assert_eq!(
+            messages(&xml),
+            vec![
+                (
+                    Severity::Warning,
+                    "This condition can never match".to_owned()
+                ),
+                (
+                    Severity::Warning,
+                    "This branch can never be reached".to_owned()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn csl_m_and_deprecated() {
+        let body = r#"
+            <macro name="sort"><text variable="title" /></macro>
+            <citation>
+                <layout>
+                    <group is-parallel="true">
+                        <text variable="volume-title" />
+                        <text term="sub verbo" />
+                    </group>
+                </layout>
+            </citation>
+            <bibliography>
+                <sort><key macro="sort" /></sort>
+                <layout><text variable="title" /></layout>
+            </bibliography>"#;
+        assert_eq!(
+            messages(&style(body)),
+            vec![
+                (
+                    Severity::Warning,
+                    "`is-parallel` is only available in CSL-M".to_owned()
+                ),
+                (
+                    Severity::Warning,
+                    "variable=\"volume-title\" is only available in CSL-M".to_owned()
+                ),
+                (
+                    Severity::Warning,
+                    "The term `sub verbo` is deprecated".to_owned()
+                ),
+                (
+                    Severity::Warning,
+                    "Sort key macro `sort` is never rendered in the layout".to_owned()
+                ),
+            ]
+        );
+        let csl_m = format!(
+            r#"<style class="in-text" version="1.1mlz1">{}</style>"#,
+            body
+        );
+        assert_eq!(messages(&csl_m).len(), 2);
+    }
+
+    #[test]
+    fn unknown_categories() {
+        let xml = style(
+            r#"
+            <info>
+                <category citation-format="author-date"/>
+                <category field="psychology"/>
+                <category field="zoology"/>
+            </info>
+            <citation><layout><text variable="title" /></layout></citation>"#,
+        );
+        assert_eq!(
+            messages(&xml),
+            vec![(
+                Severity::Warning,
+                "Unknown category field `zoology`".to_owned()
+            )]
+        );
+    }
+}
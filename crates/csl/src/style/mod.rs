@@ -1034,9 +1034,119 @@ impl Default for StyleClass {
     }
 }
 
-#[derive(Default, Debug, Eq, Clone, PartialEq)]
+/// The style's metadata from `<info>`. None of it affects rendering.
+///
+/// [Spec](https://docs.citationstyles.org/en/stable/specification.html#info)
+#[derive(Default, Debug, Eq, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Info {
+    /// Usually a URL like `http://www.zotero.org/styles/apa`
+    pub id: Option<String>,
+    pub title: Option<String>,
+    pub title_short: Option<String>,
+    pub links: Vec<InfoLink>,
+    pub authors: Vec<InfoPerson>,
+    pub contributors: Vec<InfoPerson>,
+    /// From `<category citation-format="..."/>`
+    pub citation_format: Option<CitationFormat>,
+    /// From `<category field="..."/>`
     pub categories: Vec<Category>,
+    pub issn: Vec<String>,
+    pub eissn: Option<String>,
+    pub issnl: Option<String>,
+    pub summary: Option<String>,
+    /// A timestamp, as written in the style
+    pub published: Option<String>,
+    /// A timestamp, as written in the style
+    pub updated: Option<String>,
+    pub rights: Option<String>,
+    /// The `license` attribute of `<rights>`, usually a URL
+    pub license: Option<String>,
+}
+
+impl Info {
+    /// The id of the style a dependent style takes its rendering rules from.
+    pub fn independent_parent(&self) -> Option<&str> {
+        self.links
+            .iter()
+            .find(|l| l.rel == LinkRel::IndependentParent)
+            .map(|l| l.href.as_str())
+    }
+}
+
+/// `<link href="..." rel="..." />` in `<info>`
+#[derive(Debug, Eq, Clone, PartialEq, Serialize)]
+pub struct InfoLink {
+    pub href: String,
+    pub rel: LinkRel,
+}
+
+#[derive(AsRefStr, EnumProperty, EnumString, Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[strum(serialize_all = "kebab_case")]
+#[serde(rename_all = "kebab-case")]
+pub enum LinkRel {
+    /// The style's own URI
+    #[strum(serialize = "self")]
+    #[serde(rename = "self")]
+    SelfLink,
+    Template,
+    Documentation,
+    /// Only in dependent styles
+    IndependentParent,
+}
+
+/// `<author>` or `<contributor>` in `<info>`
+#[derive(Default, Debug, Eq, Clone, PartialEq, Serialize)]
+pub struct InfoPerson {
+    pub name: String,
+    pub email: Option<String>,
+    pub uri: Option<String>,
+}
+
+/// The kind of citations a style produces, for style pickers.
+#[derive(AsRefStr, EnumProperty, EnumString, Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[strum(serialize_all = "kebab_case")]
+#[serde(rename_all = "kebab-case")]
+pub enum CitationFormat {
+    AuthorDate,
+    Author,
+    Numeric,
+    Label,
+    Note,
+}
+
+/// A style with no rendering rules of its own. It has metadata and a default locale, and
+/// borrows everything else from its independent parent.
+#[derive(Debug, Eq, Clone, PartialEq)]
+pub struct DependentStyle {
+    pub info: Info,
+    pub default_locale: Option<Lang>,
+}
+
+impl DependentStyle {
+    /// The id of the parent style, usually a URL like `http://www.zotero.org/styles/apa`.
+    pub fn parent_id(&self) -> &str {
+        self.info.independent_parent().unwrap_or("")
+    }
+
+    /// Puts this style's metadata and default locale on top of the parent's rendering rules.
+    pub fn apply_to(&self, parent: Style) -> Style {
+        Style {
+            info: self.info.clone(),
+            default_locale: self
+                .default_locale
+                .clone()
+                .unwrap_or(parent.default_locale),
+            ..parent
+        }
+    }
+}
+
+/// Either an independent style, or a dependent one that needs its parent fetched.
+#[derive(Debug, Eq, Clone, PartialEq)]
+pub enum AnyStyle {
+    Independent(Style),
+    Dependent(DependentStyle),
 }
 
 #[derive(Debug, Eq, Clone, PartialEq)]
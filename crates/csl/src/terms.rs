@@ -12,7 +12,7 @@ use std::str::FromStr;
 use super::attr::GetAttribute;
 use super::variables::{NameVariable, NumberVariable};
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum TextTermSelector {
     Simple(SimpleTermSelector),
     Gendered(GenderedTermSelector),
@@ -550,8 +550,9 @@ impl RoleTerm {
 /// that matches "terms accompanying the number variables" in [option (a)
 /// here](https://docs.citationstyles.org/en/stable/specification.html#gender-specific-ordinals)
 
-#[derive(AsRefStr, EnumProperty, EnumString, Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(AsRefStr, EnumProperty, EnumString, Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize)]
 #[strum(serialize_all = "kebab_case")]
+#[serde(rename_all = "kebab-case")]
 pub enum Category {
     Anthropology,
     Astronomy,
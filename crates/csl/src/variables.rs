@@ -9,6 +9,7 @@ use super::error::*;
 use super::version::Features;
 use super::IsIndependent;
 use super::Style;
+use std::borrow::Cow;
 
 #[derive(Debug, Eq, Copy, Clone, PartialEq, EnumProperty, Hash)]
 pub enum AnyVariable {
@@ -34,6 +35,24 @@ impl GetAttribute for AnyVariable {
     }
 }
 
+impl AsRef<str> for AnyVariable {
+    fn as_ref(&self) -> &str {
+        match self {
+            AnyVariable::Ordinary(v) => v.as_ref(),
+            AnyVariable::Name(v) => v.as_ref(),
+            AnyVariable::Date(v) => v.as_ref(),
+            AnyVariable::Number(v) => v.as_ref(),
+        }
+    }
+}
+
+/// Serialized as the CSL variable name
+impl serde::Serialize for AnyVariable {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_ref())
+    }
+}
+
 /// Contrary to the CSL-M spec's declaration that number variables in a regular `<text variable>`
 /// "should fail validation", that is perfectly valid, because "number variables are a subset of the
 /// standard variables":
@@ -256,6 +275,45 @@ impl Variable {
             _ => None,
         }
     }
+    /// Like `hyperlink`, but DOIs and PMIDs are turned into URLs if they aren't already, so
+    /// PMIDs can be linked too.
+    pub fn resolved_hyperlink(self, value: &str) -> Option<Cow<'_, str>> {
+        let value = value.trim();
+        match self {
+            Variable::URL => Some(Cow::Borrowed(value)),
+            Variable::DOI => Some(doi_url(value)),
+            Variable::PMID if has_scheme(value) => Some(Cow::Borrowed(value)),
+            Variable::PMID => Some(Cow::Owned(format!(
+                "https://www.ncbi.nlm.nih.gov/pubmed/{}",
+                value
+            ))),
+            _ => None,
+        }
+    }
+    /// Whether bare URLs in this variable's value should be linked, when linking is on.
+    pub fn may_contain_urls(self) -> bool {
+        match self {
+            Variable::Title | Variable::TitleShort | Variable::Note => true,
+            _ => false,
+        }
+    }
+}
+
+fn has_scheme(value: &str) -> bool {
+    value.starts_with("https://") || value.starts_with("http://")
+}
+
+fn doi_url(value: &str) -> Cow<'_, str> {
+    if has_scheme(value) {
+        return Cow::Borrowed(value);
+    }
+    let mut doi = value;
+    for prefix in &["doi:", "DOI:", "dx.doi.org/", "doi.org/"] {
+        if doi.starts_with(prefix) {
+            doi = doi[prefix.len()..].trim_start();
+        }
+    }
+    Cow::Owned(format!("https://doi.org/{}", doi))
 }
 
 impl IsIndependent for NumberVariable {
@@ -0,0 +1,1018 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright © 2019 Corporation for Digital Scholarship
+
+//! Writes a `Style` or `Locale` back out as CSL XML.
+//!
+//! Attributes that hold their default value are left off, so the output is not byte-for-byte
+//! the same as the input, but parsing it gives back the same tree.
+
+use crate::locale::{Locale, LocaleDate, LocaleOptionsNode};
+use crate::style::*;
+use crate::terms::*;
+use crate::variables::*;
+use crate::version::{CslVariant, CslVersionReq};
+use std::fmt::Write;
+use strum::AsStaticRef;
+
+const CSL_NAMESPACE: &str = "http://purl.org/net/xbiblio/csl";
+
+impl Style {
+    /// Serializes the style as a pretty-printed CSL document.
+    pub fn to_xml(&self) -> String {
+        document(&style_el(self))
+    }
+}
+
+impl Locale {
+    /// Serializes the locale as a pretty-printed, standalone CSL locale file.
+    pub fn to_xml(&self) -> String {
+        let mut el = locale_el(self);
+        el.attrs.insert(0, ("xmlns", CSL_NAMESPACE.into()));
+        document(&el)
+    }
+}
+
+fn document(root: &XmlElement) -> String {
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    root.write(&mut out, 0);
+    out
+}
+
+/// A minimal element tree, so the CSL structures don't have to know about indentation.
+struct XmlElement {
+    tag: &'static str,
+    attrs: Vec<(&'static str, String)>,
+    text: Option<String>,
+    children: Vec<XmlElement>,
+}
+
+impl XmlElement {
+    fn new(tag: &'static str) -> Self {
+        XmlElement {
+            tag,
+            attrs: Vec::new(),
+            text: None,
+            children: Vec::new(),
+        }
+    }
+
+    fn attr(&mut self, name: &'static str, value: impl AsRef<str>) {
+        self.attrs.push((name, value.as_ref().into()));
+    }
+
+    fn opt_attr<T: AsRef<str>>(&mut self, name: &'static str, value: Option<T>) {
+        if let Some(v) = value {
+            self.attr(name, v);
+        }
+    }
+
+    /// Only writes the attribute when it differs from the default the parser would give.
+    fn non_default<T: AsRef<str> + Default + PartialEq>(&mut self, name: &'static str, value: &T) {
+        if *value != T::default() {
+            self.attr(name, value);
+        }
+    }
+
+    fn flag(&mut self, name: &'static str, value: bool) {
+        if value {
+            self.attr(name, "true");
+        }
+    }
+
+    fn opt_bool(&mut self, name: &'static str, value: Option<bool>) {
+        self.opt_attr(name, value.map(bool_str));
+    }
+
+    fn opt_int(&mut self, name: &'static str, value: Option<u32>) {
+        self.opt_attr(name, value.map(|n| n.to_string()));
+    }
+
+    fn list<T: AsRef<str>>(&mut self, name: &'static str, values: &[T]) {
+        if !values.is_empty() {
+            let joined: Vec<&str> = values.iter().map(AsRef::as_ref).collect();
+            self.attr(name, joined.join(" "));
+        }
+    }
+
+    fn delimiter(&mut self, name: &'static str, delim: &Delimiter) {
+        if !delim.0.is_empty() {
+            self.attr(name, &delim.0);
+        }
+    }
+
+    fn formatting(&mut self, formatting: &Option<Formatting>) {
+        if let Some(f) = formatting {
+            self.opt_attr("font-style", f.font_style);
+            self.opt_attr("font-variant", f.font_variant);
+            self.opt_attr("font-weight", f.font_weight);
+            self.opt_attr("text-decoration", f.text_decoration);
+            self.opt_attr("vertical-align", f.vertical_alignment);
+        }
+    }
+
+    fn affixes(&mut self, affixes: &Option<Affixes>) {
+        if let Some(a) = affixes {
+            if !a.prefix.is_empty() || a.suffix.is_empty() {
+                self.attr("prefix", &a.prefix);
+            }
+            if !a.suffix.is_empty() {
+                self.attr("suffix", &a.suffix);
+            }
+        }
+    }
+
+    fn display(&mut self, display: Option<DisplayMode>) {
+        self.opt_attr("display", display);
+    }
+
+    fn child(&mut self, child: XmlElement) {
+        self.children.push(child);
+    }
+
+    /// `<tag>text</tag>`, if there is any text.
+    fn text_child(&mut self, tag: &'static str, text: Option<&String>) {
+        if let Some(text) = text {
+            let mut child = XmlElement::new(tag);
+            child.text = Some(text.clone());
+            self.child(child);
+        }
+    }
+
+    fn elements(&mut self, elements: &[Element]) {
+        self.children.extend(elements.iter().map(element_el));
+    }
+
+    fn write(&self, out: &mut String, depth: usize) {
+        for _ in 0..depth {
+            out.push_str("  ");
+        }
+        out.push('<');
+        out.push_str(self.tag);
+        for (name, value) in &self.attrs {
+            write!(out, " {}=\"{}\"", name, escape(value, true)).unwrap();
+        }
+        if let Some(text) = &self.text {
+            writeln!(out, ">{}</{}>", escape(text, false), self.tag).unwrap();
+        } else if self.children.is_empty() {
+            out.push_str("/>\n");
+        } else {
+            out.push_str(">\n");
+            for child in &self.children {
+                child.write(out, depth + 1);
+            }
+            for _ in 0..depth {
+                out.push_str("  ");
+            }
+            writeln!(out, "</{}>", self.tag).unwrap();
+        }
+    }
+}
+
+fn escape(s: &str, attribute: bool) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' if attribute => out.push_str("&quot;"),
+            // Parsers normalise literal whitespace in attribute values to spaces
+            '\n' if attribute => out.push_str("&#10;"),
+            '\r' => out.push_str("&#13;"),
+            '\t' if attribute => out.push_str("&#9;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn bool_str(b: bool) -> &'static str {
+    if b {
+        "true"
+    } else {
+        "false"
+    }
+}
+
+fn style_el(style: &Style) -> XmlElement {
+    let mut el = XmlElement::new("style");
+    el.attr("xmlns", CSL_NAMESPACE);
+    el.attr("class", style.class);
+    let CslVersionReq(variant, req) = &style.version_req;
+    // `VersionReq` displays "1.0" as "^1.0", which means the same thing
+    let version = req.to_string();
+    el.attr("version", version.trim_start_matches('^'));
+    if *variant == CslVariant::CslM {
+        el.attr("variant", "csl-m");
+    }
+    el.attr("default-locale", style.default_locale.to_string());
+    el.opt_attr("page-range-format", style.page_range_format);
+    el.non_default(
+        "demote-non-dropping-particle",
+        &style.demote_non_dropping_particle,
+    );
+    if !style.initialize_with_hyphen {
+        el.attr("initialize-with-hyphen", "false");
+    }
+    inheritable_names(&mut el, &style.name_inheritance, &style.names_delimiter);
+
+    if style.info != Info::default() {
+        el.child(info_el(&style.info));
+    }
+
+    let mut features = XmlElement::new("features");
+    style.features.walk_feature_fields(|name, enabled| {
+        if enabled {
+            let mut f = XmlElement::new("feature");
+            f.attr("name", name.replace('_', "-"));
+            features.child(f);
+        }
+    });
+    if !features.children.is_empty() {
+        el.child(features);
+    }
+
+    let mut locales: Vec<_> = style.locale_overrides.values().collect();
+    locales.sort_by_key(|l| l.lang.as_ref().map(ToString::to_string));
+    for locale in locales {
+        el.child(locale_el(locale));
+    }
+
+    let mut macros: Vec<_> = style.macros.iter().collect();
+    macros.sort_by(|a, b| str::cmp(a.0, b.0));
+    for (name, elements) in macros {
+        let mut m = XmlElement::new("macro");
+        m.attr("name", name);
+        m.elements(elements);
+        el.child(m);
+    }
+
+    el.child(citation_el(&style.citation));
+    if let Some(bib) = &style.bibliography {
+        el.child(bibliography_el(bib));
+    }
+    el
+}
+
+fn info_el(info: &Info) -> XmlElement {
+    let mut el = XmlElement::new("info");
+    el.text_child("title", info.title.as_ref());
+    el.text_child("title-short", info.title_short.as_ref());
+    el.text_child("id", info.id.as_ref());
+    for link in &info.links {
+        let mut l = XmlElement::new("link");
+        l.attr("href", &link.href);
+        l.attr("rel", link.rel);
+        el.child(l);
+    }
+    for author in &info.authors {
+        el.child(person_el("author", author));
+    }
+    for contributor in &info.contributors {
+        el.child(person_el("contributor", contributor));
+    }
+    if let Some(format) = info.citation_format {
+        let mut c = XmlElement::new("category");
+        c.attr("citation-format", format);
+        el.child(c);
+    }
+    for category in &info.categories {
+        let mut c = XmlElement::new("category");
+        c.attr("field", category);
+        el.child(c);
+    }
+    for issn in &info.issn {
+        el.text_child("issn", Some(issn));
+    }
+    el.text_child("eissn", info.eissn.as_ref());
+    el.text_child("issnl", info.issnl.as_ref());
+    el.text_child("summary", info.summary.as_ref());
+    el.text_child("published", info.published.as_ref());
+    el.text_child("updated", info.updated.as_ref());
+    if info.rights.is_some() || info.license.is_some() {
+        let mut rights = XmlElement::new("rights");
+        rights.opt_attr("license", info.license.as_ref());
+        rights.text = info.rights.clone();
+        el.child(rights);
+    }
+    el
+}
+
+fn person_el(tag: &'static str, person: &InfoPerson) -> XmlElement {
+    let mut el = XmlElement::new(tag);
+    el.text_child("name", Some(&person.name));
+    el.text_child("email", person.email.as_ref());
+    el.text_child("uri", person.uri.as_ref());
+    el
+}
+
+fn citation_el(citation: &Citation) -> XmlElement {
+    let mut el = XmlElement::new("citation");
+    el.flag("disambiguate-add-names", citation.disambiguate_add_names);
+    el.flag(
+        "disambiguate-add-givenname",
+        citation.disambiguate_add_givenname,
+    );
+    el.non_default(
+        "givenname-disambiguation-rule",
+        &citation.givenname_disambiguation_rule,
+    );
+    el.flag(
+        "disambiguate-add-year-suffix",
+        citation.disambiguate_add_year_suffix,
+    );
+    inheritable_names(
+        &mut el,
+        &citation.name_inheritance,
+        &citation.names_delimiter,
+    );
+    el.child(layout_el(&citation.layout));
+    el
+}
+
+fn bibliography_el(bib: &Bibliography) -> XmlElement {
+    let mut el = XmlElement::new("bibliography");
+    el.flag("hanging-indent", bib.hanging_indent);
+    el.opt_attr("second-field-align", bib.second_field_align.as_ref());
+    if bib.line_spaces != 1 {
+        el.attr("line-spaces", bib.line_spaces.to_string());
+    }
+    if bib.entry_spacing != 1 {
+        el.attr("entry-spacing", bib.entry_spacing.to_string());
+    }
+    el.opt_attr(
+        "subsequent-author-substitute",
+        bib.subsequent_author_substitute.as_ref(),
+    );
+    el.non_default(
+        "subsequent-author-substitute-rule",
+        &bib.subsequent_author_substitute_rule,
+    );
+    inheritable_names(&mut el, &bib.name_inheritance, &bib.names_delimiter);
+    if let Some(sort) = &bib.sort {
+        let mut s = XmlElement::new("sort");
+        for key in &sort.keys {
+            let mut k = XmlElement::new("key");
+            match &key.sort_source {
+                SortSource::Variable(v) => k.attr("variable", v),
+                SortSource::Macro(m) => k.attr("macro", m),
+            }
+            k.opt_int("names-min", key.names_min);
+            k.opt_int("names-use-first", key.names_use_first);
+            k.opt_bool("names-use-last", key.names_use_last);
+            k.opt_attr("sort", key.direction);
+            s.child(k);
+        }
+        el.child(s);
+    }
+    el.child(layout_el(&bib.layout));
+    el
+}
+
+fn layout_el(layout: &Layout) -> XmlElement {
+    let mut el = XmlElement::new("layout");
+    let locales: Vec<String> = layout.locale.iter().map(ToString::to_string).collect();
+    el.list("locale", &locales);
+    el.affixes(&layout.affixes);
+    el.formatting(&layout.formatting);
+    el.delimiter("delimiter", &layout.delimiter);
+    el.elements(&layout.elements);
+    el
+}
+
+/// The name options that `<style>`, `<citation>` and `<bibliography>` pass down to `<name>`.
+fn inheritable_names(el: &mut XmlElement, name: &Name, names_delimiter: &Option<Delimiter>) {
+    name_options(el, name, "name-delimiter", "name-form");
+    el.opt_attr("names-delimiter", names_delimiter.as_ref().map(|d| &d.0));
+    el.affixes(&name.affixes);
+    el.formatting(&name.formatting);
+}
+
+fn name_options(
+    el: &mut XmlElement,
+    name: &Name,
+    delim_attr: &'static str,
+    form_attr: &'static str,
+) {
+    el.opt_attr("and", name.and);
+    el.opt_attr(delim_attr, name.delimiter.as_ref().map(|d| &d.0));
+    el.opt_attr("delimiter-precedes-et-al", name.delimiter_precedes_et_al);
+    el.opt_attr("delimiter-precedes-last", name.delimiter_precedes_last);
+    el.opt_int("et-al-min", name.et_al_min);
+    el.opt_int("et-al-use-first", name.et_al_use_first);
+    el.opt_bool("et-al-use-last", name.et_al_use_last);
+    el.opt_int("et-al-subsequent-min", name.et_al_subsequent_min);
+    el.opt_int(
+        "et-al-subsequent-use-first",
+        name.et_al_subsequent_use_first,
+    );
+    el.opt_attr(form_attr, name.form);
+    el.opt_bool("initialize", name.initialize);
+    el.opt_attr("initialize-with", name.initialize_with.as_ref());
+    el.opt_attr("name-as-sort-order", name.name_as_sort_order);
+    el.opt_attr("sort-separator", name.sort_separator.as_ref());
+}
+
+fn element_el(element: &Element) -> XmlElement {
+    match element {
+        Element::Text(text) => text_el(text),
+        Element::Label(label) => {
+            let mut el = XmlElement::new("label");
+            el.attr("variable", label.variable);
+            el.non_default("form", &label.form);
+            el.non_default("plural", &label.plural);
+            el.affixes(&label.affixes);
+            el.formatting(&label.formatting);
+            el.flag("strip-periods", label.strip_periods);
+            el.non_default("text-case", &label.text_case);
+            el
+        }
+        Element::Number(number) => {
+            let mut el = XmlElement::new("number");
+            el.attr("variable", number.variable);
+            el.non_default("form", &number.form);
+            el.affixes(&number.affixes);
+            el.formatting(&number.formatting);
+            el.non_default("text-case", &number.text_case);
+            el.display(number.display);
+            el
+        }
+        Element::Group(group) => {
+            let mut el = XmlElement::new("group");
+            el.delimiter("delimiter", &group.delimiter);
+            el.affixes(&group.affixes);
+            el.formatting(&group.formatting);
+            el.display(group.display);
+            el.flag("is-parallel", group.is_parallel);
+            el.elements(&group.elements);
+            el
+        }
+        Element::Choose(choose) => choose_el(choose),
+        Element::Names(names) => names_el(names),
+        Element::Date(date) => date_el(date),
+    }
+}
+
+fn text_el(text: &TextElement) -> XmlElement {
+    let mut el = XmlElement::new("text");
+    match &text.source {
+        TextSource::Macro(name) => el.attr("macro", name),
+        TextSource::Value(value) => el.attr("value", value),
+        TextSource::Variable(var, form) => {
+            el.attr("variable", AnyVariable::from(var));
+            el.non_default("form", form);
+        }
+        TextSource::Term(sel, plural) => {
+            term_selector(&mut el, sel);
+            el.flag("plural", *plural);
+        }
+    }
+    el.affixes(&text.affixes);
+    el.formatting(&text.formatting);
+    el.flag("quotes", text.quotes);
+    el.flag("strip-periods", text.strip_periods);
+    el.non_default("text-case", &text.text_case);
+    el.display(text.display);
+    el
+}
+
+fn term_selector(el: &mut XmlElement, sel: &TextTermSelector) {
+    match *sel {
+        TextTermSelector::Simple(SimpleTermSelector::Misc(t, form)) => {
+            el.attr("term", t);
+            el.non_default("form", &form);
+        }
+        TextTermSelector::Simple(SimpleTermSelector::Category(t, form)) => {
+            el.attr("term", t);
+            el.non_default("form", &form);
+        }
+        TextTermSelector::Simple(SimpleTermSelector::Quote(t)) => el.attr("term", t),
+        TextTermSelector::Gendered(ref g) => {
+            let (name, form) = gendered_name(g);
+            el.attr("term", name);
+            el.non_default("form", &form);
+        }
+        TextTermSelector::Role(RoleTermSelector(t, form)) => {
+            el.attr("term", t);
+            el.non_default("form", &form);
+        }
+    }
+}
+
+fn gendered_name(sel: &GenderedTermSelector) -> (&str, TermForm) {
+    match sel {
+        GenderedTermSelector::Number(v, form) => (v.as_ref(), *form),
+        GenderedTermSelector::Locator(l, form) => (l.as_ref(), *form),
+        GenderedTermSelector::Month(m, form) => (m.as_ref(), *form),
+        GenderedTermSelector::Season(s, form) => (s.as_ref(), *form),
+    }
+}
+
+fn choose_el(choose: &Choose) -> XmlElement {
+    let Choose(if_, else_ifs, Else(else_elements)) = choose;
+    let mut el = XmlElement::new("choose");
+    el.child(if_then_el("if", if_));
+    for else_if in else_ifs {
+        el.child(if_then_el("else-if", else_if));
+    }
+    if !else_elements.is_empty() {
+        let mut e = XmlElement::new("else");
+        e.elements(else_elements);
+        el.child(e);
+    }
+    el
+}
+
+fn if_then_el(tag: &'static str, if_then: &IfThen) -> XmlElement {
+    let IfThen(Conditions(match_type, sets), elements) = if_then;
+    let mut el = XmlElement::new(tag);
+    match (match_type, sets.as_slice()) {
+        // Conditions written directly on the <if> parse as a single set under match="all"
+        (Match::All, [set]) => cond_set(&mut el, set),
+        _ => {
+            let mut conditions = XmlElement::new("conditions");
+            conditions.attr("match", match_type);
+            for set in sets {
+                let mut c = XmlElement::new("condition");
+                cond_set(&mut c, set);
+                conditions.child(c);
+            }
+            el.child(conditions);
+        }
+    }
+    el.elements(elements);
+    el
+}
+
+fn cond_set(el: &mut XmlElement, set: &CondSet) {
+    let mut disambiguate = None;
+    let mut is_numeric = Vec::new();
+    let mut variable = Vec::new();
+    let mut position = Vec::new();
+    let mut csl_type = Vec::new();
+    let mut locator = Vec::new();
+    let mut is_uncertain_date = Vec::new();
+    let mut has_year_only = Vec::new();
+    let mut has_month_or_season = Vec::new();
+    let mut has_day = Vec::new();
+    let mut context = None;
+    let mut is_plural = Vec::new();
+    let mut jurisdiction = None;
+    let mut subjurisdictions = None;
+    for cond in &set.conds {
+        match cond {
+            Cond::Disambiguate(d) => disambiguate = Some(*d),
+            Cond::IsNumeric(v) => is_numeric.push(v.as_ref()),
+            Cond::Variable(v) => variable.push(v.as_ref()),
+            Cond::Position(p) => position.push(p.as_ref()),
+            Cond::Type(t) => csl_type.push(t.as_ref()),
+            // "sub verbo" is the term name, but the list is space-separated
+            Cond::Locator(LocatorType::SubVerbo) => locator.push("sub-verbo"),
+            Cond::Locator(l) => locator.push(l.as_ref()),
+            Cond::IsUncertainDate(v) => is_uncertain_date.push(v.as_ref()),
+            Cond::HasYearOnly(v) => has_year_only.push(v.as_ref()),
+            Cond::HasMonthOrSeason(v) => has_month_or_season.push(v.as_ref()),
+            Cond::HasDay(v) => has_day.push(v.as_ref()),
+            Cond::Context(c) => context = Some(*c),
+            Cond::IsPlural(v) => is_plural.push(v.as_ref()),
+            Cond::Jurisdiction(j) => jurisdiction = Some(j),
+            Cond::SubJurisdiction(n) => subjurisdictions = Some(*n),
+        }
+    }
+    // The conditions are a set, so sort them for stable output
+    let mut list = |name, mut values: Vec<&str>| {
+        values.sort();
+        el.list(name, &values);
+    };
+    list("variable", variable);
+    list("type", csl_type);
+    list("position", position);
+    list("locator", locator);
+    list("is-numeric", is_numeric);
+    list("is-uncertain-date", is_uncertain_date);
+    list("is-plural", is_plural);
+    list("has-year-only", has_year_only);
+    list("has-month-or-season", has_month_or_season);
+    list("has-day", has_day);
+    el.opt_bool("disambiguate", disambiguate);
+    el.opt_attr("context", context);
+    el.opt_attr("jurisdiction", jurisdiction);
+    el.opt_int("subjurisdictions", subjurisdictions);
+    el.non_default("match", &set.match_type);
+}
+
+fn names_el(names: &Names) -> XmlElement {
+    let mut el = XmlElement::new("names");
+    el.list("variable", &names.variables);
+    el.opt_attr("delimiter", names.delimiter.as_ref().map(|d| &d.0));
+    el.affixes(&names.affixes);
+    el.formatting(&names.formatting);
+    el.display(names.display);
+
+    let label = names.label.as_ref().map(name_label_el);
+    let after_name = names.label.as_ref().map_or(false, |l| l.after_name);
+    let mut label = label.into_iter();
+    if !after_name {
+        el.children.extend(label.by_ref());
+    }
+    if let Some(name) = &names.name {
+        el.child(name_el(name));
+    }
+    el.children.extend(label);
+    if let Some(et_al) = &names.et_al {
+        let mut e = XmlElement::new("et-al");
+        if !et_al.term.is_empty() {
+            e.attr("term", &et_al.term);
+        }
+        e.formatting(&et_al.formatting);
+        el.child(e);
+    }
+    if let Some(institution) = &names.institution {
+        el.child(institution_el(institution));
+    }
+    if let Some(with) = &names.with {
+        let mut w = XmlElement::new("with");
+        w.affixes(&with.affixes);
+        w.formatting(&with.formatting);
+        el.child(w);
+    }
+    if let Some(Substitute(elements)) = &names.substitute {
+        let mut s = XmlElement::new("substitute");
+        s.elements(elements);
+        el.child(s);
+    }
+    el
+}
+
+fn name_el(name: &Name) -> XmlElement {
+    let mut el = XmlElement::new("name");
+    name_options(&mut el, name, "delimiter", "form");
+    el.affixes(&name.affixes);
+    el.formatting(&name.formatting);
+    for part in name
+        .name_part_given
+        .iter()
+        .chain(name.name_part_family.iter())
+    {
+        let mut p = XmlElement::new("name-part");
+        p.attr("name", part.name);
+        p.affixes(&part.affixes);
+        p.formatting(&part.formatting);
+        p.non_default("text-case", &part.text_case);
+        el.child(p);
+    }
+    el
+}
+
+fn name_label_el(label: &NameLabelInput) -> XmlElement {
+    let mut el = XmlElement::new("label");
+    el.opt_attr("form", label.form);
+    el.opt_attr("plural", label.plural);
+    el.affixes(&label.affixes);
+    el.formatting(&label.formatting);
+    el.opt_bool("strip-periods", label.strip_periods);
+    el.opt_attr("text-case", label.text_case);
+    el
+}
+
+fn institution_el(institution: &Institution) -> XmlElement {
+    let mut el = XmlElement::new("institution");
+    el.opt_attr("and", institution.and);
+    el.opt_attr("delimiter", institution.delimiter.as_ref().map(|d| &d.0));
+    match institution.use_first {
+        Some(InstitutionUseFirst::Normal(n)) => el.attr("use-first", n.to_string()),
+        Some(InstitutionUseFirst::Substitute(n)) => el.attr("substitute-use-first", n.to_string()),
+        None => {}
+    }
+    el.opt_int("use-last", institution.use_last);
+    el.flag("reverse-order", institution.reverse_order);
+    el.non_default("institution-parts", &institution.parts_selector);
+    for part in &institution.institution_parts {
+        let mut p = XmlElement::new("institution-part");
+        match part.name {
+            InstitutionPartName::Long(if_short) => {
+                p.attr("name", "long");
+                p.flag("if-short", if_short);
+            }
+            InstitutionPartName::Short => p.attr("name", "short"),
+        }
+        p.affixes(&part.affixes);
+        p.formatting(&part.formatting);
+        p.flag("strip-periods", part.strip_periods);
+        el.child(p);
+    }
+    el
+}
+
+fn date_el(date: &BodyDate) -> XmlElement {
+    let mut el = XmlElement::new("date");
+    el.attr("variable", date.variable());
+    match date {
+        BodyDate::Indep(indep) => {
+            el.delimiter("delimiter", &indep.delimiter);
+            el.affixes(&indep.affixes);
+            el.formatting(&indep.formatting);
+            el.display(indep.display);
+            el.non_default("text-case", &indep.text_case);
+            for part in &indep.date_parts {
+                el.child(date_part_el(part));
+            }
+        }
+        BodyDate::Local(local) => {
+            el.attr("form", local.form);
+            el.non_default("date-parts", &local.parts_selector);
+            el.affixes(&local.affixes);
+            el.formatting(&local.formatting);
+            el.display(local.display);
+            el.non_default("text-case", &local.text_case);
+            for part in &local.date_parts {
+                el.child(date_part_el(part));
+            }
+        }
+    }
+    el
+}
+
+fn date_part_el(part: &DatePart) -> XmlElement {
+    let mut el = XmlElement::new("date-part");
+    match part.form {
+        DatePartForm::Day(form) => {
+            el.attr("name", "day");
+            el.non_default("form", &form);
+        }
+        DatePartForm::Month(form, strip_periods) => {
+            el.attr("name", "month");
+            el.non_default("form", &form);
+            el.flag("strip-periods", strip_periods);
+        }
+        DatePartForm::Year(form) => {
+            el.attr("name", "year");
+            el.non_default("form", &form);
+        }
+    }
+    el.affixes(&part.affixes);
+    el.formatting(&part.formatting);
+    el.opt_attr("text-case", part.text_case);
+    el.opt_attr("range-delimiter", part.range_delimiter.as_ref());
+    el
+}
+
+fn locale_el(locale: &Locale) -> XmlElement {
+    let mut el = XmlElement::new("locale");
+    el.attr("version", &locale.version);
+    el.opt_attr("xml:lang", locale.lang.as_ref().map(ToString::to_string));
+
+    let LocaleOptionsNode {
+        limit_day_ordinals_to_day_1,
+        punctuation_in_quote,
+    } = locale.options_node;
+    if limit_day_ordinals_to_day_1.is_some() || punctuation_in_quote.is_some() {
+        let mut options = XmlElement::new("style-options");
+        options.opt_bool("limit-day-ordinals-to-day-1", limit_day_ordinals_to_day_1);
+        options.opt_bool("punctuation-in-quote", punctuation_in_quote);
+        el.child(options);
+    }
+
+    for form in &[DateForm::Text, DateForm::Numeric] {
+        if let Some(date) = locale.dates.get(form) {
+            el.child(locale_date_el(date));
+        }
+    }
+
+    let mut terms = Vec::new();
+    for (sel, content) in &locale.simple_terms {
+        let t = match *sel {
+            SimpleTermSelector::Misc(t, form) => term_el(t.as_ref(), form, content),
+            SimpleTermSelector::Category(t, form) => term_el(t.as_ref(), form, content),
+            SimpleTermSelector::Quote(t) => term_el(t.as_ref(), TermForm::Long, content),
+        };
+        terms.push(t);
+    }
+    for (sel, GenderedTerm(content, gender)) in &locale.gendered_terms {
+        let (name, form) = gendered_name(sel);
+        let mut t = term_el(name, form, content);
+        if *gender != Gender::Neuter {
+            t.attr("gender", gender.as_static());
+        }
+        terms.push(t);
+    }
+    for (OrdinalTermSelector(term, gender), content) in &locale.ordinal_terms {
+        let mut t = XmlElement::new("term");
+        match *term {
+            OrdinalTerm::Mod100(n, m) => {
+                t.attr("name", format!("ordinal-{:02}", n));
+                if m != OrdinalMatch::default_for(n) {
+                    t.attr("match", m.as_static());
+                }
+            }
+            OrdinalTerm::Ordinal => t.attr("name", "ordinal"),
+            long => t.attr("name", format!("long-ordinal-{:02}", long.to_number())),
+        }
+        if *gender != Gender::Neuter {
+            t.attr("gender-form", gender.as_static());
+        }
+        t.text = Some(content.clone()).filter(|s| !s.is_empty());
+        terms.push(t);
+    }
+    for (RoleTermSelector(t, form), content) in &locale.role_terms {
+        terms.push(term_el(t.as_ref(), *form, content));
+    }
+    if !terms.is_empty() {
+        // The term maps are unordered, so sort them for stable output
+        terms.sort_by(|a, b| a.attrs.cmp(&b.attrs));
+        let mut terms_el = XmlElement::new("terms");
+        terms_el.children = terms;
+        el.child(terms_el);
+    }
+    el
+}
+
+fn term_el<F: AsRef<str> + Default + PartialEq>(
+    name: &str,
+    form: F,
+    content: &TermPlurality,
+) -> XmlElement {
+    let mut el = XmlElement::new("term");
+    el.attr("name", name);
+    el.non_default("form", &form);
+    match content {
+        TermPlurality::Invariant(s) => {
+            el.text = Some(s.clone()).filter(|s| !s.is_empty());
+        }
+        TermPlurality::Pluralized { single, multiple } => {
+            for (tag, text) in &[("single", single), ("multiple", multiple)] {
+                let mut e = XmlElement::new(*tag);
+                e.text = Some(text.to_string()).filter(|s| !s.is_empty());
+                el.child(e);
+            }
+        }
+    }
+    el
+}
+
+fn locale_date_el(date: &LocaleDate) -> XmlElement {
+    let mut el = XmlElement::new("date");
+    el.attr("form", date.form);
+    el.delimiter("delimiter", &date.delimiter);
+    el.formatting(&date.formatting);
+    el.non_default("text-case", &date.text_case);
+    for part in &date.date_parts {
+        el.child(date_part_el(part));
+    }
+    el
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::str::FromStr;
+
+    fn round_trip(xml: &str) {
+        let style = Style::from_str(xml).unwrap();
+        let written = style.to_xml();
+        let reparsed = Style::from_str(&written)
+            .unwrap_or_else(|e| panic!("could not reparse:\n{}\n{:?}", written, e));
+        assert_eq!(style, reparsed, "\n{}", written);
+    }
+
+    #[test]
+    fn style_round_trips() {
+        round_trip(
+            r#"<style xmlns="http://purl.org/net/xbiblio/csl" class="in-text" version="1.0"
+                  default-locale="en-GB" page-range-format="chicago" et-al-min="4"
+                  et-al-use-first="1" name-form="short" initialize-with-hyphen="false">
+              <info>
+                <title>Example &amp; Style</title>
+                <title-short>ES</title-short>
+                <id>http://example.com/style</id>
+                <link href="http://example.com/style" rel="self"/>
+                <link href="http://example.com/parent" rel="independent-parent"/>
+                <author><name>A. Author</name><email>a@example.com</email></author>
+                <contributor><name>C. Contributor</name><uri>http://example.com</uri></contributor>
+                <category citation-format="author-date"/>
+                <category field="history"/>
+                <issn>1234-5678</issn>
+                <issn>2345-6789</issn>
+                <eissn>3456-7890</eissn>
+                <summary>For testing</summary>
+                <updated>2019-10-18T00:00:00+00:00</updated>
+                <rights license="http://creativecommons.org/licenses/by-sa/3.0/">CC BY-SA</rights>
+              </info>
+              <locale xml:lang="en">
+                <style-options punctuation-in-quote="false"/>
+                <terms>
+                  <term name="editor" form="short">
+                    <single>ed.</single>
+                    <multiple>eds.</multiple>
+                  </term>
+                  <term name="ordinal-11" match="whole-number">th</term>
+                  <term name="page" gender="feminine">p. &amp; more</term>
+                </terms>
+                <date form="numeric" delimiter="/">
+                  <date-part name="day" form="numeric-leading-zeros"/>
+                  <date-part name="month" form="numeric"/>
+                </date>
+              </locale>
+              <macro name="author">
+                <names variable="author editor" delimiter="; ">
+                  <name and="symbol" delimiter-precedes-last="never" initialize-with=". ">
+                    <name-part name="family" text-case="uppercase" font-variant="small-caps"/>
+                  </name>
+                  <label form="short" prefix=" (" suffix=")" strip-periods="true"/>
+                  <et-al term="and others" font-style="italic"/>
+                  <substitute>
+                    <names variable="translator"/>
+                    <text variable="title" form="short" quotes="true"/>
+                  </substitute>
+                </names>
+              </macro>
+              <macro name="issued">
+                <choose>
+                  <if type="book chapter" variable="issued" match="all">
+                    <date variable="issued" form="text" date-parts="year-month"/>
+                  </if>
+                  <else-if position="ibid-with-locator" locator="sub-verbo">
+                    <text term="ibid" text-case="capitalize-first"/>
+                  </else-if>
+                  <else-if is-numeric="volume" match="none">
+                    <date variable="issued" delimiter="-" prefix="[" suffix="]">
+                      <date-part name="year" form="short" range-delimiter="/"/>
+                      <date-part name="month" form="short" strip-periods="true"/>
+                    </date>
+                  </else-if>
+                  <else>
+                    <text term="no date" form="short"/>
+                  </else>
+                </choose>
+              </macro>
+              <citation disambiguate-add-year-suffix="true" givenname-disambiguation-rule="primary-name">
+                <layout prefix="(" suffix=")" delimiter="; ">
+                  <group delimiter=", ">
+                    <text macro="author"/>
+                    <text macro="issued"/>
+                    <group>
+                      <label variable="locator" form="short" plural="never"/>
+                      <number variable="locator" form="ordinal"/>
+                    </group>
+                    <text value="&lt;tab&#9;&quot;quoted&quot;&gt;" vertical-align="sup"/>
+                  </group>
+                </layout>
+              </citation>
+              <bibliography hanging-indent="true" entry-spacing="0"
+                            subsequent-author-substitute="———" names-delimiter=". ">
+                <sort>
+                  <key macro="author" names-min="3" names-use-first="1"/>
+                  <key variable="issued" sort="descending"/>
+                </sort>
+                <layout suffix=".">
+                  <text macro="author" display="block"/>
+                  <text variable="URL" prefix=" "/>
+                </layout>
+              </bibliography>
+            </style>"#,
+        );
+    }
+
+    #[test]
+    fn real_styles_round_trip() {
+        round_trip(include_str!("../../proc/tests/data/apa.csl"));
+        round_trip(include_str!("../../proc/tests/data/aglc.csl"));
+        round_trip(include_str!(
+            "../../citeproc/tests/data/styles/modern-humanities-research-association.csl"
+        ));
+        round_trip(include_str!("../../citeproc/tests/data/styles/year-suffix.csl"));
+        round_trip(include_str!(
+            "../../citeproc/benches/data/australian-guide-to-legal-citation.csl"
+        ));
+    }
+
+    #[test]
+    fn locale_round_trips() {
+        let locale = Locale::from_str(crate::locale::EN_US).unwrap();
+        let written = locale.to_xml();
+        assert_eq!(Locale::from_str(&written).unwrap(), locale);
+    }
+
+    #[test]
+    fn pretty_prints() {
+        let style = Style::from_str(
+            r#"<style class="note" version="1.0"><citation><layout><text value="a"/></layout></citation></style>"#,
+        )
+        .unwrap();
+        assert_eq!(
+            style.to_xml(),
+            r#"<?xml version="1.0" encoding="utf-8"?>
+<style xmlns="http://purl.org/net/xbiblio/csl" class="note" version="1.0" default-locale="en-US">
+  <citation>
+    <layout>
+      <text value="a"/>
+    </layout>
+  </citation>
+</style>
+"#
+        );
+    }
+}
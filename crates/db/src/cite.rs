@@ -13,26 +13,38 @@ use std::collections::HashSet;
 use std::sync::Arc;
 
 use citeproc_io::output::markup::Markup;
-use citeproc_io::{Cite, ClusterId, ClusterNumber, Reference};
-use csl::Atom;
+use citeproc_io::{BibliographyFilter, Cite, ClusterId, ClusterNumber, Reference};
+use csl::{AnyVariable, Atom, NumberVariable};
 
 #[salsa::query_group(CiteDatabaseStorage)]
 pub trait CiteDatabase: LocaleDatabase + StyleDatabase {
     #[salsa::input]
     fn reference_input(&self, key: Atom) -> Arc<Reference>;
     fn reference(&self, key: Atom) -> Option<Arc<Reference>>;
+    /// The variables the style would render for a reference, but which it doesn't have, sorted
+    /// by name. Empty if the reference isn't in the library. See `Style::missing_variables`.
+    fn missing_variables(&self, key: Atom) -> Arc<Vec<AnyVariable>>;
 
     #[salsa::input]
     fn all_keys(&self) -> Arc<HashSet<Atom>>;
 
     #[salsa::input]
     fn all_uncited(&self) -> Arc<HashSet<Atom>>;
+    /// Puts every reference in the library in the bibliography, like pandoc's `nocite: @*`.
+    #[salsa::input]
+    fn nocite_all(&self) -> bool;
     /// Filters out keys not in the library
     fn uncited(&self) -> Arc<HashSet<Atom>>;
 
     /// Filters out keys not in the library
     fn cited_keys(&self) -> Arc<HashSet<Atom>>;
 
+    /// The names of the extra bibliographies, each made by filtering the main one.
+    #[salsa::input]
+    fn bibliography_names(&self) -> Arc<Vec<Atom>>;
+    #[salsa::input]
+    fn bibliography_filter(&self, name: Atom) -> Arc<BibliographyFilter>;
+
     /// Equal to `all.intersection(cited U uncited)`
     /// Also represents "the refs that will be in the bibliography if we generate one"
     fn disamb_participants(&self) -> Arc<HashSet<Atom>>;
@@ -46,9 +58,24 @@ pub trait CiteDatabase: LocaleDatabase + StyleDatabase {
     #[salsa::input]
     fn cluster_note_number(&self, key: ClusterId) -> Option<ClusterNumber>;
 
+    /// The clusters that begin a new section of the document, like a chapter. Positions and
+    /// citation numbers start again in each section.
+    #[salsa::input]
+    fn section_starts(&self) -> Arc<Vec<ClusterId>>;
+
+    /// Which section a cluster is in. The clusters before the first section start are in section
+    /// 0, and each section start begins the next one, unless it is the first cluster, which is
+    /// always in section 0. Clusters not in the document are in none.
+    fn cluster_section(&self, key: ClusterId) -> Option<u32>;
+    /// `cluster_section` for every cluster in the document at once.
+    fn cluster_sections(&self) -> Arc<FnvHashMap<ClusterId, u32>>;
+
     // All cite ids, in the order they appear in the document
     fn all_cite_ids(&self) -> Arc<Vec<CiteId>>;
 
+    /// The cite ids referring to each reference, in the order they appear in the document
+    fn cites_by_ref(&self) -> Arc<FnvHashMap<Atom, Vec<CiteId>>>;
+
     fn cite_positions(&self) -> Arc<FnvHashMap<CiteId, (Position, Option<u32>)>>;
 
     /// The first element is a [`Position`]; first, ibid, subsequent, etc
@@ -96,6 +123,7 @@ impl CiteId {
 pub struct ClusterData {
     pub id: ClusterId,
     pub number: ClusterNumber,
+    pub section: u32,
     pub cites: Arc<Vec<CiteId>>,
 }
 
@@ -107,6 +135,30 @@ fn reference(db: &impl CiteDatabase, key: Atom) -> Option<Arc<Reference>> {
     }
 }
 
+fn missing_variables(db: &impl CiteDatabase, key: Atom) -> Arc<Vec<AnyVariable>> {
+    let refr = match db.reference(key) {
+        Some(refr) => refr,
+        None => return Arc::new(Vec::new()),
+    };
+    let has_variable = |var: AnyVariable| match var {
+        // Derived from page when rendering
+        AnyVariable::Number(NumberVariable::PageFirst) => {
+            refr.number.contains_key(&NumberVariable::Page)
+        }
+        AnyVariable::Ordinary(v) => refr.ordinary.contains_key(&v),
+        AnyVariable::Number(v) => refr.number.contains_key(&v),
+        AnyVariable::Name(v) => refr.name.contains_key(&v),
+        AnyVariable::Date(v) => refr.date.contains_key(&v),
+    };
+    let mut missing: Vec<AnyVariable> = db
+        .style()
+        .missing_variables(refr.csl_type, &has_variable)
+        .into_iter()
+        .collect();
+    missing.sort_by(|a, b| a.as_ref().cmp(b.as_ref()));
+    Arc::new(missing)
+}
+
 fn locale_by_cite(db: &impl CiteDatabase, id: CiteId) -> Arc<Locale> {
     let cite = id.lookup(db);
     db.locale_by_reference(cite.ref_id.clone())
@@ -122,6 +174,9 @@ fn locale_by_reference(db: &impl CiteDatabase, ref_id: Atom) -> Arc<Locale> {
 // make sure there are no keys we wouldn't recognise
 fn uncited(db: &impl CiteDatabase) -> Arc<HashSet<Atom>> {
     let all = db.all_keys();
+    if db.nocite_all() {
+        return all;
+    }
     let uncited = db.all_uncited();
     let merged = all.intersection(&uncited).cloned().collect();
     Arc::new(merged)
@@ -177,6 +232,16 @@ fn all_cite_ids(db: &impl CiteDatabase) -> Arc<Vec<CiteId>> {
     Arc::new(ids)
 }
 
+fn cites_by_ref(db: &impl CiteDatabase) -> Arc<FnvHashMap<Atom, Vec<CiteId>>> {
+    let mut map: FnvHashMap<Atom, Vec<CiteId>> = FnvHashMap::default();
+    for &id in db.all_cite_ids().iter() {
+        map.entry(id.lookup(db).ref_id.clone())
+            .or_insert_with(Vec::new)
+            .push(id);
+    }
+    Arc::new(map)
+}
+
 fn clusters_sorted(db: &impl CiteDatabase) -> Arc<Vec<ClusterData>> {
     let cluster_ids = db.cluster_ids();
     let mut clusters: Vec<_> = cluster_ids
@@ -187,13 +252,37 @@ fn clusters_sorted(db: &impl CiteDatabase) -> Arc<Vec<ClusterData>> {
         .map(|(id, number)| ClusterData {
             id,
             number,
+            section: 0,
             cites: db.cluster_cites(id),
         })
         .collect();
     clusters.sort_by_key(|cluster| cluster.number);
+    let section_starts = db.section_starts();
+    let mut section = 0;
+    for (i, cluster) in clusters.iter_mut().enumerate() {
+        // Starting a section at the first cluster leaves nothing before it to be section 0.
+        if i > 0 && section_starts.contains(&cluster.id) {
+            section += 1;
+        }
+        cluster.section = section;
+    }
     Arc::new(clusters)
 }
 
+fn cluster_section(db: &impl CiteDatabase, key: ClusterId) -> Option<u32> {
+    db.cluster_sections().get(&key).cloned()
+}
+
+fn cluster_sections(db: &impl CiteDatabase) -> Arc<FnvHashMap<ClusterId, u32>> {
+    let clusters = db.clusters_sorted();
+    Arc::new(
+        clusters
+            .iter()
+            .map(|cluster| (cluster.id, cluster.section))
+            .collect(),
+    )
+}
+
 // See https://github.com/jgm/pandoc-citeproc/blob/e36c73ac45c54dec381920e92b199787601713d1/src/Text/CSL/Reference.hs#L910
 fn cite_positions(db: &impl CiteDatabase) -> Arc<FnvHashMap<CiteId, (Position, Option<u32>)>> {
     let clusters = db.clusters_sorted();
@@ -210,6 +299,11 @@ fn cite_positions(db: &impl CiteDatabase) -> Arc<FnvHashMap<CiteId, (Position, O
     let mut first_seen: FnvHashMap<Atom, ClusterNumber> = FnvHashMap::default();
 
     for (i, cluster) in clusters.iter().enumerate() {
+        // Every section starts from scratch, as if it were its own document
+        let prev_section = clusters.get(i.wrapping_sub(1)).map(|c| c.section);
+        if prev_section.map_or(false, |s| s != cluster.section) {
+            first_seen.clear();
+        }
         let in_text = match cluster.number {
             ClusterNumber::InText(n) => Some(n),
             _ => None,
@@ -233,6 +327,7 @@ fn cite_positions(db: &impl CiteDatabase) -> Arc<FnvHashMap<CiteId, (Position, O
                             _ => false,
                         };
                         if prev_in_text == in_text.is_some()
+                            && prev_cluster.section == cluster.section
                             && prev_cluster.cites.len() > 0
                             && prev_cluster
                                 .cites
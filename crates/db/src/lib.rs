@@ -13,6 +13,9 @@ pub fn safe_default(db: &mut (impl cite::CiteDatabase + xml::LocaleDatabase + xm
     db.set_style(Default::default());
     db.set_all_keys(Default::default());
     db.set_all_uncited(Default::default());
+    db.set_nocite_all(false);
+    db.set_bibliography_names(Default::default());
     db.set_cluster_ids(Arc::new(vec![]));
+    db.set_section_starts(Arc::new(vec![]));
     db.set_locale_input_langs(Default::default());
 }
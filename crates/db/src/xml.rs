@@ -9,8 +9,10 @@ use std::str::FromStr;
 use std::sync::Arc;
 
 use csl::{
+    introspect::StyleUsage,
     locale::{Lang, Locale, LocaleOptions, LocaleSource, EN_US},
-    style::{Delimiter, Name, Style, TextElement, TextSource},
+    style::{CslType, Delimiter, Name, Style, TextElement, TextSource},
+    variables::AnyVariable,
 };
 use fnv::FnvHashSet;
 
@@ -33,6 +35,11 @@ pub trait StyleDatabase {
 
     /// Lists every <names> block in the style, with each name variable it is used for
     fn name_configurations(&self) -> Arc<Vec<(NameVariable, Name)>>;
+
+    /// The variables, terms and unused macros of the style. See `Style::usage`.
+    fn style_usage(&self) -> Arc<StyleUsage>;
+    /// The variables the style can render for an item type. See `Style::variables_for_type`.
+    fn variables_for_type(&self, csl_type: CslType) -> Arc<FnvHashSet<AnyVariable>>;
 }
 
 fn name_info_citation(db: &impl StyleDatabase) -> (Option<Delimiter>, Arc<Name>) {
@@ -135,6 +142,14 @@ fn name_configurations(db: &impl StyleDatabase) -> Arc<Vec<(NameVariable, Name)>
     Arc::new(name_configurations_middle(&style))
 }
 
+fn style_usage(db: &impl StyleDatabase) -> Arc<StyleUsage> {
+    Arc::new(db.style().usage())
+}
+
+fn variables_for_type(db: &impl StyleDatabase, csl_type: CslType) -> Arc<FnvHashSet<AnyVariable>> {
+    Arc::new(db.style().variables_for_type(csl_type))
+}
+
 #[test]
 fn test_name_configurations() {
     let sty = style_xml!(
@@ -182,6 +197,9 @@ pub trait LocaleDatabase: salsa::Database + StyleDatabase + HasFetcher {
     /// Backed by the LocaleFetcher implementation
     fn locale_xml(&self, key: Lang) -> Option<Arc<String>>;
 
+    /// Parses the locale file for exactly `key`, without any fallbacks
+    fn locale_file(&self, key: Lang) -> Result<Arc<Locale>, LocaleFileError>;
+
     /// Derived from a `Style`
     fn inline_locale(&self, key: Option<Lang>) -> Option<Arc<Locale>>;
 
@@ -219,24 +237,33 @@ fn locale_xml(db: &impl LocaleDatabase, key: Lang) -> Option<Arc<String>> {
     }
 }
 
+/// Why `LocaleDatabase::locale_file` has no locale.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LocaleFileError {
+    /// Neither stored nor available from the fetcher
+    NotFound,
+    /// Found, but could not be parsed; holds the parse error
+    Invalid(String),
+}
+
+fn locale_file(db: &impl LocaleDatabase, key: Lang) -> Result<Arc<Locale>, LocaleFileError> {
+    let string = db.locale_xml(key.clone()).ok_or(LocaleFileError::NotFound)?;
+    match Locale::from_str(&string) {
+        Ok(l) => Ok(Arc::new(l)),
+        Err(e) => {
+            error!("failed to parse locale for lang {}: {:?}", key, e);
+            Err(LocaleFileError::Invalid(format!("{:?}", e)))
+        }
+    }
+}
+
 fn inline_locale(db: &impl LocaleDatabase, key: Option<Lang>) -> Option<Arc<Locale>> {
     db.style().locale_overrides.get(&key).cloned().map(Arc::new)
 }
 
 fn locale(db: &impl LocaleDatabase, key: LocaleSource) -> Option<Arc<Locale>> {
     match key {
-        LocaleSource::File(ref lang) => {
-            let string = db.locale_xml(lang.clone());
-            string
-                .and_then(|s| match Locale::from_str(&s) {
-                    Ok(l) => Some(l),
-                    Err(e) => {
-                        error!("failed to parse locale for lang {}: {:?}", lang, e);
-                        None
-                    }
-                })
-                .map(Arc::new)
-        }
+        LocaleSource::File(ref lang) => db.locale_file(lang.clone()).ok(),
         LocaleSource::Inline(ref lang) => db.inline_locale(lang.clone()),
     }
 }
@@ -331,3 +358,45 @@ impl LocaleFetcher for PredefinedLocales {
         Ok(self.0.get(lang).cloned())
     }
 }
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "parallel")] {
+        /// Supplies the independent parents of dependent styles, by style id.
+        pub trait StyleFetcher: Send + Sync {
+            /// Style ids are usually URLs like `http://www.zotero.org/styles/apa`.
+            fn fetch_style(&self, id: &str) -> Result<Option<String>, StyleFetchError>;
+        }
+    } else {
+        /// Supplies the independent parents of dependent styles, by style id.
+        pub trait StyleFetcher {
+            /// Style ids are usually URLs like `http://www.zotero.org/styles/apa`.
+            fn fetch_style(&self, id: &str) -> Result<Option<String>, StyleFetchError>;
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum StyleFetchError {
+    Io(io::Error),
+    Other(String),
+}
+
+impl From<String> for StyleFetchError {
+    fn from(err: String) -> StyleFetchError {
+        StyleFetchError::Other(err)
+    }
+}
+
+impl From<io::Error> for StyleFetchError {
+    fn from(err: io::Error) -> StyleFetchError {
+        StyleFetchError::Io(err)
+    }
+}
+
+pub struct PredefinedStyles(pub HashMap<String, String>);
+
+impl StyleFetcher for PredefinedStyles {
+    fn fetch_style(&self, id: &str) -> Result<Option<String>, StyleFetchError> {
+        Ok(self.0.get(id).cloned())
+    }
+}
@@ -11,7 +11,7 @@ use csl::LocatorType;
 
 pub type ClusterId = u32;
 
-#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Deserialize)]
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
 pub enum Suppression {
     // For author-in-text, or whatever the style author wants to put inline.
     //
@@ -24,10 +24,52 @@ pub enum Suppression {
 }
 
 #[derive(Clone, Eq, PartialEq, Hash, Debug, Deserialize)]
+#[serde(from = "RawLocator")]
 pub struct Locator {
     pub locator: NumericValue,
-    #[serde(default, rename = "label")]
     pub loc_type: LocatorType,
+    /// A label that isn't a locator type. `loc_type` is then the default, page.
+    pub unknown_label: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RawLocator {
+    locator: NumericValue,
+    #[serde(default)]
+    label: Option<String>,
+}
+
+impl Serialize for Locator {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let label = match &self.unknown_label {
+            Some(label) => label.as_str(),
+            None => self.loc_type.as_ref(),
+        };
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry("locator", &self.locator)?;
+        map.serialize_entry("label", label)?;
+        map.end()
+    }
+}
+
+impl From<RawLocator> for Locator {
+    fn from(raw: RawLocator) -> Self {
+        use std::str::FromStr;
+        let parsed = raw.label.as_ref().map(|l| LocatorType::from_str(l));
+        let (loc_type, unknown_label) = match parsed {
+            None => (LocatorType::default(), None),
+            Some(Ok(loc_type)) => (loc_type, None),
+            Some(Err(_)) => (LocatorType::default(), raw.label),
+        };
+        Locator {
+            locator: raw.locator,
+            loc_type,
+            unknown_label,
+        }
+    }
 }
 
 impl Locator {
@@ -40,6 +82,7 @@ impl Locator {
 }
 
 use serde::de::{Deserialize, Deserializer};
+use serde::ser::{Serialize, SerializeMap, Serializer};
 
 /// Techincally reference IDs are allowed to be numbers.
 pub fn get_ref_id<'de, D>(d: D) -> Result<Atom, D::Error>
@@ -54,8 +97,8 @@ where
 /// Represents one cite in someone's document, to exactly one reference.
 ///
 /// Prefixes and suffixes
-#[derive(Debug, Clone, Deserialize)]
-#[serde(rename_all = "camelCase", bound(deserialize = ""))]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", bound(serialize = "", deserialize = ""))]
 pub struct Cite<O: OutputFormat> {
     #[serde(rename = "id", deserialize_with = "get_ref_id")]
     pub ref_id: Atom,
@@ -77,7 +120,7 @@ pub struct Cite<O: OutputFormat> {
 /// Accepts either
 /// `{ "locator": "54", "label": "page" }` or
 /// `{ "locators": [["chapter", "19"], ["page", "581"]] }`.
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 #[serde(untagged)]
 pub enum Locators {
     Single(Locator),
@@ -151,7 +194,7 @@ impl<O: OutputFormat> Cite<O> {
     }
 }
 
-#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Ord)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Ord)]
 #[serde(untagged)]
 pub enum IntraNote {
     Single(u32),
@@ -184,7 +227,7 @@ impl PartialOrd for IntraNote {
     }
 }
 
-#[derive(Deserialize, Ord, Eq, PartialEq)]
+#[derive(Serialize, Deserialize, Ord, Eq, PartialEq)]
 #[serde(rename_all = "camelCase")]
 #[derive(Clone, Copy, Debug)]
 pub enum ClusterNumber {
@@ -245,8 +288,8 @@ impl PartialOrd for ClusterNumber {
 ///
 /// Similarly, it is up to a library consumer to make sure no clusters have the same number as any
 /// other.
-#[derive(Debug, Clone, Deserialize, PartialEq)]
-#[serde(bound(deserialize = ""))]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(bound(serialize = "", deserialize = ""))]
 pub struct Cluster<O: OutputFormat> {
     pub id: ClusterId,
     pub cites: Vec<Cite<O>>,
@@ -4,12 +4,13 @@
 //
 // Copyright © 2018 Corporation for Digital Scholarship
 
-// We implement serde::de::Deserialize for CSL-JSON spec for now.
+// We implement serde::de::Deserialize for CSL-JSON spec for now, and Serialize back into it.
 // If you want to add a new input format, you can write one
 // e.g. with a bibtex parser https://github.com/charlesvdv/nom-bibtex
 
 use serde::de::Error;
 use serde::de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{Serialize, SerializeMap, SerializeSeq, Serializer};
 use std::borrow::Cow;
 use std::collections::hash_map::Entry;
 use std::fmt;
@@ -28,7 +29,7 @@ use csl::Lang;
 
 use super::date::{Date, DateOrRange};
 use super::numeric::NumericValue;
-use super::reference::Reference;
+use super::reference::{Reference, ReferencePatch};
 use fnv::FnvHashMap;
 use std::marker::PhantomData;
 
@@ -244,6 +245,61 @@ impl<'de> Deserialize<'de> for Reference {
     }
 }
 
+impl<'de> Deserialize<'de> for ReferencePatch {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct PatchVisitor;
+
+        impl<'de> Visitor<'de> for PatchVisitor {
+            type Value = ReferencePatch;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("struct ReferencePatch")
+            }
+
+            fn visit_map<V>(self, mut map: V) -> Result<Self::Value, V::Error>
+            where
+                V: MapAccess<'de>,
+            {
+                let mut patch = ReferencePatch::default();
+                while let Some(key) = map.next_key()? {
+                    match key {
+                        Field::Id => {
+                            return Err(de::Error::custom("a patch cannot change a reference's id"));
+                        }
+                        Field::Type => {
+                            let WrapType(csl_type) = map.next_value()?;
+                            patch.csl_type = Some(csl_type);
+                        }
+                        Field::Language => {
+                            let language: Option<WrapLang> = map.next_value()?;
+                            patch.language = Some(language.map(|WrapLang(l)| l));
+                        }
+                        Field::Any(WrapVar(AnyVariable::Ordinary(v))) => {
+                            patch.ordinary.insert(v, map.next_value()?);
+                        }
+                        Field::Any(WrapVar(AnyVariable::Number(v))) => {
+                            patch.number.insert(v, map.next_value()?);
+                        }
+                        Field::Any(WrapVar(AnyVariable::Name(v))) => {
+                            patch.name.insert(v, map.next_value()?);
+                        }
+                        Field::Any(WrapVar(AnyVariable::Date(v))) => {
+                            patch.date.insert(v, map.next_value()?);
+                        }
+                    }
+                }
+                Ok(patch)
+            }
+        }
+
+        const FIELDS: &[&str] = &["type", "any variable name"];
+        deserializer.deserialize_struct("ReferencePatch", FIELDS, PatchVisitor)
+    }
+}
+
 impl<'de> Deserialize<'de> for NumericValue {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -644,3 +700,89 @@ impl<'de> Deserialize<'de> for DateOrRange {
         deserializer.deserialize_struct("DateOrRange", DATE_TYPES, DateVisitor)
     }
 }
+
+// Serializing produces CSL-JSON that deserializes back into an equal value.
+
+impl Serialize for Reference {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("id", &*self.id)?;
+        map.serialize_entry("type", self.csl_type.as_ref())?;
+        if let Some(lang) = &self.language {
+            map.serialize_entry("language", &lang.to_string())?;
+        }
+        for (var, value) in &self.ordinary {
+            map.serialize_entry(var.as_ref(), value)?;
+        }
+        for (var, value) in &self.number {
+            map.serialize_entry(var.as_ref(), value)?;
+        }
+        for (var, value) in &self.name {
+            map.serialize_entry(var.as_ref(), value)?;
+        }
+        for (var, value) in &self.date {
+            map.serialize_entry(var.as_ref(), value)?;
+        }
+        map.end()
+    }
+}
+
+impl Serialize for NumericValue {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.verbatim())
+    }
+}
+
+struct SerializeDate<'a>(&'a Date);
+
+impl Serialize for SerializeDate<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let date = self.0;
+        let len = if date.has_day() {
+            3
+        } else if date.has_month() {
+            2
+        } else {
+            1
+        };
+        let mut seq = serializer.serialize_seq(Some(len))?;
+        seq.serialize_element(&date.year)?;
+        if len > 1 {
+            seq.serialize_element(&date.month)?;
+        }
+        if len > 2 {
+            seq.serialize_element(&date.day)?;
+        }
+        seq.end()
+    }
+}
+
+impl Serialize for DateOrRange {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(Some(1))?;
+        match self {
+            DateOrRange::Single(date) => {
+                map.serialize_entry("date-parts", &[SerializeDate(date)])?;
+            }
+            DateOrRange::Range(from, to) => {
+                map.serialize_entry("date-parts", &[SerializeDate(from), SerializeDate(to)])?;
+            }
+            DateOrRange::Literal(lit) => {
+                map.serialize_entry("literal", lit)?;
+            }
+        }
+        map.end()
+    }
+}
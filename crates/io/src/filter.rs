@@ -0,0 +1,133 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright © 2019 Corporation for Digital Scholarship
+
+//! Filters for splitting a bibliography into several, modelled on citeproc-js'
+//! `select`/`include`/`exclude`/`quash`.
+
+use crate::Reference;
+use csl::{NumberVariable, Variable};
+use std::str::FromStr;
+
+/// A `field` of a reference that should have a particular `value`.
+///
+/// `field` is `"type"`, `"id"` or the name of a CSL variable. `"keyword"` matches any one of the
+/// comma- or semicolon-separated keywords in the variable.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct FieldMatch {
+    pub field: String,
+    pub value: String,
+}
+
+impl FieldMatch {
+    pub fn new(field: &str, value: &str) -> Self {
+        FieldMatch {
+            field: field.to_owned(),
+            value: value.to_owned(),
+        }
+    }
+
+    pub fn matches(&self, refr: &Reference) -> bool {
+        let value = self.value.as_str();
+        match self.field.as_str() {
+            "type" => refr.csl_type.as_ref() == value,
+            "id" => &*refr.id == value,
+            field => {
+                if let Ok(var) = Variable::from_str(field) {
+                    refr.ordinary.get(&var).map_or(false, |v| {
+                        if var == Variable::Keyword {
+                            v.split(|c| c == ',' || c == ';')
+                                .any(|kw| kw.trim() == value)
+                        } else {
+                            v == value
+                        }
+                    })
+                } else if let Ok(var) = NumberVariable::from_str(field) {
+                    refr.number
+                        .get(&var)
+                        .map_or(false, |v| v.verbatim() == value)
+                } else {
+                    false
+                }
+            }
+        }
+    }
+}
+
+/// Decides which references go in a bibliography. A reference has to pass every list that is
+/// not empty.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BibliographyFilter {
+    /// Keeps references that match all of these.
+    pub select: Vec<FieldMatch>,
+    /// Keeps references that match any of these.
+    pub include: Vec<FieldMatch>,
+    /// Drops references that match any of these.
+    pub exclude: Vec<FieldMatch>,
+    /// Drops references that match all of these.
+    pub quash: Vec<FieldMatch>,
+}
+
+impl BibliographyFilter {
+    pub fn accepts(&self, refr: &Reference) -> bool {
+        let matches = |m: &FieldMatch| m.matches(refr);
+        (self.select.is_empty() || self.select.iter().all(matches))
+            && (self.include.is_empty() || self.include.iter().any(matches))
+            && !self.exclude.iter().any(matches)
+            && (self.quash.is_empty() || !self.quash.iter().all(matches))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use csl::CslType;
+
+    fn book(keywords: &str) -> Reference {
+        let mut refr = Reference::empty("a".into(), CslType::Book);
+        refr.ordinary.insert(Variable::Keyword, keywords.into());
+        refr
+    }
+
+    #[test]
+    fn filter_lists() {
+        let refr = book("primary; archive");
+        let filter = |f: BibliographyFilter| f.accepts(&refr);
+        assert!(filter(BibliographyFilter::default()));
+        assert!(filter(BibliographyFilter {
+            select: vec![
+                FieldMatch::new("type", "book"),
+                FieldMatch::new("keyword", "primary")
+            ],
+            ..Default::default()
+        }));
+        assert!(!filter(BibliographyFilter {
+            select: vec![
+                FieldMatch::new("type", "book"),
+                FieldMatch::new("keyword", "secondary")
+            ],
+            ..Default::default()
+        }));
+        assert!(filter(BibliographyFilter {
+            include: vec![
+                FieldMatch::new("type", "chapter"),
+                FieldMatch::new("keyword", "archive")
+            ],
+            ..Default::default()
+        }));
+        assert!(!filter(BibliographyFilter {
+            exclude: vec![FieldMatch::new("keyword", "archive")],
+            ..Default::default()
+        }));
+        assert!(filter(BibliographyFilter {
+            quash: vec![
+                FieldMatch::new("type", "book"),
+                FieldMatch::new("keyword", "secondary")
+            ],
+            ..Default::default()
+        }));
+    }
+}
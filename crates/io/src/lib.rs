@@ -24,6 +24,7 @@ extern crate log;
 mod cite;
 mod csl_json;
 mod date;
+mod filter;
 mod names;
 mod numeric;
 pub mod output;
@@ -35,6 +36,7 @@ pub use csl_json::IdOrNumber;
 
 pub use self::cite::*;
 pub use self::date::*;
+pub use self::filter::*;
 pub use self::names::*;
 pub use self::numeric::*;
 pub use self::reference::*;
@@ -57,6 +59,8 @@ pub struct IngestOptions {
     pub quotes: LocalizedQuotes,
     pub strip_periods: bool,
     pub is_english: bool,
+    /// Link any bare URLs found in the input, if the output format is linking URLs.
+    pub link_bare_urls: bool,
 }
 
 /// https://stackoverflow.com/a/38406885
@@ -489,6 +493,15 @@ impl IngestOptions {
                 | InlineElement::Div(_, content)
                 | InlineElement::Anchor {
                     content, ..
+                }
+                | InlineElement::Cite {
+                    inlines: content, ..
+                }
+                | InlineElement::BibEntry {
+                    inlines: content, ..
+                }
+                | InlineElement::Microdata {
+                    inlines: content, ..
                 } => {
                     seen_one = self.apply_text_case_inner(content.as_mut(), seen_one, is_uppercase)
                         || seen_one;
@@ -505,6 +518,7 @@ impl IngestOptions {
                 InlineElement::Formatted(content, _) => {
                     seen_one = seen_one || self.contains_word(content.as_ref());
                 }
+                InlineElement::Coins(_) => {}
             }
         }
         seen_one
@@ -600,12 +614,16 @@ fn any_lowercase(s: &str) -> bool {
 fn any_inlines<F: Fn(&str) -> bool + Copy>(f: F, invert: bool, inlines: &[InlineElement]) -> bool {
     inlines.iter().any(|i| match i {
         InlineElement::Text(txt) => f(txt.as_ref()),
+        InlineElement::Coins(_) => false,
         InlineElement::Micro(micros) => any_micros(f, invert, micros.as_ref()),
         InlineElement::Quoted { inlines, .. }
         | InlineElement::Div(_, inlines)
         | InlineElement::Anchor {
             content: inlines, ..
         }
+        | InlineElement::Cite { inlines, .. }
+        | InlineElement::BibEntry { inlines, .. }
+        | InlineElement::Microdata { inlines, .. }
         | InlineElement::Formatted(inlines, _) => any_inlines(f, invert, inlines.as_ref()) ^ invert,
     }) ^ invert
 }
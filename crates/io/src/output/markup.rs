@@ -8,13 +8,13 @@ use self::InlineElement::*;
 use super::micro_html::MicroNode;
 use super::{FormatCmd, LocalizedQuotes, OutputFormat};
 use crate::utils::JoinMany;
-use crate::IngestOptions;
+use crate::{IngestOptions, Reference};
 use csl::{
     DisplayMode, FontStyle, FontVariant, FontWeight, Formatting, TextDecoration, VerticalAlignment, TextCase,
 };
 
 mod rtf;
-use self::rtf::RtfWriter;
+use self::rtf::{RtfOptions, RtfWriter};
 
 mod html;
 use self::html::{HtmlOptions, HtmlWriter};
@@ -22,6 +22,12 @@ use self::html::{HtmlOptions, HtmlWriter};
 mod plain;
 use self::plain::PlainWriter;
 
+mod ansi;
+use self::ansi::AnsiWriter;
+
+mod metadata;
+pub use self::metadata::HtmlMetadata;
+
 mod flip_flop;
 use self::flip_flop::FlipFlopState;
 mod move_punctuation;
@@ -33,8 +39,10 @@ pub(self) mod puncttable;
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Markup {
     Html(HtmlOptions),
-    Rtf,
+    Rtf(RtfOptions),
     Plain,
+    /// For previews in a terminal.
+    Ansi,
 }
 
 /// TODO: serialize and deserialize using an HTML parser?
@@ -58,21 +66,144 @@ pub enum InlineElement {
         content: Vec<InlineElement>,
     },
     Div(DisplayMode, Vec<InlineElement>),
+    /// One cite in a cluster, tagged so it can link to its bibliography entry. Only produced
+    /// when `HtmlOptions::link_citations` is on. `link` is false when the reference has no
+    /// bibliography entry to point at.
+    Cite {
+        id: String,
+        ref_id: String,
+        link: bool,
+        inlines: Vec<InlineElement>,
+    },
+    /// A bibliography entry, with the ids of the cites that refer to it.
+    BibEntry {
+        ref_id: String,
+        backrefs: Vec<String>,
+        inlines: Vec<InlineElement>,
+    },
+    /// An empty COinS span. The string is the OpenURL query, not yet HTML-escaped.
+    Coins(String),
+    /// Wraps a bibliography entry with schema.org microdata.
+    Microdata {
+        itemtype: String,
+        properties: Vec<(String, String)>,
+        inlines: Vec<InlineElement>,
+    },
 }
 
 impl Markup {
     pub fn html() -> Self {
         Markup::Html(HtmlOptions::default())
     }
+    /// HTML where cites link to their bibliography entries and back.
+    pub fn linked_html() -> Self {
+        Markup::Html(HtmlOptions::default().with_link_citations(true))
+    }
     pub fn test_html() -> Self {
         Markup::Html(HtmlOptions::test_suite())
     }
     pub fn rtf() -> Self {
-        Markup::Rtf
+        Markup::Rtf(RtfOptions::default())
     }
     pub fn plain() -> Self {
         Markup::Plain
     }
+    pub fn ansi() -> Self {
+        Markup::Ansi
+    }
+
+    /// Turns URL, DOI and PMID variables, and bare URLs in titles and notes, into links. Plain
+    /// and ANSI output are unaffected.
+    pub fn with_link_urls(self, link_urls: bool) -> Self {
+        match self {
+            Markup::Html(options) => Markup::Html(options.with_link_urls(link_urls)),
+            Markup::Rtf(options) => Markup::Rtf(options.with_link_urls(link_urls)),
+            other => other,
+        }
+    }
+
+    pub fn with_html_metadata(self, metadata: HtmlMetadata) -> Self {
+        match self {
+            Markup::Html(options) => Markup::Html(options.with_metadata(metadata)),
+            other => other,
+        }
+    }
+
+    /// Embeds machine-readable metadata describing `refr` in its bibliography entry, if the HTML
+    /// options ask for it.
+    pub fn entry_metadata(
+        &self,
+        inlines: Vec<InlineElement>,
+        refr: &Reference,
+    ) -> Vec<InlineElement> {
+        let kind = match self {
+            Markup::Html(options) => options.metadata(),
+            _ => HtmlMetadata::None,
+        };
+        match kind {
+            HtmlMetadata::None => inlines,
+            HtmlMetadata::Coins => {
+                let mut inlines = inlines;
+                inlines.push(InlineElement::Coins(metadata::coins(refr)));
+                inlines
+            }
+            HtmlMetadata::SchemaOrg => {
+                let (itemtype, properties) = metadata::schema_org(refr);
+                vec![InlineElement::Microdata {
+                    itemtype,
+                    properties,
+                    inlines,
+                }]
+            }
+        }
+    }
+
+    /// Whether cites and bibliography entries should be wrapped with `cite_span` and
+    /// `bib_entry`.
+    pub fn links_citations(&self) -> bool {
+        match self {
+            Markup::Html(options) => options.link_citations(),
+            _ => false,
+        }
+    }
+
+    /// Tags a rendered cite with its id (`"{cluster}-{index}"`) and reference, so HTML output
+    /// can link it to the bibliography. Does nothing unless `links_citations()`.
+    pub fn cite_span(
+        &self,
+        inlines: Vec<InlineElement>,
+        id: String,
+        ref_id: &str,
+        link: bool,
+    ) -> Vec<InlineElement> {
+        if !self.links_citations() || inlines.is_empty() {
+            return inlines;
+        }
+        vec![InlineElement::Cite {
+            id,
+            ref_id: ref_id.into(),
+            link,
+            inlines,
+        }]
+    }
+
+    /// Tags a bibliography entry with its reference and the ids of the cites pointing to it.
+    /// Does nothing unless `links_citations()`.
+    pub fn bib_entry(
+        &self,
+        inlines: Vec<InlineElement>,
+        ref_id: &str,
+        backrefs: Vec<String>,
+    ) -> Vec<InlineElement> {
+        if !self.links_citations() || inlines.is_empty() {
+            return inlines;
+        }
+        vec![InlineElement::BibEntry {
+            ref_id: ref_id.into(),
+            backrefs,
+            inlines,
+        }]
+    }
 }
 
 impl Default for Markup {
@@ -98,8 +229,9 @@ impl OutputFormat for Markup {
     fn meta(&self) -> Self::BibMeta {
         let (pre, post) = match self {
             Markup::Html(_) => ("<div class=\"csl-bib-body\">", "</div>"),
-            Markup::Rtf => ("", ""),
+            Markup::Rtf(_) => ("", ""),
             Markup::Plain => ("", ""),
+            Markup::Ansi => ("", ""),
         };
         MarkupBibMeta {
             markup_pre: pre.to_string(),
@@ -111,6 +243,9 @@ impl OutputFormat for Markup {
     fn ingest(&self, input: &str, options: &IngestOptions) -> Self::Build {
         let mut nodes = MicroNode::parse(input, options);
         options.apply_text_case_micro(&mut nodes);
+        if options.link_bare_urls && self.links_urls() {
+            return link_bare_urls(nodes);
+        }
         vec![InlineElement::Micro(nodes)]
     }
 
@@ -198,6 +333,14 @@ impl OutputFormat for Markup {
         }
     }
 
+    fn links_urls(&self) -> bool {
+        match self {
+            Markup::Html(options) => options.link_urls(),
+            Markup::Rtf(options) => options.link_urls(),
+            _ => false,
+        }
+    }
+
     #[inline]
     fn is_empty(&self, a: &Self::Build) -> bool {
         a.is_empty()
@@ -223,8 +366,9 @@ impl OutputFormat for Markup {
     fn stack_preorder(&self, dest: &mut String, stack: &[FormatCmd]) {
         match *self {
             Markup::Html(options) => HtmlWriter::new(dest, options).stack_preorder(stack),
-            Markup::Rtf => PlainWriter::new(dest).stack_preorder(stack),
+            Markup::Rtf(_) => PlainWriter::new(dest).stack_preorder(stack),
             Markup::Plain => PlainWriter::new(dest).stack_preorder(stack),
+            Markup::Ansi => PlainWriter::new(dest).stack_preorder(stack),
         }
     }
 
@@ -232,8 +376,9 @@ impl OutputFormat for Markup {
     fn stack_postorder(&self, dest: &mut String, stack: &[FormatCmd]) {
         match *self {
             Markup::Html(options) => HtmlWriter::new(dest, options).stack_postorder(stack),
-            Markup::Rtf => PlainWriter::new(dest).stack_postorder(stack),
+            Markup::Rtf(_) => PlainWriter::new(dest).stack_postorder(stack),
             Markup::Plain => PlainWriter::new(dest).stack_postorder(stack),
+            Markup::Ansi => PlainWriter::new(dest).stack_postorder(stack),
         }
     }
 
@@ -282,13 +427,61 @@ impl Markup {
         let mut dest = String::new();
         match *self {
             Markup::Html(options) => HtmlWriter::new(&mut dest, options).write_inlines(&flipped),
-            Markup::Rtf => RtfWriter::new(&mut dest).write_inlines(&flipped),
+            Markup::Rtf(_) => RtfWriter::new(&mut dest).write_inlines(&flipped),
             Markup::Plain => PlainWriter::new(&mut dest).write_inlines(&flipped),
+            Markup::Ansi => AnsiWriter::new(&mut dest).write_inlines(&flipped),
         }
         dest
     }
 }
 
+/// Splits the top-level text nodes in `nodes` around any URLs, and wraps those in anchors. URLs
+/// inside formatting or quotes are left alone.
+fn link_bare_urls(nodes: Vec<MicroNode>) -> Vec<InlineElement> {
+    use once_cell::sync::Lazy;
+    use regex::Regex;
+    static URL: Lazy<Regex> = Lazy::new(|| Regex::new(r#"https?://[^\s<>"]+"#).unwrap());
+
+    let mut inlines = Vec::new();
+    let mut micros = Vec::new();
+    for node in nodes {
+        let text = match node {
+            MicroNode::Text(text) => text,
+            other => {
+                micros.push(other);
+                continue;
+            }
+        };
+        let mut last = 0;
+        for found in URL.find_iter(&text) {
+            // Sentence punctuation after a URL is almost never part of it.
+            let url = found.as_str().trim_end_matches(|c: char| match c {
+                '.' | ',' | ';' | ':' | '!' | '?' | ')' | '\'' => true,
+                _ => false,
+            });
+            if found.start() > last {
+                micros.push(MicroNode::Text(text[last..found.start()].to_owned()));
+            }
+            if !micros.is_empty() {
+                inlines.push(InlineElement::Micro(std::mem::replace(&mut micros, Vec::new())));
+            }
+            inlines.push(InlineElement::Anchor {
+                title: "".into(),
+                url: url.into(),
+                content: vec![InlineElement::Text(url.into())],
+            });
+            last = found.start() + url.len();
+        }
+        if last < text.len() {
+            micros.push(MicroNode::Text(text[last..].to_owned()));
+        }
+    }
+    if !micros.is_empty() {
+        inlines.push(InlineElement::Micro(micros));
+    }
+    inlines
+}
+
 pub trait MarkupWriter {
     fn write_escaped(&mut self, text: &str);
     fn stack_preorder(&mut self, stack: &[FormatCmd]);
@@ -0,0 +1,280 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright © 2019 Corporation for Digital Scholarship
+
+use super::InlineElement;
+use super::MarkupWriter;
+use crate::output::micro_html::MicroNode;
+use crate::output::superscript::from_sup_sub;
+use crate::output::FormatCmd;
+use csl::Formatting;
+
+/// Writes terminal output using ANSI SGR escape codes.
+///
+/// Terminals can't nest styles, so instead of writing an open and close code for each
+/// `FormatCmd`, we keep a stack of the active ones and write out the whole effective style
+/// whenever it changes.
+#[derive(Debug)]
+pub struct AnsiWriter<'a> {
+    dest: &'a mut String,
+    stack: Vec<FormatCmd>,
+    written: AnsiStyle,
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+struct AnsiStyle {
+    italic: bool,
+    bold: bool,
+    light: bool,
+    underline: bool,
+    small_caps: bool,
+    /// Some(true) for superscript, Some(false) for subscript
+    vertical: Option<bool>,
+}
+
+impl AnsiStyle {
+    fn from_stack(stack: &[FormatCmd]) -> Self {
+        use crate::output::FormatCmd::*;
+        let mut style = AnsiStyle::default();
+        for cmd in stack {
+            match cmd {
+                FontStyleItalic | FontStyleOblique => style.italic = true,
+                FontStyleNormal => style.italic = false,
+                FontWeightBold => {
+                    style.bold = true;
+                    style.light = false;
+                }
+                FontWeightLight => {
+                    style.bold = false;
+                    style.light = true;
+                }
+                FontWeightNormal => {
+                    style.bold = false;
+                    style.light = false;
+                }
+                FontVariantSmallCaps => style.small_caps = true,
+                FontVariantNormal => style.small_caps = false,
+                TextDecorationUnderline => style.underline = true,
+                TextDecorationNone => style.underline = false,
+                VerticalAlignmentSuperscript => style.vertical = Some(true),
+                VerticalAlignmentSubscript => style.vertical = Some(false),
+                VerticalAlignmentBaseline => style.vertical = None,
+                DisplayBlock | DisplayIndent | DisplayLeftMargin | DisplayRightInline => {}
+            }
+        }
+        style
+    }
+
+    /// Vertical alignment is done with unicode characters, so it doesn't need a code.
+    fn sgr(self) -> String {
+        let mut codes = vec!["0"];
+        if self.bold {
+            codes.push("1");
+        }
+        // Small caps are rendered as uppercase, dimmed to tell them apart from real capitals.
+        if self.light || self.small_caps {
+            codes.push("2");
+        }
+        if self.italic {
+            codes.push("3");
+        }
+        if self.underline {
+            codes.push("4");
+        }
+        format!("\x1b[{}m", codes.join(";"))
+    }
+}
+
+impl<'a> AnsiWriter<'a> {
+    pub fn new(dest: &'a mut String) -> Self {
+        AnsiWriter {
+            dest,
+            stack: Vec::new(),
+            written: AnsiStyle::default(),
+        }
+    }
+
+    fn push_cmds(&mut self, cmds: &[FormatCmd]) {
+        self.stack.extend_from_slice(cmds);
+    }
+
+    fn pop_cmds(&mut self, count: usize) {
+        let len = self.stack.len();
+        self.stack.truncate(len.saturating_sub(count));
+    }
+
+    /// Writes the SGR code for the current stack, if it differs from what's in effect.
+    fn sync_style(&mut self) {
+        let style = AnsiStyle::from_stack(&self.stack);
+        if style.sgr() != self.written.sgr() {
+            self.dest.push_str(&style.sgr());
+        }
+        self.written = style;
+    }
+}
+
+impl<'a> MarkupWriter for AnsiWriter<'a> {
+    fn write_escaped(&mut self, text: &str) {
+        self.sync_style();
+        let style = self.written;
+        for c in text.chars() {
+            // Don't let reference data smuggle in its own escape sequences, or anything else
+            // the terminal acts on, like 8-bit CSI, BEL or carriage returns.
+            if c.is_control() && c != '\n' && c != '\t' {
+                continue;
+            }
+            if let Some(sup) = style.vertical.and_then(|sup| from_sup_sub(c, sup)) {
+                self.dest.push(sup);
+            } else if style.small_caps {
+                self.dest.extend(c.to_uppercase());
+            } else {
+                self.dest.push(c);
+            }
+        }
+    }
+
+    fn stack_preorder(&mut self, stack: &[FormatCmd]) {
+        self.push_cmds(stack);
+    }
+
+    fn stack_postorder(&mut self, stack: &[FormatCmd]) {
+        self.pop_cmds(stack.len());
+        // Reset as soon as a span ends, so nothing leaks past the end of the output.
+        self.sync_style();
+    }
+
+    fn write_micro(&mut self, micro: &MicroNode) {
+        use MicroNode::*;
+        match micro {
+            Text(text) => {
+                self.write_escaped(text);
+            }
+            Quoted {
+                is_inner,
+                localized,
+                children,
+            } => {
+                self.write_escaped(localized.opening(*is_inner));
+                self.write_micros(children);
+                self.write_escaped(localized.closing(*is_inner));
+            }
+            Formatted(nodes, cmd) => {
+                self.push_cmds(&[*cmd]);
+                self.write_micros(nodes);
+                self.pop_cmds(1);
+                self.sync_style();
+            }
+            NoCase(inners) => {
+                self.write_micros(inners);
+            }
+        }
+    }
+
+    fn write_inline(&mut self, inline: &InlineElement) {
+        use super::InlineElement::*;
+        match inline {
+            Text(text) => {
+                self.write_escaped(text);
+            }
+            Div(display, inlines) => {
+                self.stack_formats(inlines, Formatting::default(), Some(*display));
+            }
+            Micro(micros) => {
+                self.write_micros(micros);
+            }
+            Formatted(inlines, formatting) => {
+                self.stack_formats(inlines, *formatting, None);
+            }
+            Quoted {
+                is_inner,
+                localized,
+                inlines,
+            } => {
+                self.write_escaped(localized.opening(*is_inner));
+                self.write_inlines(inlines);
+                self.write_escaped(localized.closing(*is_inner));
+            }
+            Anchor { url, content, .. } => {
+                // OSC 8 hyperlinks. Terminals that don't support them just show the content.
+                let url: String = url.chars().filter(|c| !c.is_control()).collect();
+                self.dest.push_str("\x1b]8;;");
+                self.dest.push_str(&url);
+                self.dest.push_str("\x1b\\");
+                self.write_inlines(content);
+                self.dest.push_str("\x1b]8;;\x1b\\");
+            }
+            Cite { inlines, .. } | BibEntry { inlines, .. } | Microdata { inlines, .. } => {
+                self.write_inlines(inlines);
+            }
+            Coins(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use csl::{FontStyle, FontVariant, VerticalAlignment};
+
+    fn ansi(inlines: &[InlineElement]) -> String {
+        let mut dest = String::new();
+        AnsiWriter::new(&mut dest).write_inlines(inlines);
+        dest
+    }
+
+    fn formatted(text: &str, formatting: Formatting) -> InlineElement {
+        InlineElement::Formatted(vec![InlineElement::Text(text.into())], formatting)
+    }
+
+    #[test]
+    fn nested_styles_reset() {
+        let italic = Formatting {
+            font_style: Some(FontStyle::Italic),
+            ..Default::default()
+        };
+        let inlines = vec![
+            InlineElement::Text("a ".into()),
+            formatted("b", italic),
+            InlineElement::Text(" c".into()),
+        ];
+        assert_eq!(ansi(&inlines), "a \x1b[0;3mb\x1b[0m c");
+    }
+
+    #[test]
+    fn small_caps_and_superscript() {
+        let small_caps = Formatting {
+            font_variant: Some(FontVariant::SmallCaps),
+            ..Default::default()
+        };
+        let sup = Formatting {
+            vertical_alignment: Some(VerticalAlignment::Superscript),
+            ..Default::default()
+        };
+        assert_eq!(
+            ansi(&[formatted("Smith", small_caps)]),
+            "\x1b[0;2mSMITH\x1b[0m"
+        );
+        assert_eq!(ansi(&[formatted("2", sup)]), "²");
+    }
+
+    #[test]
+    fn strips_control_characters() {
+        let text = InlineElement::Text("a\x1b[31mb\u{9b}2Jc\x07d\re\nf\tg".into());
+        assert_eq!(ansi(&[text]), "a[31mb2Jcde\nf\tg");
+    }
+
+    #[test]
+    fn osc8_links() {
+        let anchor = InlineElement::Anchor {
+            title: "".into(),
+            url: "https://example.com".into(),
+            content: vec![InlineElement::Text("link".into())],
+        };
+        assert_eq!(
+            ansi(&[anchor]),
+            "\x1b]8;;https://example.com\x1b\\link\x1b]8;;\x1b\\"
+        );
+    }
+}
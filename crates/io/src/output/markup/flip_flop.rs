@@ -103,6 +103,38 @@ fn flip_flop(inline: &InlineElement, state: &FlipFlopState) -> Option<InlineElem
             })
         }
 
+        InlineElement::Cite {
+            ref id,
+            ref ref_id,
+            link,
+            ref inlines,
+        } => Some(InlineElement::Cite {
+            id: id.clone(),
+            ref_id: ref_id.clone(),
+            link,
+            inlines: state.flip_flop_inlines(inlines),
+        }),
+
+        InlineElement::BibEntry {
+            ref ref_id,
+            ref backrefs,
+            ref inlines,
+        } => Some(InlineElement::BibEntry {
+            ref_id: ref_id.clone(),
+            backrefs: backrefs.clone(),
+            inlines: state.flip_flop_inlines(inlines),
+        }),
+
+        InlineElement::Microdata {
+            ref itemtype,
+            ref properties,
+            ref inlines,
+        } => Some(InlineElement::Microdata {
+            itemtype: itemtype.clone(),
+            properties: properties.clone(),
+            inlines: state.flip_flop_inlines(inlines),
+        }),
+
         InlineElement::Text(ref string) if string.is_empty() => None,
 
         _ => Some(inline.clone()),
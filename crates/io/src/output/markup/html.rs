@@ -4,8 +4,9 @@
 //
 // Copyright © 2019 Corporation for Digital Scholarship
 
+use super::metadata::percent_encode;
 use super::InlineElement;
-use super::MarkupWriter;
+use super::{HtmlMetadata, MarkupWriter};
 use crate::output::micro_html::MicroNode;
 use crate::output::FormatCmd;
 use csl::Formatting;
@@ -16,6 +17,9 @@ pub struct HtmlOptions {
     // quotes: LocalizedQuotes,
     use_b_for_strong: bool,
     link_anchors: bool,
+    link_citations: bool,
+    link_urls: bool,
+    metadata: HtmlMetadata,
 }
 
 impl Default for HtmlOptions {
@@ -23,6 +27,9 @@ impl Default for HtmlOptions {
         HtmlOptions {
             use_b_for_strong: false,
             link_anchors: true,
+            link_citations: false,
+            link_urls: false,
+            metadata: HtmlMetadata::None,
         }
     }
 }
@@ -32,8 +39,41 @@ impl HtmlOptions {
         HtmlOptions {
             use_b_for_strong: true,
             link_anchors: false,
+            link_citations: false,
+            link_urls: false,
+            metadata: HtmlMetadata::None,
         }
     }
+
+    /// Wrap each cite in a `<span class="csl-cite">` that links to its bibliography entry, and
+    /// give each entry an `id` and a list of backlinks to the cites that refer to it.
+    pub fn with_link_citations(mut self, link_citations: bool) -> Self {
+        self.link_citations = link_citations;
+        self
+    }
+
+    pub fn link_citations(&self) -> bool {
+        self.link_citations
+    }
+
+    pub fn with_link_urls(mut self, link_urls: bool) -> Self {
+        self.link_urls = link_urls;
+        self
+    }
+
+    pub fn link_urls(&self) -> bool {
+        self.link_urls
+    }
+
+    /// Embed COinS or schema.org metadata in each bibliography entry.
+    pub fn with_metadata(mut self, metadata: HtmlMetadata) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    pub fn metadata(&self) -> HtmlMetadata {
+        self.metadata
+    }
 }
 
 #[derive(Debug)]
@@ -46,6 +86,15 @@ impl<'a> HtmlWriter<'a> {
     pub fn new(dest: &'a mut String, options: HtmlOptions) -> Self {
         HtmlWriter { dest, options }
     }
+
+    fn write_attr(&mut self, name: &str, value: &str) {
+        use v_htmlescape::escape;
+        self.dest.push(' ');
+        self.dest.push_str(name);
+        self.dest.push_str("=\"");
+        self.dest.push_str(&escape(value).to_string());
+        self.dest.push('"');
+    }
 }
 
 impl<'a> MarkupWriter for HtmlWriter<'a> {
@@ -125,15 +174,84 @@ impl<'a> MarkupWriter for HtmlWriter<'a> {
             }
             Anchor { url, content, .. } => {
                 if self.options.link_anchors {
-                    self.dest.push_str(r#"<a href=""#);
-                    // TODO: HTML-quoted-escape? the url?
-                    self.dest.push_str(&url.trim());
-                    self.dest.push_str(r#"">"#);
+                    self.dest.push_str("<a");
+                    self.write_attr("href", url.trim());
+                    self.dest.push_str(">");
                     self.write_inlines(content);
                     self.dest.push_str("</a>");
                 } else {
-                    self.dest.push_str(&url.trim());
+                    self.write_escaped(url.trim());
+                }
+            }
+            Cite {
+                id,
+                ref_id,
+                link,
+                inlines,
+            } => {
+                self.dest.push_str("<span");
+                self.write_attr("class", "csl-cite");
+                self.write_attr("id", &fragment_id("cite", id));
+                self.write_attr("data-cite-id", id);
+                self.write_attr("data-ref-id", ref_id);
+                self.dest.push_str(">");
+                if *link && !(self.options.link_anchors && has_anchor(inlines)) {
+                    self.dest.push_str("<a");
+                    self.write_attr("href", &format!("#{}", fragment_id("ref", ref_id)));
+                    self.dest.push_str(">");
+                    self.write_inlines(inlines);
+                    self.dest.push_str("</a>");
+                } else {
+                    self.write_inlines(inlines);
+                }
+                self.dest.push_str("</span>");
+            }
+            BibEntry {
+                ref_id,
+                backrefs,
+                inlines,
+            } => {
+                self.dest.push_str("<div");
+                self.write_attr("class", "csl-entry");
+                self.write_attr("id", &fragment_id("ref", ref_id));
+                self.dest.push_str(">");
+                self.write_inlines(inlines);
+                if !backrefs.is_empty() {
+                    self.dest.push_str(r#" <span class="csl-backrefs">"#);
+                    for (i, backref) in backrefs.iter().enumerate() {
+                        if i > 0 {
+                            self.dest.push(' ');
+                        }
+                        self.dest.push_str("<a");
+                        self.write_attr("href", &format!("#{}", fragment_id("cite", backref)));
+                        self.dest.push_str(">\u{21A9}</a>");
+                    }
+                    self.dest.push_str("</span>");
                 }
+                self.dest.push_str("</div>");
+            }
+            Coins(kev) => {
+                self.dest.push_str("<span");
+                self.write_attr("class", "Z3988");
+                self.write_attr("title", kev);
+                self.dest.push_str("></span>");
+            }
+            Microdata {
+                itemtype,
+                properties,
+                inlines,
+            } => {
+                self.dest.push_str("<span itemscope");
+                self.write_attr("itemtype", itemtype);
+                self.dest.push_str(">");
+                for (name, content) in properties {
+                    self.dest.push_str("<meta");
+                    self.write_attr("itemprop", name);
+                    self.write_attr("content", content);
+                    self.dest.push_str(">");
+                }
+                self.write_inlines(inlines);
+                self.dest.push_str("</span>");
             }
         }
     }
@@ -178,6 +296,132 @@ impl FormatCmd {
     }
 }
 
+/// `prefix-id`, with `id` percent-encoded, so that reference ids with spaces or `#` in them still
+/// make a matching `id` and `#fragment`.
+fn fragment_id(prefix: &str, id: &str) -> String {
+    let mut out = String::with_capacity(prefix.len() + id.len() + 1);
+    out.push_str(prefix);
+    out.push('-');
+    percent_encode(id, &mut out);
+    out
+}
+
+/// Whether `inlines` contain an `Anchor`. Links can't be nested, so a cite that does is not
+/// linked to its bibliography entry.
+fn has_anchor(inlines: &[InlineElement]) -> bool {
+    use super::InlineElement::*;
+    inlines.iter().any(|inline| match inline {
+        Anchor { .. } => true,
+        Div(_, inlines)
+        | Formatted(inlines, _)
+        | Quoted { inlines, .. }
+        | Cite { inlines, .. }
+        | BibEntry { inlines, .. }
+        | Microdata { inlines, .. } => has_anchor(inlines),
+        Text(_) | Micro(_) | Coins(_) => false,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn html(inlines: &[InlineElement]) -> String {
+        let mut dest = String::new();
+        let options = HtmlOptions::default().with_link_citations(true);
+        HtmlWriter::new(&mut dest, options).write_inlines(inlines);
+        dest
+    }
+
+    #[test]
+    fn cite_links() {
+        let cite = InlineElement::Cite {
+            id: "1-0".into(),
+            ref_id: "a&b".into(),
+            link: true,
+            inlines: vec![InlineElement::Text("Smith".into())],
+        };
+        assert_eq!(
+            html(&[cite]),
+            r##"<span class="csl-cite" id="cite-1-0" data-cite-id="1-0" data-ref-id="a&amp;b"><a href="#ref-a%26b">Smith</a></span>"##
+        );
+    }
+
+    #[test]
+    fn cite_with_anchor_is_not_linked() {
+        let cite = InlineElement::Cite {
+            id: "1-0".into(),
+            ref_id: "a".into(),
+            link: true,
+            inlines: vec![InlineElement::Formatted(
+                vec![InlineElement::Anchor {
+                    title: "".into(),
+                    url: "https://example.com".into(),
+                    content: vec![InlineElement::Text("example.com".into())],
+                }],
+                Formatting::default(),
+            )],
+        };
+        assert_eq!(
+            html(&[cite]),
+            r#"<span class="csl-cite" id="cite-1-0" data-cite-id="1-0" data-ref-id="a"><a href="https://example.com">example.com</a></span>"#
+        );
+    }
+
+    #[test]
+    fn fragment_ids_match() {
+        let entry = InlineElement::BibEntry {
+            ref_id: "smith 2019#a".into(),
+            backrefs: vec![],
+            inlines: vec![],
+        };
+        let cite = InlineElement::Cite {
+            id: "1-0".into(),
+            ref_id: "smith 2019#a".into(),
+            link: true,
+            inlines: vec![],
+        };
+        assert!(html(&[entry]).contains(r#"id="ref-smith%202019%23a""#));
+        assert!(html(&[cite]).contains(r##"href="#ref-smith%202019%23a""##));
+    }
+
+    #[test]
+    fn anchor_href_is_escaped() {
+        let anchor = InlineElement::Anchor {
+            title: "".into(),
+            url: r#"https://example.com/?a="b"&c"#.into(),
+            content: vec![InlineElement::Text("link".into())],
+        };
+        assert_eq!(
+            html(&[anchor]),
+            r#"<a href="https://example.com/?a=&quot;b&quot;&amp;c">link</a>"#
+        );
+    }
+
+    #[test]
+    fn coins_title_is_escaped() {
+        let coins = InlineElement::Coins("ctx_ver=Z39.88-2004&rft.title=A%20B".into());
+        assert_eq!(
+            html(&[coins]),
+            r#"<span class="Z3988" title="ctx_ver=Z39.88-2004&amp;rft.title=A%20B"></span>"#
+        );
+    }
+
+    #[test]
+    fn bib_entry_backrefs() {
+        let entry = InlineElement::BibEntry {
+            ref_id: "smith".into(),
+            backrefs: vec!["1-0".into(), "2-1".into()],
+            inlines: vec![InlineElement::Text("Smith.".into())],
+        };
+        assert_eq!(
+            html(&[entry]),
+            "<div class=\"csl-entry\" id=\"ref-smith\">Smith. <span class=\"csl-backrefs\">\
+             <a href=\"#cite-1-0\">\u{21A9}</a> <a href=\"#cite-2-1\">\u{21A9}</a></span></div>"
+        );
+    }
+}
+
 // impl InlineElement {
 // fn is_disp(&self, disp: DisplayMode) -> bool {
 //     match *self {
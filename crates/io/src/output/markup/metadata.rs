@@ -0,0 +1,288 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright © 2019 Corporation for Digital Scholarship
+
+//! Machine-readable descriptions of a reference, for embedding in HTML bibliographies so that
+//! tools like the Zotero connector can pick them up.
+
+use crate::{DateOrRange, Name, Reference};
+use csl::{CslType, DateVariable, NameVariable, NumberVariable, Variable};
+use std::str::FromStr;
+
+/// Which kind of metadata, if any, to embed in each HTML bibliography entry.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum HtmlMetadata {
+    None,
+    /// An empty `<span class="Z3988">` holding an OpenURL ContextObject.
+    Coins,
+    /// schema.org microdata: `itemscope` on a wrapping span, and `<meta itemprop>` tags.
+    SchemaOrg,
+}
+
+impl Default for HtmlMetadata {
+    fn default() -> Self {
+        HtmlMetadata::None
+    }
+}
+
+impl FromStr for HtmlMetadata {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(HtmlMetadata::None),
+            "coins" => Ok(HtmlMetadata::Coins),
+            "schema-org" => Ok(HtmlMetadata::SchemaOrg),
+            _ => Err(()),
+        }
+    }
+}
+
+fn name_string(name: &Name) -> Option<String> {
+    match name {
+        Name::Literal { literal } => Some(literal.clone()),
+        Name::Person(pn) => {
+            let mut family = String::new();
+            if let Some(ndp) = &pn.non_dropping_particle {
+                family.push_str(ndp);
+                family.push(' ');
+            }
+            family.push_str(pn.family.as_ref()?);
+            Some(match &pn.given {
+                Some(given) => format!("{}, {}", family, given),
+                None => family,
+            })
+        }
+    }
+}
+
+/// `YYYY`, `YYYY-MM` or `YYYY-MM-DD`, using the start of a range.
+fn iso_date(date: &DateOrRange) -> Option<String> {
+    let date = date.single_or_first()?;
+    // Months above 12 are seasons
+    Some(if date.month == 0 || date.month > 12 {
+        format!("{:04}", date.year)
+    } else if date.day == 0 {
+        format!("{:04}-{:02}", date.year, date.month)
+    } else {
+        format!("{:04}-{:02}-{:02}", date.year, date.month, date.day)
+    })
+}
+
+fn authors(refr: &Reference) -> impl Iterator<Item = String> + '_ {
+    refr.name
+        .get(&NameVariable::Author)
+        .into_iter()
+        .flatten()
+        .filter_map(name_string)
+}
+
+/// Percent-encodes everything except RFC 3986 unreserved characters, like JavaScript's
+/// `encodeURIComponent`.
+pub(super) fn percent_encode(s: &str, dest: &mut String) {
+    use std::fmt::Write;
+    for byte in s.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                dest.push(byte as char)
+            }
+            _ => {
+                let _ = write!(dest, "%{:02X}", byte);
+            }
+        }
+    }
+}
+
+/// Builds the OpenURL (Z39.88-2004) key-encoded-value string for a COinS span. The pairs are
+/// percent-encoded, but the string still needs HTML escaping before it goes in an attribute.
+pub(super) fn coins(refr: &Reference) -> String {
+    let mut pairs: Vec<(&str, String)> = vec![("ctx_ver", "Z39.88-2004".into())];
+    let ordinary = |var: Variable| refr.ordinary.get(&var).cloned();
+    let number = |var: NumberVariable| refr.number.get(&var).map(|n| n.verbatim().to_owned());
+
+    let (format, genre, title_key, container_key) = match refr.csl_type {
+        CslType::ArticleJournal
+        | CslType::ArticleMagazine
+        | CslType::ArticleNewspaper
+        | CslType::Article
+        | CslType::Review
+        | CslType::ReviewBook => ("journal", "article", "rft.atitle", Some("rft.jtitle")),
+        CslType::PaperConference => ("book", "proceeding", "rft.atitle", Some("rft.btitle")),
+        CslType::Chapter | CslType::EntryDictionary | CslType::EntryEncyclopedia => {
+            ("book", "bookitem", "rft.atitle", Some("rft.btitle"))
+        }
+        CslType::Book => ("book", "book", "rft.btitle", None),
+        CslType::Report => ("book", "report", "rft.btitle", None),
+        _ => ("dc", "", "rft.title", None),
+    };
+    pairs.push(("rft_val_fmt", format!("info:ofi/fmt:kev:mtx:{}", format)));
+    if let Some(doi) = ordinary(Variable::DOI) {
+        pairs.push(("rft_id", format!("info:doi/{}", doi)));
+    }
+    if let Some(url) = ordinary(Variable::URL) {
+        pairs.push(("rft_id", url));
+    }
+    if !genre.is_empty() {
+        pairs.push(("rft.genre", genre.into()));
+    }
+    if let Some(title) = ordinary(Variable::Title) {
+        pairs.push((title_key, title));
+    }
+    if let Some(key) = container_key {
+        if let Some(container) = ordinary(Variable::ContainerTitle) {
+            pairs.push((key, container));
+        }
+    }
+    let author_key = if format == "dc" {
+        "rft.creator"
+    } else {
+        "rft.au"
+    };
+    for author in authors(refr) {
+        pairs.push((author_key, author));
+    }
+    if let Some(date) = refr.date.get(&DateVariable::Issued).and_then(iso_date) {
+        pairs.push(("rft.date", date));
+    }
+    let rest = [
+        ("rft.volume", number(NumberVariable::Volume)),
+        ("rft.issue", number(NumberVariable::Issue)),
+        ("rft.pages", number(NumberVariable::Page)),
+        ("rft.edition", number(NumberVariable::Edition)),
+        ("rft.pub", ordinary(Variable::Publisher)),
+        ("rft.place", ordinary(Variable::PublisherPlace)),
+        ("rft.isbn", ordinary(Variable::ISBN)),
+        ("rft.issn", ordinary(Variable::ISSN)),
+    ];
+    for (key, value) in rest.iter() {
+        if let Some(value) = value {
+            // Dublin Core only has a few of these
+            if format == "dc" && *key != "rft.pub" {
+                continue;
+            }
+            let key = if format == "dc" {
+                "rft.publisher"
+            } else {
+                *key
+            };
+            pairs.push((key, value.clone()));
+        }
+    }
+
+    let mut kev = String::new();
+    for (i, (key, value)) in pairs.iter().enumerate() {
+        if i > 0 {
+            kev.push('&');
+        }
+        kev.push_str(key);
+        kev.push('=');
+        percent_encode(value, &mut kev);
+    }
+    kev
+}
+
+/// The schema.org type and `itemprop`s describing a reference.
+pub(super) fn schema_org(refr: &Reference) -> (String, Vec<(String, String)>) {
+    let itemtype = match refr.csl_type {
+        CslType::ArticleJournal | CslType::PaperConference => "ScholarlyArticle",
+        CslType::ArticleNewspaper => "NewsArticle",
+        CslType::Article | CslType::ArticleMagazine => "Article",
+        CslType::Review | CslType::ReviewBook => "Review",
+        CslType::Book => "Book",
+        CslType::Chapter => "Chapter",
+        CslType::Thesis => "Thesis",
+        CslType::Report => "Report",
+        CslType::Dataset => "Dataset",
+        CslType::Webpage => "WebPage",
+        CslType::Post | CslType::PostWeblog => "BlogPosting",
+        CslType::MotionPicture | CslType::Video => "Movie",
+        CslType::Map => "Map",
+        CslType::Song => "MusicRecording",
+        _ => "CreativeWork",
+    };
+    let mut props = Vec::new();
+    let mut prop = |name: &str, value: String| props.push((name.to_owned(), value));
+    if let Some(title) = refr.ordinary.get(&Variable::Title) {
+        prop("name", title.clone());
+    }
+    for author in authors(refr) {
+        prop("author", author);
+    }
+    if let Some(date) = refr.date.get(&DateVariable::Issued).and_then(iso_date) {
+        prop("datePublished", date);
+    }
+    if let Some(container) = refr.ordinary.get(&Variable::ContainerTitle) {
+        prop("isPartOf", container.clone());
+    }
+    if let Some(publisher) = refr.ordinary.get(&Variable::Publisher) {
+        prop("publisher", publisher.clone());
+    }
+    if let Some(pages) = refr.number.get(&NumberVariable::Page) {
+        prop("pagination", pages.verbatim().to_owned());
+    }
+    if let Some(doi) = refr.ordinary.get(&Variable::DOI) {
+        if let Some(url) = Variable::DOI.resolved_hyperlink(doi) {
+            prop("sameAs", url.into_owned());
+        }
+    }
+    if let Some(url) = refr.ordinary.get(&Variable::URL) {
+        prop("url", url.clone());
+    }
+    if let Some(isbn) = refr.ordinary.get(&Variable::ISBN) {
+        prop("isbn", isbn.clone());
+    }
+    (format!("https://schema.org/{}", itemtype), props)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::PersonName;
+
+    fn article() -> Reference {
+        let mut refr = Reference::empty("a".into(), CslType::ArticleJournal);
+        refr.ordinary
+            .insert(Variable::Title, "Cats & Dogs: A Study".into());
+        refr.ordinary
+            .insert(Variable::ContainerTitle, "Pets".into());
+        refr.name.insert(
+            NameVariable::Author,
+            vec![Name::Person(PersonName {
+                family: Some("Smith".into()),
+                given: Some("Jo".into()),
+                non_dropping_particle: None,
+                dropping_particle: None,
+                suffix: None,
+            })],
+        );
+        refr.date
+            .insert(DateVariable::Issued, DateOrRange::new(2019, 3, 0));
+        refr
+    }
+
+    #[test]
+    fn coins_kev() {
+        assert_eq!(
+            coins(&article()),
+            "ctx_ver=Z39.88-2004&rft_val_fmt=info%3Aofi%2Ffmt%3Akev%3Amtx%3Ajournal\
+             &rft.genre=article&rft.atitle=Cats%20%26%20Dogs%3A%20A%20Study&rft.jtitle=Pets\
+             &rft.au=Smith%2C%20Jo&rft.date=2019-03"
+        );
+    }
+
+    #[test]
+    fn schema_org_props() {
+        let (itemtype, props) = schema_org(&article());
+        assert_eq!(itemtype, "https://schema.org/ScholarlyArticle");
+        assert_eq!(
+            props,
+            vec![
+                ("name".to_owned(), "Cats & Dogs: A Study".to_owned()),
+                ("author".to_owned(), "Smith, Jo".to_owned()),
+                ("datePublished".to_owned(), "2019-03".to_owned()),
+                ("isPartOf".to_owned(), "Pets".to_owned()),
+            ]
+        );
+    }
+}
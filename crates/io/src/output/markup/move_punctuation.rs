@@ -55,6 +55,9 @@ fn find_string_left(next: &mut InlineElement) -> Option<&mut String> {
     match next {
         InlineElement::Text(ref mut string) => Some(string),
         InlineElement::Micro(ref mut micros) => micros.first_mut().and_then(find_string_left_micro),
+        InlineElement::Cite { ref mut inlines, .. } => {
+            inlines.first_mut().and_then(find_string_left)
+        }
         _ => None,
     }
 }
@@ -87,6 +90,9 @@ pub fn move_punctuation(slice: &mut [InlineElement]) {
             match inl {
                 InlineElement::Quoted { inlines, .. }
                 | InlineElement::Div(_, inlines)
+                | InlineElement::Cite { inlines, .. }
+                | InlineElement::BibEntry { inlines, .. }
+                | InlineElement::Microdata { inlines, .. }
                 | InlineElement::Formatted(inlines, _) => move_punctuation(inlines),
                 _ => {}
             }
@@ -171,9 +177,11 @@ fn find_right_quote<'a>(el: &'a mut InlineElement) -> Option<RightQuoteInsertion
             }
         }
         InlineElement::Micro(micros) => micros.last_mut().and_then(find_right_quote_micro),
-        InlineElement::Div(_, inlines) | InlineElement::Formatted(inlines, _) => {
-            inlines.last_mut().and_then(find_right_quote)
-        }
+        InlineElement::Div(_, inlines)
+        | InlineElement::Cite { inlines, .. }
+        | InlineElement::BibEntry { inlines, .. }
+        | InlineElement::Microdata { inlines, .. }
+        | InlineElement::Formatted(inlines, _) => inlines.last_mut().and_then(find_right_quote),
         _ => None,
     }
 }
@@ -212,6 +220,9 @@ fn last_string(is: &mut [InlineElement]) -> Option<&mut String> {
         InlineElement::Micro(micros) => last_string_micro(micros),
         InlineElement::Quoted { inlines, .. }
         | InlineElement::Div(_, inlines)
+        | InlineElement::Cite { inlines, .. }
+        | InlineElement::BibEntry { inlines, .. }
+        | InlineElement::Microdata { inlines, .. }
         | InlineElement::Formatted(inlines, _) => {
             last_string(inlines)
         }
@@ -236,6 +247,9 @@ fn ends_with_punctuation(i: &InlineElement) -> bool {
         InlineElement::Micro(micros) => micros.last().map_or(false, ends_with_punctuation_micro),
         InlineElement::Quoted { inlines, .. }
         | InlineElement::Div(_, inlines)
+        | InlineElement::Cite { inlines, .. }
+        | InlineElement::BibEntry { inlines, .. }
+        | InlineElement::Microdata { inlines, .. }
         | InlineElement::Formatted(inlines, _) => {
             inlines.last().map_or(false, ends_with_punctuation)
         }
@@ -81,6 +81,10 @@ impl<'a> MarkupWriter for PlainWriter<'a> {
             Anchor { content, .. } => {
                 self.write_inlines(content);
             }
+            Cite { inlines, .. } | BibEntry { inlines, .. } | Microdata { inlines, .. } => {
+                self.write_inlines(inlines);
+            }
+            Coins(_) => {}
         }
     }
 }
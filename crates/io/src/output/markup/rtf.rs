@@ -10,6 +10,22 @@ use crate::output::micro_html::MicroNode;
 use crate::output::FormatCmd;
 use csl::Formatting;
 
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct RtfOptions {
+    link_urls: bool,
+}
+
+impl RtfOptions {
+    pub fn with_link_urls(mut self, link_urls: bool) -> Self {
+        self.link_urls = link_urls;
+        self
+    }
+
+    pub fn link_urls(&self) -> bool {
+        self.link_urls
+    }
+}
+
 #[derive(Debug)]
 pub struct RtfWriter<'a> {
     dest: &'a mut String,
@@ -92,14 +108,18 @@ impl<'a> MarkupWriter for RtfWriter<'a> {
                 self.write_escaped(localized.closing(*is_inner));
             }
             Anchor { url, content, .. } => {
-                // TODO: {\field{\*\fldinst{HYPERLINK "https://google.com"}}{\fldrslt whatever}}
-                // TODO: HTML-quoted-escape? the url?
-                self.dest.push_str(r#"<a href=""#);
-                self.dest.push_str(&url);
-                self.dest.push_str(r#"">"#);
+                // The field instruction is a quoted string, so the URL can't contain quotes.
+                let url = url.trim().replace('"', "%22");
+                self.dest.push_str(r#"{\field{\*\fldinst{HYPERLINK ""#);
+                rtf_escape_into(&url, self.dest);
+                self.dest.push_str(r#""}}{\fldrslt "#);
                 self.write_inlines(content);
-                self.dest.push_str("</a>");
+                self.dest.push_str("}}");
+            }
+            Cite { inlines, .. } | BibEntry { inlines, .. } | Microdata { inlines, .. } => {
+                self.write_inlines(inlines);
             }
+            Coins(_) => {}
         }
     }
 }
@@ -182,3 +202,18 @@ fn test_rtf_escape_unicode() {
     let poop = "Hello 💩";
     assert_eq!(rtf_escape(poop), r"Hello \uc0\u55357 \uc0\u56489 ");
 }
+
+#[test]
+fn test_rtf_hyperlink_field() {
+    let anchor = InlineElement::Anchor {
+        title: "".into(),
+        url: "https://doi.org/10.1000/{x}".into(),
+        content: vec![InlineElement::Text("10.1000/{x}".into())],
+    };
+    let mut dest = String::new();
+    RtfWriter::new(&mut dest).write_inline(&anchor);
+    assert_eq!(
+        dest,
+        r#"{\field{\*\fldinst{HYPERLINK "https://doi.org/10.1000/\{x\}"}}{\fldrslt 10.1000/\{x\}}}"#
+    );
+}
@@ -107,7 +107,15 @@ pub enum FormatCmd {
 use std::hash::Hash;
 
 pub trait OutputFormat: Send + Sync + Clone + Default + std::fmt::Debug {
-    type Input: std::fmt::Debug + DeserializeOwned + Default + Clone + Send + Sync + Eq + Hash;
+    type Input: std::fmt::Debug
+        + DeserializeOwned
+        + Serialize
+        + Default
+        + Clone
+        + Send
+        + Sync
+        + Eq
+        + Hash;
     type Build: std::fmt::Debug + Default + Clone + Send + Sync + Eq;
     type Output: Default + Clone + Send + Sync + Eq + Serialize;
     type BibMeta: Serialize;
@@ -219,6 +227,12 @@ pub trait OutputFormat: Send + Sync + Clone + Default + std::fmt::Debug {
 
     fn hyperlinked(&self, a: Self::Build, target: Option<&str>) -> Self::Build;
 
+    /// Whether DOI and PMID variables link to their resolved URLs, and bare URLs in titles and
+    /// notes are linked. URL and DOI variables are passed to `hyperlinked` either way.
+    fn links_urls(&self) -> bool {
+        false
+    }
+
     fn stack_preorder(&self, s: &mut String, stack: &[FormatCmd]);
     fn stack_postorder(&self, s: &mut String, stack: &[FormatCmd]);
     fn tag_stack(&self, formatting: Formatting, display: Option<DisplayMode>) -> Vec<FormatCmd>;
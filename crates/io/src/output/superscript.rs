@@ -134,3 +134,49 @@ fn to_sup_sub(c: char) -> SupSub {
         SupSub::Normal
     }
 }
+
+/// The reverse of `to_sup_sub`: finds the unicode superscript (or subscript) form of a plain
+/// character, if there is one. For formats like the terminal that can't raise or lower text.
+pub(crate) fn from_sup_sub(c: char, superscript: bool) -> Option<char> {
+    use crate::unicode::sup_sub::{
+        lookup_decomposition, SUBSCRIPT_MEMBERSHIP, SUPERSCRIPT_MEMBERSHIP,
+    };
+    use fnv::FnvHashMap;
+    use once_cell::sync::Lazy;
+
+    fn reverse(ranges: &[(char, char)]) -> FnvHashMap<char, char> {
+        let mut map = FnvHashMap::default();
+        for &(from, upto) in ranges {
+            for cp in (from as u32)..=(upto as u32) {
+                if let Some(sup) = std::char::from_u32(cp) {
+                    let mut decomposed = lookup_decomposition(sup).chars();
+                    // Skip multi-character decompositions like ™. Later codepoints win, so we get
+                    // modifier letters like ᵃ instead of ordinal indicators like ª.
+                    if let (Some(plain), None) = (decomposed.next(), decomposed.next()) {
+                        map.insert(plain, sup);
+                    }
+                }
+            }
+        }
+        map
+    }
+
+    static SUPERSCRIPTS: Lazy<FnvHashMap<char, char>> =
+        Lazy::new(|| reverse(SUPERSCRIPT_MEMBERSHIP));
+    static SUBSCRIPTS: Lazy<FnvHashMap<char, char>> = Lazy::new(|| reverse(SUBSCRIPT_MEMBERSHIP));
+
+    if superscript {
+        SUPERSCRIPTS.get(&c).cloned()
+    } else {
+        SUBSCRIPTS.get(&c).cloned()
+    }
+}
+
+#[test]
+fn reverse_sup_sub() {
+    assert_eq!(from_sup_sub('2', true), Some('²'));
+    assert_eq!(from_sup_sub('n', true), Some('ⁿ'));
+    assert_eq!(from_sup_sub('a', true), Some('ᵃ'));
+    assert_eq!(from_sup_sub('2', false), Some('₂'));
+    assert_eq!(from_sup_sub('%', true), None);
+}
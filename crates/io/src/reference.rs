@@ -45,3 +45,43 @@ impl Reference {
         }
     }
 }
+
+/// Changes to some of the fields of a `Reference`, leaving the rest alone. A variable mapped to
+/// `None` is removed. In CSL-JSON, this is an object with any of the fields of a reference except
+/// `id`, where `null` removes a variable.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReferencePatch {
+    pub csl_type: Option<CslType>,
+    pub language: Option<Option<Lang>>,
+    pub ordinary: FnvHashMap<Variable, Option<String>>,
+    pub number: FnvHashMap<NumberVariable, Option<NumericValue>>,
+    pub name: FnvHashMap<NameVariable, Option<Vec<Name>>>,
+    pub date: FnvHashMap<DateVariable, Option<DateOrRange>>,
+}
+
+impl ReferencePatch {
+    pub fn apply(&self, refr: &mut Reference) {
+        fn patch<K, V>(fields: &mut FnvHashMap<K, V>, changes: &FnvHashMap<K, Option<V>>)
+        where
+            K: std::hash::Hash + Eq + Copy,
+            V: Clone,
+        {
+            for (&k, v) in changes {
+                match v {
+                    Some(v) => fields.insert(k, v.clone()),
+                    None => fields.remove(&k),
+                };
+            }
+        }
+        if let Some(csl_type) = self.csl_type {
+            refr.csl_type = csl_type;
+        }
+        if let Some(language) = &self.language {
+            refr.language = language.clone();
+        }
+        patch(&mut refr.ordinary, &self.ordinary);
+        patch(&mut refr.number, &self.number);
+        patch(&mut refr.name, &self.name);
+        patch(&mut refr.date, &self.date);
+    }
+}
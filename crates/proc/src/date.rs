@@ -14,8 +14,8 @@ use csl::LocaleDate;
 #[cfg(test)]
 use csl::RangeDelimiter;
 use csl::{
-    BodyDate, DatePart, DatePartForm, DateParts, DateVariable, DayForm, IndependentDate, Locale,
-    LocalizedDate, MonthForm, NumberVariable, SortKey, YearForm,
+    AnyVariable, BodyDate, DatePart, DatePartForm, DateParts, DateVariable, DayForm,
+    IndependentDate, Locale, LocalizedDate, MonthForm, NumberVariable, SortKey, YearForm,
 };
 #[cfg(test)]
 use pretty_assertions::assert_eq;
@@ -31,10 +31,10 @@ enum Either<O: OutputFormat> {
 }
 
 impl<O: OutputFormat> Either<O> {
-    fn into_cite_ir(self) -> IrSum<O> {
+    fn into_cite_ir(self, var: DateVariable) -> IrSum<O> {
         match self {
             Either::Build(opt) => {
-                let content = opt.map(CiteEdgeData::Output);
+                let content = opt.map(|b| CiteEdgeData::Variable(AnyVariable::Date(var), b));
                 let gv = GroupVars::rendered_if(content.is_some());
                 (IR::Rendered(content), gv)
             }
@@ -125,7 +125,7 @@ where
             BodyDate::Indep(idate) => intermediate_generic_indep(idate, GenericContext::Cit(ctx)),
             BodyDate::Local(ldate) => intermediate_generic_local(ldate, GenericContext::Cit(ctx)),
         }
-        .map(Either::into_cite_ir)
+        .map(|either| either.into_cite_ir(self.variable()))
         .unwrap_or((IR::Rendered(None), GroupVars::rendered_if(false)))
     }
 }
@@ -7,15 +7,15 @@
 // For the query group macro expansion
 #![allow(clippy::large_enum_variant)]
 
-use fnv::FnvHashMap;
+use fnv::{FnvHashMap, FnvHashSet};
 use std::sync::Arc;
 
 use crate::disamb::{Dfa, DisambName, DisambNameData, Edge, EdgeData, FreeCondSets};
 use crate::prelude::*;
 use crate::{CiteContext, DisambPass, IrState, Proc, IR};
 use citeproc_io::output::{markup::Markup, LocalizedQuotes, OutputFormat};
-use citeproc_io::{Cite, ClusterId, Name};
-use csl::{Atom, Bibliography, Element, Position, SortKey, TextElement};
+use citeproc_io::{Cite, ClusterId, Name, Reference};
+use csl::{Atom, Bibliography, Element, Locale, NumberVariable, Position, SortKey, TextElement};
 use std::sync::Mutex;
 
 pub trait HasFormatter {
@@ -48,6 +48,26 @@ pub trait IrDatabase: CiteDatabase + LocaleDatabase + StyleDatabase + HasFormatt
 
     fn bib_item_gen0(&self, ref_id: Atom) -> Option<Arc<IrGen>>;
     fn bib_item(&self, ref_id: Atom) -> Arc<MarkupOutput>;
+    /// For styles with `second-field-align`, the first field of a bibliography entry and the
+    /// rest of it, rendered separately.
+    fn bib_item_first_field(&self, ref_id: Atom) -> Option<Arc<(MarkupOutput, MarkupOutput)>>;
+
+    /// Bibliography entries for one section of the document, numbered within the section.
+    fn section_bib_item_gen0(&self, section: u32, ref_id: Atom) -> Option<Arc<IrGen>>;
+    fn section_bib_item(&self, section: u32, ref_id: Atom) -> Arc<MarkupOutput>;
+    fn section_bib_item_first_field(
+        &self,
+        section: u32,
+        ref_id: Atom,
+    ) -> Option<Arc<(MarkupOutput, MarkupOutput)>>;
+    /// Bibliography entries for a named bibliography, numbered within it.
+    fn named_bib_item_gen0(&self, name: Atom, ref_id: Atom) -> Option<Arc<IrGen>>;
+    fn named_bib_item(&self, name: Atom, ref_id: Atom) -> Arc<MarkupOutput>;
+    fn named_bib_item_first_field(
+        &self,
+        name: Atom,
+        ref_id: Atom,
+    ) -> Option<Arc<(MarkupOutput, MarkupOutput)>>;
 
     fn branch_runs(&self) -> Arc<FreeCondSets>;
 
@@ -74,6 +94,12 @@ pub trait IrDatabase: CiteDatabase + LocaleDatabase + StyleDatabase + HasFormatt
 
     #[salsa::invoke(crate::sort::sorted_refs)]
     fn sorted_refs(&self) -> Arc<(Vec<Atom>, FnvHashMap<Atom, u32>)>;
+    /// The references cited in one section of the document, numbered within it.
+    #[salsa::invoke(crate::sort::section_refs)]
+    fn section_refs(&self, section: u32) -> Arc<(Vec<Atom>, FnvHashMap<Atom, u32>)>;
+    /// The part of `sorted_refs` that passes a named bibliography's filter, numbered within it.
+    #[salsa::invoke(crate::sort::filtered_refs)]
+    fn filtered_refs(&self, name: Atom) -> Arc<(Vec<Atom>, FnvHashMap<Atom, u32>)>;
     #[salsa::invoke(crate::sort::sort_string_citation)]
     fn sort_string_citation(&self, ref_id: Atom, macro_name: Atom) -> Arc<String>;
     #[salsa::invoke(crate::sort::sort_string_bibliography)]
@@ -277,6 +303,11 @@ impl IrGen {
             matching_refs,
         }
     }
+    /// The reference's number variables that a `<number>` printed as written while rendering
+    /// this, because they weren't numbers.
+    pub fn non_numeric(&self) -> &FnvHashSet<NumberVariable> {
+        &self.state.non_numeric
+    }
     fn unambiguous(&self) -> bool {
         self.matching_refs.len() <= 1
     }
@@ -298,7 +329,8 @@ impl PartialEq<IrGen> for IrGen {
 
 fn ref_not_found(db: &impl IrDatabase, ref_id: &Atom, log: bool) -> Arc<IrGen> {
     if log {
-        eprintln!("citeproc-rs: reference {} not found", ref_id);
+        // Also reported by `Processor::diagnostics`
+        warn!("reference {} not found", ref_id);
     }
     Arc::new(IrGen::new(
         IR::Rendered(Some(CiteEdgeData::Output(db.get_formatter().plain("???")))),
@@ -345,8 +377,10 @@ fn is_unambiguous(
 ) -> bool {
     let edges = ir.to_edge_stream(&db.get_formatter());
     let mut n = 0;
-    for k in db.cited_keys().iter() {
-        let dfa = db.ref_dfa(k.clone()).expect("cited_keys should all exist");
+    for k in db.disamb_participants().iter() {
+        let dfa = db
+            .ref_dfa(k.clone())
+            .expect("disamb_participants should all exist");
         let acc = dfa.accepts_data(db, &edges);
         if acc {
             n += 1;
@@ -367,8 +401,10 @@ fn refs_accepting_cite<O: OutputFormat>(
     use log::Level::{Info, Warn};
     let edges = ir.to_edge_stream(&db.get_formatter());
     let mut v = Vec::with_capacity(1);
-    for k in db.cited_keys().iter() {
-        let dfa = db.ref_dfa(k.clone()).expect("cited_keys should all exist");
+    for k in db.disamb_participants().iter() {
+        let dfa = db
+            .ref_dfa(k.clone())
+            .expect("disamb_participants should all exist");
         let acc = dfa.accepts_data(db, &edges);
         if acc {
             v.push(k.clone());
@@ -520,7 +556,9 @@ fn disambiguate_add_names(
         }
         let mut dfas = Vec::with_capacity(best as usize);
         for k in &initial_refs {
-            let dfa = db.ref_dfa(k.clone()).expect("cited_keys should all exist");
+            let dfa = db
+                .ref_dfa(k.clone())
+                .expect("disamb_participants should all exist");
             dfas.push(dfa);
         }
 
@@ -858,13 +896,16 @@ fn built_cluster(
         .filter_map(|&id| {
             let gen4 = db.ir_gen4_conditionals(id);
             let ir = &gen4.ir;
-            let cite = id.lookup(db);
+            let (_, index, cite) = db.lookup_cite(id);
             let flattened = ir.flatten(&fmt)?;
             let aff = Affixes {
                 prefix: Atom::from(cite.prefix.as_ref().map(AsRef::as_ref).unwrap_or("")),
                 suffix: Atom::from(cite.suffix.as_ref().map(AsRef::as_ref).unwrap_or("")),
             };
-            Some(fmt.affixed(flattened, Some(&aff)))
+            let affixed = fmt.affixed(flattened, Some(&aff));
+            // Only link to bibliography entries that will actually be there
+            let link = style.bibliography.is_some() && db.reference(cite.ref_id.clone()).is_some();
+            Some(fmt.cite_span(affixed, cite_anchor(cluster_id, index), &cite.ref_id, link))
         })
         .collect();
     let build = fmt.with_format(
@@ -877,6 +918,11 @@ fn built_cluster(
     Arc::new(fmt.output(build))
 }
 
+/// The id a cite is given in linked HTML output, unique within the document.
+pub(crate) fn cite_anchor(cluster_id: ClusterId, index: u32) -> String {
+    format!("{}-{}", cluster_id, index)
+}
+
 // TODO: intermediate layer before bib_item, which is before subsequent-author-substitute. Then
 // mutate.
 
@@ -887,14 +933,26 @@ pub fn with_bib_context<T>(
     sort_key: Option<SortKey>,
     f: impl Fn(&Bibliography, CiteContext) -> T,
 ) -> Option<T> {
-    let style = db.style();
     let locale = db.locale_by_reference(ref_id.clone());
-    let cite = Cite::basic(ref_id.clone());
     let refr = db.reference(ref_id)?;
+    with_reference_bib_context(db, &refr, &locale, bib_number, sort_key, f)
+}
+
+/// Like `with_bib_context`, for a reference that doesn't have to be in the library.
+pub fn with_reference_bib_context<T>(
+    db: &impl IrDatabase,
+    refr: &Reference,
+    locale: &Locale,
+    bib_number: Option<u32>,
+    sort_key: Option<SortKey>,
+    f: impl Fn(&Bibliography, CiteContext) -> T,
+) -> Option<T> {
+    let style = db.style();
+    let cite = Cite::basic(refr.id.clone());
     let (names_delimiter, name_el) = db.name_info_bibliography();
     if let Some(bib) = &style.bibliography {
         let ctx = CiteContext {
-            reference: &refr,
+            reference: refr,
             format: db.get_formatter(),
             cite_id: None,
             cite: &cite,
@@ -902,7 +960,7 @@ pub fn with_bib_context<T>(
             citation_number: 0,
             disamb_pass: None,
             style: &style,
-            locale: &locale,
+            locale,
             bib_number,
             in_bibliography: true,
             names_delimiter,
@@ -921,7 +979,32 @@ fn bib_item_gen0(db: &impl IrDatabase, ref_id: Atom) -> Option<Arc<IrGen>> {
     let bib_number = *citation_numbers_by_id
         .get(&ref_id)
         .expect("sorted_refs should contain a bib_item key");
+    numbered_bib_item_gen0(db, ref_id, bib_number)
+}
+
+fn section_bib_item_gen0(db: &impl IrDatabase, section: u32, ref_id: Atom) -> Option<Arc<IrGen>> {
+    let section_refs = db.section_refs(section);
+    let bib_number = *section_refs
+        .1
+        .get(&ref_id)
+        .expect("section_refs should contain a section_bib_item key");
+    numbered_bib_item_gen0(db, ref_id, bib_number)
+}
 
+fn named_bib_item_gen0(db: &impl IrDatabase, name: Atom, ref_id: Atom) -> Option<Arc<IrGen>> {
+    let filtered_refs = db.filtered_refs(name);
+    let bib_number = *filtered_refs
+        .1
+        .get(&ref_id)
+        .expect("filtered_refs should contain a named_bib_item key");
+    numbered_bib_item_gen0(db, ref_id, bib_number)
+}
+
+fn numbered_bib_item_gen0(
+    db: &impl IrDatabase,
+    ref_id: Atom,
+    bib_number: u32,
+) -> Option<Arc<IrGen>> {
     with_bib_context(
         db,
         ref_id.clone(),
@@ -951,19 +1034,121 @@ fn bib_item_gen0(db: &impl IrDatabase, ref_id: Atom) -> Option<Arc<IrGen>> {
 }
 
 fn bib_item(db: &impl IrDatabase, ref_id: Atom) -> Arc<MarkupOutput> {
+    render_bib_item(db, ref_id.clone(), db.bib_item_gen0(ref_id))
+}
+
+fn section_bib_item(db: &impl IrDatabase, section: u32, ref_id: Atom) -> Arc<MarkupOutput> {
+    render_bib_item(
+        db,
+        ref_id.clone(),
+        db.section_bib_item_gen0(section, ref_id),
+    )
+}
+
+fn named_bib_item(db: &impl IrDatabase, name: Atom, ref_id: Atom) -> Arc<MarkupOutput> {
+    render_bib_item(db, ref_id.clone(), db.named_bib_item_gen0(name, ref_id))
+}
+
+fn render_bib_item(
+    db: &impl IrDatabase,
+    ref_id: Atom,
+    gen0: Option<Arc<IrGen>>,
+) -> Arc<MarkupOutput> {
     let fmt = db.get_formatter();
     let style = db.style();
-    if let Some(gen0) = db.bib_item_gen0(ref_id) {
+    if let Some(gen0) = gen0 {
         let layout = &style.bibliography.as_ref().unwrap().layout;
         let ir = &gen0.ir;
         let flat = ir.flatten(&fmt).unwrap_or_else(|| fmt.plain(""));
-        let build = fmt.with_format(
+        let mut build = fmt.with_format(
             fmt.affixed(flat, layout.affixes.as_ref()),
             layout.formatting,
         );
+        if let Some(refr) = db.reference(ref_id.clone()) {
+            build = fmt.entry_metadata(build, &refr);
+        }
+        if fmt.links_citations() {
+            let cites_by_ref = db.cites_by_ref();
+            let backrefs = cites_by_ref
+                .get(&ref_id)
+                .map(|ids| {
+                    ids.iter()
+                        .map(|&id| {
+                            let (cluster_id, index, _) = db.lookup_cite(id);
+                            cite_anchor(cluster_id, index)
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+            build = fmt.bib_entry(build, &ref_id, backrefs);
+        }
         Arc::new(fmt.output(build))
     } else {
         // Whatever
         Arc::new(fmt.output(fmt.plain("")))
     }
 }
+
+/// The first field is the first child of the layout that renders anything. The layout's prefix
+/// goes on the first field and its suffix on the rest. Neither part gets the cite links or
+/// embedded metadata that `bib_item` adds to the whole entry.
+fn bib_item_first_field(
+    db: &impl IrDatabase,
+    ref_id: Atom,
+) -> Option<Arc<(MarkupOutput, MarkupOutput)>> {
+    split_first_field(db, db.bib_item_gen0(ref_id)?)
+}
+
+fn section_bib_item_first_field(
+    db: &impl IrDatabase,
+    section: u32,
+    ref_id: Atom,
+) -> Option<Arc<(MarkupOutput, MarkupOutput)>> {
+    split_first_field(db, db.section_bib_item_gen0(section, ref_id)?)
+}
+
+fn named_bib_item_first_field(
+    db: &impl IrDatabase,
+    name: Atom,
+    ref_id: Atom,
+) -> Option<Arc<(MarkupOutput, MarkupOutput)>> {
+    split_first_field(db, db.named_bib_item_gen0(name, ref_id)?)
+}
+
+fn split_first_field(
+    db: &impl IrDatabase,
+    gen0: Arc<IrGen>,
+) -> Option<Arc<(MarkupOutput, MarkupOutput)>> {
+    let fmt = db.get_formatter();
+    let style = db.style();
+    let bib = style.bibliography.as_ref()?;
+    if bib.second_field_align.is_none() {
+        return None;
+    }
+    let seq = match &gen0.ir {
+        IR::Seq(seq) => seq,
+        _ => return None,
+    };
+    let (index, first) = seq
+        .contents
+        .iter()
+        .enumerate()
+        .find_map(|(i, ir)| ir.flatten(&fmt).map(|flat| (i, flat)))?;
+    let rest = IR::Seq(IrSeq {
+        contents: seq.contents[index + 1..].to_vec(),
+        ..seq.clone()
+    });
+    let rest = rest.flatten(&fmt).unwrap_or_else(|| fmt.plain(""));
+    let layout = &bib.layout;
+    let prefix = layout.affixes.as_ref().map(|a| Affixes {
+        prefix: a.prefix.clone(),
+        suffix: "".into(),
+    });
+    let suffix = layout.affixes.as_ref().map(|a| Affixes {
+        prefix: "".into(),
+        suffix: a.suffix.clone(),
+    });
+    let first = fmt.with_format(fmt.affixed(first, prefix.as_ref()), layout.formatting);
+    let rest = fmt.with_format(fmt.affixed(rest, suffix.as_ref()), layout.formatting);
+    Some(Arc::new((fmt.output(first), fmt.output(rest))))
+}
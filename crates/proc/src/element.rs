@@ -153,8 +153,13 @@ where
                 } else {
                     state.maybe_suppress_num(var);
                     ctx.get_number(var)
-                        .map(|val| renderer.number(number, &val))
-                        .map(CiteEdgeData::Output)
+                        .map(|val| {
+                            if !val.is_numeric() {
+                                state.non_numeric.insert(var);
+                            }
+                            renderer.number(number, &val)
+                        })
+                        .map(|b| CiteEdgeData::Variable(AnyVariable::Number(var), b))
                 };
                 let gv = GroupVars::rendered_if(content.is_some());
                 (IR::Rendered(content), gv)
@@ -10,7 +10,7 @@ use citeproc_io::output::markup::Markup;
 use citeproc_io::output::LocalizedQuotes;
 use csl::Atom;
 use csl::{Affixes, BodyDate, Choose, Element, Formatting, GivenNameDisambiguationRule};
-use csl::{NumberVariable, StandardVariable, Variable};
+use csl::{AnyVariable, NumberVariable, StandardVariable, Variable};
 
 use std::sync::Arc;
 
@@ -185,30 +185,34 @@ pub enum CiteEdgeData<O: OutputFormat = Markup> {
     CitationNumberLabel(O::Build),
     Frnn(O::Build),
     FrnnLabel(O::Build),
+    /// Any other variable's output. Renders the same as `Output`, but remembers where it came
+    /// from, so it can be traced back in `crate::semantic`.
+    Variable(AnyVariable, O::Build),
 }
 
 impl<O: OutputFormat> CiteEdgeData<O> {
-    pub fn from_number_variable(var: NumberVariable, label: bool) -> fn(O::Build) -> Self {
-        match (var, label) {
-            (NumberVariable::Locator, false) => CiteEdgeData::Locator,
-            (NumberVariable::Locator, true) => CiteEdgeData::LocatorLabel,
-            (NumberVariable::FirstReferenceNoteNumber, false) => CiteEdgeData::Frnn,
-            (NumberVariable::FirstReferenceNoteNumber, true) => CiteEdgeData::FrnnLabel,
-            (NumberVariable::CitationNumber, false) => CiteEdgeData::CitationNumber,
-            (NumberVariable::CitationNumber, true) => CiteEdgeData::CitationNumberLabel,
-            _ => CiteEdgeData::Output,
+    pub fn from_number_variable(var: NumberVariable, label: bool) -> impl Fn(O::Build) -> Self {
+        move |b| match (var, label) {
+            (NumberVariable::Locator, false) => CiteEdgeData::Locator(b),
+            (NumberVariable::Locator, true) => CiteEdgeData::LocatorLabel(b),
+            (NumberVariable::FirstReferenceNoteNumber, false) => CiteEdgeData::Frnn(b),
+            (NumberVariable::FirstReferenceNoteNumber, true) => CiteEdgeData::FrnnLabel(b),
+            (NumberVariable::CitationNumber, false) => CiteEdgeData::CitationNumber(b),
+            (NumberVariable::CitationNumber, true) => CiteEdgeData::CitationNumberLabel(b),
+            (_, false) => CiteEdgeData::Variable(AnyVariable::Number(var), b),
+            (_, true) => CiteEdgeData::Output(b),
         }
     }
-    pub fn from_ordinary_variable(var: Variable) -> fn(O::Build) -> Self {
-        match var {
-            Variable::YearSuffix => CiteEdgeData::YearSuffix,
-            _ => CiteEdgeData::Output,
+    pub fn from_ordinary_variable(var: Variable) -> impl Fn(O::Build) -> Self {
+        move |b| match var {
+            Variable::YearSuffix => CiteEdgeData::YearSuffix(b),
+            _ => CiteEdgeData::Variable(AnyVariable::Ordinary(var), b),
         }
     }
-    pub fn from_standard_variable(var: StandardVariable, label: bool) -> fn(O::Build) -> Self {
-        match var {
-            StandardVariable::Number(nv) => CiteEdgeData::from_number_variable(nv, label),
-            StandardVariable::Ordinary(v) => CiteEdgeData::from_ordinary_variable(v),
+    pub fn from_standard_variable(var: StandardVariable, label: bool) -> impl Fn(O::Build) -> Self {
+        move |b| match var {
+            StandardVariable::Number(nv) => Self::from_number_variable(nv, label)(b),
+            StandardVariable::Ordinary(v) => Self::from_ordinary_variable(v)(b),
         }
     }
 }
@@ -348,7 +352,7 @@ impl<O: OutputFormat<Output = String>> IrSeq<O> {
 impl<O: OutputFormat<Output = String>> CiteEdgeData<O> {
     pub(crate) fn to_edge_data(&self, fmt: &O, formatting: Formatting) -> EdgeData {
         match self {
-            CiteEdgeData::Output(x) => {
+            CiteEdgeData::Output(x) | CiteEdgeData::Variable(_, x) => {
                 EdgeData::Output(fmt.output_in_context(x.clone(), formatting))
             }
             CiteEdgeData::YearSuffix(_) => EdgeData::YearSuffix,
@@ -360,9 +364,10 @@ impl<O: OutputFormat<Output = String>> CiteEdgeData<O> {
             CiteEdgeData::CitationNumberLabel(_) => EdgeData::CitationNumberLabel,
         }
     }
-    fn inner(&self) -> O::Build {
+    pub(crate) fn inner(&self) -> O::Build {
         match self {
             CiteEdgeData::Output(x)
+            | CiteEdgeData::Variable(_, x)
             | CiteEdgeData::YearSuffix(x)
             | CiteEdgeData::Frnn(x)
             | CiteEdgeData::FrnnLabel(x)
@@ -6,6 +6,8 @@
 
 #[macro_use]
 extern crate log;
+#[macro_use]
+extern crate serde_derive;
 
 // #[macro_use]
 extern crate citeproc_db;
@@ -28,7 +30,9 @@ mod names;
 mod number;
 mod page_range;
 mod renderer;
+pub mod semantic;
 mod sort;
+pub mod standalone;
 mod unicode;
 mod walker;
 
@@ -194,6 +198,8 @@ pub struct IrState {
     pub name_override: NameOverrider,
     suppressed: FnvHashSet<AnyVariable>,
     pub disamb_count: u32,
+    /// Number variables that a `<number>` printed as written, because they weren't numbers.
+    pub non_numeric: FnvHashSet<NumberVariable>,
 }
 
 #[derive(Default, Debug, PartialEq, Eq, Clone)]
@@ -7,6 +7,7 @@ use csl::{
     NumberElement, NumberVariable, NumericForm, Plural, RoleTermSelector, SortKey,
     StandardVariable, Style, TextCase, TextElement, TextTermSelector, Variable,
 };
+use std::borrow::Cow;
 
 #[derive(Clone)]
 pub enum GenericContext<'a, O: OutputFormat, I: OutputFormat = O> {
@@ -335,7 +336,10 @@ impl<'c, O: OutputFormat, I: OutputFormat> Renderer<'c, O, I> {
             quotes: self.quotes(),
             strip_periods: text.strip_periods,
             is_english: self.ctx.is_english(),
-            ..Default::default()
+            link_bare_urls: match var {
+                StandardVariable::Ordinary(v) => v.may_contain_urls(),
+                StandardVariable::Number(_) => false,
+            },
         };
         let hyper = match var {
             StandardVariable::Ordinary(v) => Some(v),
@@ -388,8 +392,12 @@ impl<'c, O: OutputFormat, I: OutputFormat> Renderer<'c, O, I> {
         let mut b = fmt.ingest(string, &options);
         b = fmt.with_format(b, text.formatting);
         if let Some(hyper) = hyper {
-            let maybe_link = hyper.hyperlink(string);
-            b = fmt.hyperlinked(b, maybe_link)
+            let maybe_link = if fmt.links_urls() {
+                hyper.resolved_hyperlink(string)
+            } else {
+                hyper.hyperlink(string).map(Cow::Borrowed)
+            };
+            b = fmt.hyperlinked(b, maybe_link.as_ref().map(AsRef::as_ref))
         }
         b = fmt.affixed_quoted(b, text.affixes.as_ref(), self.quotes_if(text.quotes));
         fmt.with_display(b, text.display, self.ctx.in_bibliography())
@@ -0,0 +1,208 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright © 2019 Corporation for Digital Scholarship
+
+//! A serializable tree of formatted spans for a cluster, built from the final (disambiguated)
+//! IR of each cite before it is flattened. Each span records which variable produced it, and
+//! whether it only appears because of disambiguation, so editors can offer click-to-edit and
+//! tooltips on rendered citations.
+
+use crate::db::cite_anchor;
+use crate::prelude::*;
+use citeproc_io::ClusterId;
+use csl::{AnyVariable, Atom};
+
+/// What kind of style element a span came from.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SpanKind {
+    /// Terms, values, delimiters and anything else that isn't a variable.
+    Text,
+    /// A single variable, which is named in `SemanticSpan::variable`.
+    Variable,
+    /// A label for a number variable, like `p.` for the locator.
+    Label,
+    /// A `<names>` block. All of its children render that name variable.
+    Names,
+    /// A `<choose>` that may be re-rendered with `disambiguate="true"`.
+    Conditional,
+    /// A group, macro or any other sequence of spans.
+    Group,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SemanticSpan {
+    pub kind: SpanKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub variable: Option<String>,
+    /// True if this span was added or expanded to disambiguate the cite, e.g. year suffixes,
+    /// extra names, or the output of a `disambiguate="true"` branch.
+    #[serde(skip_serializing_if = "is_false")]
+    pub disambiguated: bool,
+    /// This span's output in the processor's format, including its own formatting and affixes.
+    pub output: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<SemanticSpan>,
+}
+
+/// The spans for one cite in a cluster. `index` is the cite's position in `Cluster::cites`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CiteSpans {
+    /// Identifies the cite within the document. Linked HTML output gives the cite's element
+    /// the same id in `data-cite-id`.
+    pub cite_id: String,
+    pub index: u32,
+    pub ref_id: Atom,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub suffix: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub span: Option<SemanticSpan>,
+}
+
+fn is_false(b: &bool) -> bool {
+    !*b
+}
+
+fn variable_name(var: AnyVariable) -> String {
+    match var {
+        AnyVariable::Ordinary(v) => v.as_ref().to_owned(),
+        AnyVariable::Name(v) => v.as_ref().to_owned(),
+        AnyVariable::Date(v) => v.as_ref().to_owned(),
+        AnyVariable::Number(v) => v.as_ref().to_owned(),
+    }
+}
+
+impl SemanticSpan {
+    fn leaf(kind: SpanKind, variable: Option<&str>, disambiguated: bool, output: String) -> Self {
+        SemanticSpan {
+            kind,
+            variable: variable.map(String::from),
+            disambiguated,
+            output,
+            children: Vec::new(),
+        }
+    }
+}
+
+impl CiteEdgeData<Markup> {
+    fn to_span(&self, fmt: &Markup) -> SemanticSpan {
+        let output = fmt.output(self.inner());
+        match self {
+            CiteEdgeData::Output(_) => SemanticSpan::leaf(SpanKind::Text, None, false, output),
+            CiteEdgeData::Variable(var, _) => SemanticSpan::leaf(
+                SpanKind::Variable,
+                Some(&variable_name(*var)),
+                false,
+                output,
+            ),
+            CiteEdgeData::Locator(_) => {
+                SemanticSpan::leaf(SpanKind::Variable, Some("locator"), false, output)
+            }
+            CiteEdgeData::LocatorLabel(_) => {
+                SemanticSpan::leaf(SpanKind::Label, Some("locator"), false, output)
+            }
+            CiteEdgeData::YearSuffix(_) => {
+                SemanticSpan::leaf(SpanKind::Variable, Some("year-suffix"), true, output)
+            }
+            CiteEdgeData::CitationNumber(_) => {
+                SemanticSpan::leaf(SpanKind::Variable, Some("citation-number"), false, output)
+            }
+            CiteEdgeData::CitationNumberLabel(_) => {
+                SemanticSpan::leaf(SpanKind::Label, Some("citation-number"), false, output)
+            }
+            CiteEdgeData::Frnn(_) => SemanticSpan::leaf(
+                SpanKind::Variable,
+                Some("first-reference-note-number"),
+                false,
+                output,
+            ),
+            CiteEdgeData::FrnnLabel(_) => SemanticSpan::leaf(
+                SpanKind::Label,
+                Some("first-reference-note-number"),
+                false,
+                output,
+            ),
+        }
+    }
+}
+
+impl IR<Markup> {
+    /// Builds the span tree for this IR. Returns `None` if it renders nothing, like `flatten`.
+    pub fn to_semantic(&self, fmt: &Markup) -> Option<SemanticSpan> {
+        match self {
+            IR::Rendered(None) | IR::YearSuffix(_, None) => None,
+            IR::Rendered(Some(ed)) => Some(ed.to_span(fmt)),
+            IR::YearSuffix(_, Some(b)) => Some(SemanticSpan::leaf(
+                SpanKind::Variable,
+                Some("year-suffix"),
+                true,
+                fmt.output(b.clone()),
+            )),
+            IR::Name(nir) => {
+                let nir = nir.lock().unwrap();
+                Some(SemanticSpan {
+                    kind: SpanKind::Names,
+                    variable: Some(nir.variable.as_ref().to_owned()),
+                    // Names added to the et-al list during disambiguation
+                    disambiguated: nir.name_counter.bump > 0,
+                    output: fmt.output(nir.ir.flatten(fmt)?),
+                    children: nir.ir.to_semantic(fmt).into_iter().collect(),
+                })
+            }
+            IR::ConditionalDisamb(c) => {
+                let c = c.lock().unwrap();
+                Some(SemanticSpan {
+                    kind: SpanKind::Conditional,
+                    variable: None,
+                    disambiguated: c.done,
+                    output: fmt.output(c.ir.flatten(fmt)?),
+                    children: c.ir.to_semantic(fmt).into_iter().collect(),
+                })
+            }
+            IR::Seq(seq) => {
+                let output = fmt.output(self.flatten(fmt)?);
+                let children = seq
+                    .contents
+                    .iter()
+                    .filter_map(|ir| ir.to_semantic(fmt))
+                    .collect();
+                Some(SemanticSpan {
+                    kind: SpanKind::Group,
+                    variable: None,
+                    disambiguated: false,
+                    output,
+                    children,
+                })
+            }
+        }
+    }
+}
+
+/// The span trees for every cite in a cluster, in the order they appear in it.
+///
+/// The layout's affixes and delimiter are not included; they are the same for every cluster.
+pub fn cluster_spans(db: &impl IrDatabase, cluster_id: ClusterId) -> Vec<CiteSpans> {
+    let fmt = db.get_formatter();
+    let cite_ids = db.cluster_cites(cluster_id);
+    cite_ids
+        .iter()
+        .map(|&id| {
+            let (_cluster, index, cite) = db.lookup_cite(id);
+            let gen4 = db.ir_gen4_conditionals(id);
+            CiteSpans {
+                cite_id: cite_anchor(cluster_id, index),
+                index,
+                ref_id: cite.ref_id.clone(),
+                prefix: cite.prefix.clone(),
+                suffix: cite.suffix.clone(),
+                span: gen4.ir.to_semantic(&fmt),
+            }
+        })
+        .collect()
+}
@@ -4,6 +4,7 @@ use citeproc_io::output::plain::PlainText;
 use citeproc_io::Reference;
 use csl::*;
 use fnv::FnvHashMap;
+use std::collections::HashSet;
 use std::sync::Arc;
 
 use std::cmp::Ordering;
@@ -45,6 +46,27 @@ pub fn sort_string_bibliography(
 }
 
 pub fn sorted_refs(db: &impl IrDatabase) -> Arc<(Vec<Atom>, FnvHashMap<Atom, u32>)> {
+    let all_cite_ids = db.all_cite_ids();
+    let uncited = db.uncited();
+    Arc::new(sort_and_number(db, all_cite_ids.iter(), &uncited))
+}
+
+/// Like `sorted_refs`, but only for the references cited in one section of the document, and
+/// numbered from 1. Uncited references only go in the main bibliography.
+pub fn section_refs(db: &impl IrDatabase, section: u32) -> Arc<(Vec<Atom>, FnvHashMap<Atom, u32>)> {
+    let clusters = db.clusters_sorted();
+    let cite_ids = clusters
+        .iter()
+        .filter(|cluster| cluster.section == section)
+        .flat_map(|cluster| cluster.cites.iter());
+    Arc::new(sort_and_number(db, cite_ids, &Default::default()))
+}
+
+fn sort_and_number<'a>(
+    db: &impl IrDatabase,
+    cite_ids: impl Iterator<Item = &'a CiteId>,
+    uncited: &HashSet<Atom>,
+) -> (Vec<Atom>, FnvHashMap<Atom, u32>) {
     let style = db.style();
     let bib = match style.bibliography {
         None => None,
@@ -57,10 +79,9 @@ pub fn sorted_refs(db: &impl IrDatabase) -> Arc<(Vec<Atom>, FnvHashMap<Atom, u32
     // first, compute refs in the order that they are cited.
     // stable sorting will cause this to be the final tiebreaker.
     let all = db.all_keys();
-    let all_cite_ids = db.all_cite_ids();
     let mut preordered = Vec::with_capacity(all.len());
     let mut i = 1;
-    for &id in all_cite_ids.iter() {
+    for &id in cite_ids {
         let ref_id = &id.lookup(db).ref_id;
         if all.contains(ref_id) && !citation_numbers.contains_key(ref_id) {
             preordered.push(ref_id.clone());
@@ -68,6 +89,19 @@ pub fn sorted_refs(db: &impl IrDatabase) -> Arc<(Vec<Atom>, FnvHashMap<Atom, u32
             i += 1;
         }
     }
+    // Uncited references come after all the cited ones. They have no document order, so they
+    // are ordered by id to keep the numbering stable.
+    let mut uncited: Vec<_> = uncited
+        .iter()
+        .filter(|ref_id| !citation_numbers.contains_key(*ref_id))
+        .cloned()
+        .collect();
+    uncited.sort_by(|a, b| (**a).cmp(&**b));
+    for ref_id in uncited {
+        citation_numbers.insert(ref_id.clone(), i as u32);
+        preordered.push(ref_id);
+        i += 1;
+    }
     let refs = if let Some(ref sort) = bib {
         // dbg!(sort);
         preordered.sort_by(|a, b| {
@@ -86,12 +120,37 @@ pub fn sorted_refs(db: &impl IrDatabase) -> Arc<(Vec<Atom>, FnvHashMap<Atom, u32
     for (i, ref_id) in refs.iter().enumerate() {
         citation_numbers.insert(ref_id.clone(), (i + 1) as u32);
     }
-    Arc::new((refs, citation_numbers))
+    (refs, citation_numbers)
 }
 
+/// Like `sorted_refs`, but only for the references that pass a named bibliography's filter, and
+/// numbered from 1.
+pub fn filtered_refs(db: &impl IrDatabase, name: Atom) -> Arc<(Vec<Atom>, FnvHashMap<Atom, u32>)> {
+    let filter = db.bibliography_filter(name);
+    let all = db.all_keys();
+    let accepts =
+        |ref_id: &Atom| all.contains(ref_id) && filter.accepts(&db.reference_input(ref_id.clone()));
+    let all_cite_ids = db.all_cite_ids();
+    let cite_ids = all_cite_ids
+        .iter()
+        .filter(|&&id| accepts(&id.lookup(db).ref_id));
+    let uncited: HashSet<Atom> = db
+        .uncited()
+        .iter()
+        .filter(|ref_id| accepts(ref_id))
+        .cloned()
+        .collect();
+    Arc::new(sort_and_number(db, cite_ids, &uncited))
+}
+
+/// Once the document has sections, cites are numbered within their own section.
 pub fn bib_number(db: &impl IrDatabase, id: CiteId) -> Option<u32> {
-    let cite = id.lookup(db);
-    let arc = db.sorted_refs();
+    let (cluster_id, _, cite) = db.lookup_cite(id);
+    let arc = if db.section_starts().is_empty() {
+        db.sorted_refs()
+    } else {
+        db.section_refs(db.cluster_section(cluster_id)?)
+    };
     let (_, ref lookup_ref_ids) = &*arc;
     lookup_ref_ids.get(&cite.ref_id).cloned()
 }
@@ -0,0 +1,91 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright © 2019 Corporation for Digital Scholarship
+
+//! Renders a single reference on its own, for list views and search results. The reference
+//! doesn't need to be in the library, and the document's clusters and bibliography are not
+//! consulted or changed. So there is no disambiguation, no year suffixes, and no citation
+//! numbers.
+//!
+//! Nothing here is memoized, but it only ever renders one IR tree per call, so it is cheap.
+
+use crate::db::with_reference_bib_context;
+use crate::prelude::*;
+use citeproc_io::{Cite, Reference};
+use csl::{Locale, Position};
+use std::sync::Arc;
+
+type MarkupOutput = <Markup as OutputFormat>::Output;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StandaloneReference {
+    /// The reference cited alone, as if it were the first cite in the document.
+    pub citation: MarkupOutput,
+    /// The reference's bibliography entry. None if the style has no bibliography.
+    pub bib_entry: Option<MarkupOutput>,
+}
+
+fn locale_for(db: &impl IrDatabase, refr: &Reference) -> Arc<Locale> {
+    refr.language
+        .clone()
+        .map(|lang| db.merged_locale(lang))
+        .unwrap_or_else(|| db.default_locale())
+}
+
+pub fn standalone_reference(db: &impl IrDatabase, refr: &Reference) -> StandaloneReference {
+    StandaloneReference {
+        citation: standalone_citation(db, refr),
+        bib_entry: standalone_bib_entry(db, refr),
+    }
+}
+
+pub fn standalone_citation(db: &impl IrDatabase, refr: &Reference) -> MarkupOutput {
+    let style = db.style();
+    let locale = locale_for(db, refr);
+    let fmt = db.get_formatter();
+    let cite = Cite::basic(refr.id.clone());
+    let (names_delimiter, name_el) = db.name_info_citation();
+    let ctx = CiteContext {
+        reference: refr,
+        format: fmt.clone(),
+        cite_id: None,
+        cite: &cite,
+        position: (Position::First, None),
+        citation_number: 0,
+        disamb_pass: None,
+        style: &style,
+        locale: &locale,
+        bib_number: None,
+        in_bibliography: false,
+        names_delimiter,
+        name_citation: name_el,
+        sort_key: None,
+    };
+    let mut state = IrState::new();
+    let ir = style.intermediate(db, &mut state, &ctx).0;
+    let layout = &style.citation.layout;
+    let flat = ir.flatten(&fmt).unwrap_or_else(|| fmt.plain(""));
+    let build = fmt.with_format(
+        fmt.affixed(flat, layout.affixes.as_ref()),
+        layout.formatting,
+    );
+    fmt.output(build)
+}
+
+pub fn standalone_bib_entry(db: &impl IrDatabase, refr: &Reference) -> Option<MarkupOutput> {
+    let locale = locale_for(db, refr);
+    with_reference_bib_context(db, refr, &locale, None, None, |bib, ctx| {
+        let fmt = &ctx.format;
+        let mut state = IrState::new();
+        let ir = bib.intermediate(db, &mut state, &ctx).0;
+        let flat = ir.flatten(fmt).unwrap_or_else(|| fmt.plain(""));
+        let build = fmt.with_format(
+            fmt.affixed(flat, bib.layout.affixes.as_ref()),
+            bib.layout.formatting,
+        );
+        fmt.output(build)
+    })
+}
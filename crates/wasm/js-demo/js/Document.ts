@@ -36,14 +36,17 @@ export class RenderedDocument {
                 if (entry_ids != null) {
                     draft.bibliographyIds = entry_ids;
                 }
-                for (let key of Object.keys(bib.updatedEntries)) {
-                    draft.bibliography[key] = bib.updatedEntries[key];
+                for (let entry of bib.updatedEntries) {
+                    draft.bibliography[entry.id] = entry.value;
                 }
             }
             for (let [id, built] of summary.clusters) {
                 draft.builtClusters[id] = built;
                 draft.updatedLastRevision[id] = true;
             }
+            for (let id of summary.removedClusters) {
+                delete draft.builtClusters[id];
+            }
         });
         return neu;
     }
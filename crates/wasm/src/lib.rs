@@ -14,9 +14,9 @@ extern crate log;
 
 use self::utils::ErrorPlaceholder;
 
-use js_sys::{Error as JsError, Promise};
+use js_sys::{Error as JsError, Function, Promise};
 use serde::Serialize;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::error::Error;
 use std::rc::Rc;
 use std::str::FromStr;
@@ -25,22 +25,23 @@ use wasm_bindgen::prelude::*;
 use wasm_bindgen_futures::{future_to_promise, JsFuture};
 
 use citeproc::prelude::*;
-use citeproc::{ClusterPosition, Processor};
+use citeproc::{ClusterPosition, Edit, Processor, ProcessorSnapshot, UpdateListener};
 use csl::Lang;
 
 #[wasm_bindgen]
 pub struct Driver {
     engine: Rc<RefCell<Processor>>,
     fetcher: Lifecycle,
+    notifier: Notifier,
 }
 
 #[wasm_bindgen]
 impl Driver {
     /// Creates a new Driver.
     ///
-    /// * `style` is a CSL style as a string. Independent styles only.
+    /// * `style` is a CSL style as a string. Independent styles only; see `newDependent`.
     /// * `lifecycle` must implement the `Lifecycle` interface
-    /// * `format` is one of { "html", "rtf" }
+    /// * `format` is one of { "html", "html-linked", "rtf" }
     ///
     /// Throws an error if it cannot parse the style you gave it.
     pub fn new(style: &str, lifecycle: Lifecycle, format: &str) -> Result<Driver, JsValue> {
@@ -61,25 +62,169 @@ impl Driver {
         Ok(Driver {
             engine,
             fetcher: lifecycle,
+            notifier: Notifier::default(),
         })
     }
 
+    /// Creates a Driver for a dependent style, which takes its rendering rules from `parent`
+    /// and its metadata and default locale from `style`. Use `Driver.parentStyleId` to find out
+    /// which parent to fetch. The other arguments are as for the constructor.
+    ///
+    /// Throws an error if either style can't be parsed, or `parent` is not the style's parent.
+    #[wasm_bindgen(js_name = "newDependent")]
+    pub fn new_dependent(
+        style: &str,
+        parent: &str,
+        lifecycle: Lifecycle,
+        format: &str,
+    ) -> Result<Driver, JsValue> {
+        utils::set_panic_hook();
+        utils::init_log();
+
+        let parent_id = Driver::parent_style_id(style)?
+            .ok_or_else(|| JsError::new("style is not a dependent style"))?;
+        let mut parents = std::collections::HashMap::new();
+        parents.insert(parent_id, parent.to_owned());
+        let us_fetcher = Arc::new(utils::USFetcher);
+        let format = SupportedFormat::from_str(format)
+            .map_err(|_| JsError::new(&format!("unknown format `{}`", format)))?;
+        let engine = Processor::with_style_fetcher(
+            style,
+            us_fetcher,
+            Arc::new(PredefinedStyles(parents)),
+            true,
+            format,
+        )
+        .map(RefCell::new)
+        .map(Rc::new)
+        .map_err(|e| JsError::new(&serde_json::to_string(&e).unwrap()))?;
+        Ok(Driver {
+            engine,
+            fetcher: lifecycle,
+            notifier: Notifier::default(),
+        })
+    }
+
+    /// The id of the independent parent of a dependent style, or `undefined` if `style` is an
+    /// independent style. Style ids are usually URLs, like `http://www.zotero.org/styles/apa`.
+    ///
+    /// Throws an error if it cannot parse the style you gave it.
+    #[wasm_bindgen(js_name = "parentStyleId")]
+    pub fn parent_style_id(style: &str) -> Result<Option<String>, JsValue> {
+        let style = csl::AnyStyle::from_str(style)
+            .map_err(|e| JsError::new(&serde_json::to_string(&e).unwrap()))?;
+        Ok(match style {
+            csl::AnyStyle::Dependent(d) => Some(d.parent_id().to_owned()),
+            csl::AnyStyle::Independent(_) => None,
+        })
+    }
+
+    /// Reads the `<info>` of any style, dependent or independent, without creating a Driver.
+    /// Much quicker than a full parse, for showing a list of styles to pick from.
+    ///
+    /// * returns a `StyleInfo`
+    ///
+    /// Throws an error if it cannot parse the style you gave it.
+    #[wasm_bindgen(js_name = "readStyleInfo")]
+    pub fn read_style_info(style: &str) -> Result<JsValue, JsValue> {
+        let info = csl::Info::from_style_text(style)
+            .map_err(|e| JsError::new(&serde_json::to_string(&e).unwrap()))?;
+        Ok(JsValue::from_serde(&info).unwrap())
+    }
+
+    /// Creates a Driver from a snapshot taken with `takeSnapshot`, which renders exactly like the
+    /// one it was taken from. The other arguments are as for the constructor.
+    ///
+    /// Throws an error if the snapshot is from an incompatible version, or can't be read.
+    #[wasm_bindgen(js_name = "fromSnapshot")]
+    pub fn from_snapshot(
+        snapshot: JsValue,
+        lifecycle: Lifecycle,
+        format: &str,
+    ) -> Result<Driver, JsValue> {
+        utils::set_panic_hook();
+        utils::init_log();
+
+        let snapshot: ProcessorSnapshot = snapshot.into_serde().map_err(|e| {
+            ErrorPlaceholder::throw(&format!("could not parse snapshot from host: {}", e))
+        })?;
+        let us_fetcher = Arc::new(utils::USFetcher);
+        let format = SupportedFormat::from_str(format)
+            .map_err(|_| JsError::new(&format!("unknown format `{}`", format)))?;
+        let engine = Processor::from_snapshot(snapshot, us_fetcher, true, format)
+            .map(RefCell::new)
+            .map(Rc::new)
+            .map_err(|e| JsError::new(&e.to_string()))?;
+        Ok(Driver {
+            engine,
+            fetcher: lifecycle,
+            notifier: Notifier::default(),
+        })
+    }
+
+    /// Saves the style, locales, references and document as a plain, JSON-compatible object,
+    /// for `Driver.fromSnapshot`.
+    ///
+    /// * returns a `ProcessorSnapshot`
+    #[wasm_bindgen(js_name = "takeSnapshot")]
+    pub fn take_snapshot(&self) -> Result<JsValue, JsValue> {
+        self.serde_result(|engine| engine.take_snapshot())
+    }
+
+    /// Turns URL, DOI and PMID variables, and bare URLs in titles and notes, into links. Has no
+    /// effect on plain text output.
+    #[wasm_bindgen(js_name = "setLinkUrls")]
+    pub fn set_link_urls(&mut self, link_urls: bool) {
+        self.engine.borrow_mut().set_link_urls(link_urls);
+        self.notifier.edited(&self.engine);
+    }
+
+    /// Embeds machine-readable metadata in each HTML bibliography entry. `kind` is one of
+    /// { "none", "coins", "schema-org" }.
+    #[wasm_bindgen(js_name = "setHtmlMetadata")]
+    pub fn set_html_metadata(&mut self, kind: &str) -> Result<(), JsValue> {
+        let metadata = HtmlMetadata::from_str(kind)
+            .map_err(|_| JsError::new(&format!("unknown metadata kind `{}`", kind)))?;
+        self.engine.borrow_mut().set_html_metadata(metadata);
+        self.notifier.edited(&self.engine);
+        Ok(())
+    }
+
     /// Sets the style (which will also cause everything to be recomputed)
     #[wasm_bindgen(js_name = "setStyle")]
     pub fn set_style(&mut self, style_text: &str) -> JsValue {
-        self.engine
+        let result = self.engine.borrow_mut().set_style_text(style_text);
+        match result {
+            Ok(()) => {
+                self.notifier.edited(&self.engine);
+                JsValue::UNDEFINED
+            }
+            Err(e) => JsValue::from_serde(&e).unwrap(),
+        }
+    }
+
+    /// Sets the style like `setStyle`, but if parts of it are invalid, carries on without them.
+    /// For previewing a style while it is being edited.
+    ///
+    /// * returns the `InvalidCsl[]` that were skipped
+    /// * throws a `StyleError` if the style couldn't be used at all
+    #[wasm_bindgen(js_name = "setStyleRecovering")]
+    pub fn set_style_recovering(&mut self, style_text: &str) -> Result<JsValue, JsValue> {
+        let errors = self
+            .engine
             .borrow_mut()
-            .set_style_text(style_text)
-            .map_err(|e| JsValue::from_serde(&e).unwrap())
-            .err()
-            .unwrap_or(JsValue::UNDEFINED)
+            .set_style_text_recovering(style_text)
+            .map_err(|e| JsError::new(&serde_json::to_string(&e).unwrap()))?;
+        self.notifier.edited(&self.engine);
+        Ok(JsValue::from_serde(&errors).unwrap())
     }
 
-    /// Inserts or overwrites references as a batch operation.
+    /// Replaces the whole library. Any reference not in `refs` is removed.
     #[wasm_bindgen(js_name = "setReferences")]
     pub fn set_references(&mut self, refs: Box<[JsValue]>) -> Result<(), JsValue> {
         let refs = utils::read_js_array(refs)?;
         self.engine.borrow_mut().set_references(refs);
+        self.notifier.edited(&self.engine);
         Ok(())
     }
 
@@ -93,9 +238,63 @@ impl Driver {
             .map_err(|_| ErrorPlaceholder::throw("could not parse Reference from host"))?;
         // inserting & replacing are the same
         self.engine.borrow_mut().insert_reference(refr);
+        self.notifier.edited(&self.engine);
+        Ok(())
+    }
+
+    /// Inserts or overwrites references as a batch operation, keeping the rest of the library.
+    #[wasm_bindgen(js_name = "upsertReferences")]
+    pub fn upsert_references(&mut self, refs: Box<[JsValue]>) -> Result<(), JsValue> {
+        let refs = utils::read_js_array(refs)?;
+        self.engine.borrow_mut().upsert_references(refs);
+        self.notifier.edited(&self.engine);
+        Ok(())
+    }
+
+    /// Removes a reference from the library. Returns false if there was no such reference.
+    #[wasm_bindgen(js_name = "removeReference")]
+    pub fn remove_reference(&mut self, id: &str) -> bool {
+        let removed = self.engine.borrow_mut().remove_reference(Atom::from(id));
+        self.notifier.edited(&self.engine);
+        removed
+    }
+
+    /// Changes some of the fields of a reference. Returns false if there was no such reference.
+    ///
+    /// * `patch` is a `ReferencePatch`
+    #[wasm_bindgen(js_name = "patchReference")]
+    pub fn patch_reference(&mut self, id: &str, patch: JsValue) -> Result<bool, JsValue> {
+        let patch: ReferencePatch = patch
+            .into_serde()
+            .map_err(|e| ErrorPlaceholder::throw(&format!("could not parse patch: {}", e)))?;
+        let patched = self
+            .engine
+            .borrow_mut()
+            .patch_reference(Atom::from(id), &patch);
+        self.notifier.edited(&self.engine);
+        Ok(patched)
+    }
+
+    /// Puts references in the bibliography without citing them. Replaces any previous list.
+    ///
+    /// * `ids` is an `Array<string>` of reference ids
+    #[wasm_bindgen(js_name = "setUncited")]
+    pub fn set_uncited(&mut self, ids: Box<[JsValue]>) -> Result<(), JsValue> {
+        let ids: Vec<String> = utils::read_js_array(ids)?;
+        self.engine
+            .borrow_mut()
+            .set_uncited(ids.into_iter().map(Atom::from).collect());
+        self.notifier.edited(&self.engine);
         Ok(())
     }
 
+    /// Puts every reference in the bibliography, cited or not (pandoc's `nocite: @*`).
+    #[wasm_bindgen(js_name = "includeAllUncited")]
+    pub fn include_all_uncited(&mut self, include: bool) {
+        self.engine.borrow_mut().include_all_uncited(include);
+        self.notifier.edited(&self.engine);
+    }
+
     fn serde_result<T>(&self, f: impl Fn(&Processor) -> T) -> Result<JsValue, JsValue>
     where
         T: Serialize,
@@ -119,22 +318,35 @@ impl Driver {
         })
     }
 
+    /// Renders a reference on its own, as a first-position cite and as a bibliography entry,
+    /// without adding it to the document. Cheap enough to call for every item in a list.
+    ///
+    /// * `refr` is a Reference
+    /// * returns a `StandaloneReference`
+    #[wasm_bindgen(js_name = "renderReference")]
+    pub fn render_reference(&self, refr: JsValue) -> Result<JsValue, JsValue> {
+        let refr = refr
+            .into_serde()
+            .map_err(|_| ErrorPlaceholder::throw("could not parse Reference from host"))?;
+        self.serde_result(|engine| engine.render_reference(&refr))
+    }
+
     /// Inserts or replaces a cluster with a matching `id`.
     #[wasm_bindgen(js_name = "insertCluster")]
     pub fn insert_cluster(&mut self, cluster_id: JsValue) -> Result<(), JsValue> {
         let cluster = cluster_id.into_serde().map_err(|e| {
             ErrorPlaceholder::throw(&format!("could not parse cluster from host: {}", e))
         })?;
-        let mut eng = self.engine.borrow_mut();
-        eng.insert_cluster(cluster);
+        self.engine.borrow_mut().insert_cluster(cluster);
+        self.notifier.edited(&self.engine);
         Ok(())
     }
 
     /// Removes a cluster with a matching `id`
     #[wasm_bindgen(js_name = "removeCluster")]
     pub fn remove_cluster(&mut self, cluster_id: u32) -> Result<(), JsValue> {
-        let mut eng = self.engine.borrow_mut();
-        eng.remove_cluster(cluster_id);
+        self.engine.borrow_mut().remove_cluster(cluster_id);
+        self.notifier.edited(&self.engine);
         Ok(())
     }
 
@@ -145,6 +357,7 @@ impl Driver {
     pub fn init_clusters(&mut self, clusters: Box<[JsValue]>) -> Result<(), JsValue> {
         let clusters = utils::read_js_array(clusters)?;
         self.engine.borrow_mut().init_clusters(clusters);
+        self.notifier.edited(&self.engine);
         Ok(())
     }
 
@@ -166,11 +379,70 @@ impl Driver {
             .and_then(|b| JsValue::from_serde(&b).map_err(|e| JsError::new(e.description())))?)
     }
 
+    /// Returns a tree of formatted spans for each cite in `cluster_id`, annotated with the
+    /// variable that produced each one. Useful for click-to-edit and tooltips.
+    #[wasm_bindgen(js_name = "clusterSpans")]
+    pub fn cluster_spans(&self, id: ClusterId) -> Result<JsValue, JsValue> {
+        let eng = self.engine.borrow();
+        let spans = eng.get_cluster_spans(id);
+        Ok(spans
+            .ok_or_else(|| {
+                JsError::new(&format!(
+                    "Cluster {} has not been assigned a position in the document.",
+                    id
+                ))
+            })
+            .and_then(|s| JsValue::from_serde(&s).map_err(|e| JsError::new(e.description())))?)
+    }
+
     #[wasm_bindgen(js_name = "makeBibliography")]
     pub fn full_bibliography(&self) -> Result<JsValue, JsValue> {
         self.serde_result(|engine| engine.get_bibliography())
     }
 
+    /// Returns an `Array<BibEntry>`, with ids, numbers and the first-field split alongside
+    /// each entry's output.
+    #[wasm_bindgen(js_name = "bibliographyEntries")]
+    pub fn bibliography_entries(&self) -> Result<JsValue, JsValue> {
+        self.serde_result(|engine| engine.get_bibliography_entries())
+    }
+
+    /// Replaces the named bibliographies.
+    ///
+    /// * `filters` is an `Array<[string, BibliographyFilter]>` of names and filters
+    #[wasm_bindgen(js_name = "setBibliographyFilters")]
+    pub fn set_bibliography_filters(&mut self, filters: Box<[JsValue]>) -> Result<(), JsValue> {
+        let filters: Vec<(String, BibliographyFilter)> = utils::read_js_array(filters)?;
+        self.engine.borrow_mut().set_bibliography_filters(
+            filters
+                .into_iter()
+                .map(|(name, filter)| (Atom::from(name), filter))
+                .collect(),
+        );
+        self.notifier.edited(&self.engine);
+        Ok(())
+    }
+
+    /// Returns the `Array<BibEntry>` for a named bibliography, or null if there is none by that
+    /// name.
+    #[wasm_bindgen(js_name = "namedBibliography")]
+    pub fn named_bibliography(&self, name: &str) -> Result<JsValue, JsValue> {
+        self.serde_result(|engine| engine.get_named_bibliography(Atom::from(name)))
+    }
+
+    /// The number of sections in the document, or 0 if no cluster starts a new section.
+    #[wasm_bindgen(js_name = "sectionCount")]
+    pub fn section_count(&self) -> u32 {
+        self.engine.borrow().section_count()
+    }
+
+    /// Returns the `Array<BibEntry>` for one section of the document, numbered within the
+    /// section.
+    #[wasm_bindgen(js_name = "sectionBibliography")]
+    pub fn section_bibliography(&self, section: u32) -> Result<JsValue, JsValue> {
+        self.serde_result(|engine| engine.get_section_bibliography(section))
+    }
+
     #[wasm_bindgen(js_name = "bibliographyMeta")]
     pub fn bibliography_meta(&self) -> Result<JsValue, JsValue> {
         self.serde_result(|engine| engine.get_bibliography_meta())
@@ -199,8 +471,8 @@ impl Driver {
     #[wasm_bindgen(js_name = "renumberClusters")]
     pub fn renumber_clusters(&mut self, mappings: Box<[JsValue]>) -> Result<(), JsValue> {
         let mappings: Vec<(ClusterId, ClusterNumber)> = utils::read_js_array(mappings)?;
-        let mut eng = self.engine.borrow_mut();
-        eng.renumber_clusters(&mappings);
+        self.engine.borrow_mut().renumber_clusters(&mappings);
+        self.notifier.edited(&self.engine);
         Ok(())
     }
 
@@ -230,17 +502,101 @@ impl Driver {
     #[wasm_bindgen(js_name = "setClusterOrder")]
     pub fn set_cluster_order(&mut self, pieces: Box<[JsValue]>) -> Result<(), JsValue> {
         let pieces: Vec<ClusterPosition> = utils::read_js_array(pieces)?;
-        let mut eng = self.engine.borrow_mut();
-        eng.set_cluster_order(&pieces)
-            .map_err(|e| ErrorPlaceholder::throw(&format!("{:?}", e)))?;
+        let result = self.engine.borrow_mut().set_cluster_order(&pieces);
+        result.map_err(|e| ErrorPlaceholder::throw(&format!("{:?}", e)))?;
+        self.notifier.edited(&self.engine);
         Ok(())
     }
 
-    /// Retrieve any clusters that have been touched since last time `batchedUpdates` was
-    /// called. Intended to be called every time an edit has been made. Every cluster in the
-    /// returned summary should then be reflected in any UI.
+    /// Starts a new section (e.g. a chapter) at each of these clusters. Positions and citation
+    /// numbers start again in each section, and each one gets its own bibliography.
     ///
-    /// Some built clusters may occasionally have identical contents to before.
+    /// * `section_starts` is a ClusterId[]. Ids of clusters that aren't in the document are
+    ///   ignored.
+    #[wasm_bindgen(js_name = "setSections")]
+    pub fn set_sections(&mut self, section_starts: Box<[u32]>) {
+        self.engine
+            .borrow_mut()
+            .set_sections(section_starts.into_vec());
+        self.notifier.edited(&self.engine);
+    }
+
+    /// Applies a batch of edits all together, or, if any of them is invalid, throws and changes
+    /// nothing. Valid edits have known cluster ids, no duplicate cluster or reference ids, and
+    /// note numbers that don't go backwards.
+    ///
+    /// * `edits` is an `Edit[]`, applied in order
+    #[wasm_bindgen(js_name = "commit")]
+    pub fn commit(&mut self, edits: Box<[JsValue]>) -> Result<(), JsValue> {
+        let edits: Vec<Edit> = utils::read_js_array(edits)?;
+        let result = self.engine.borrow_mut().commit(Transaction::from(edits));
+        result.map_err(|e| ErrorPlaceholder::throw(&e.to_string()))?;
+        self.notifier.edited(&self.engine);
+        Ok(())
+    }
+
+    /// Previews what `cluster` would look like in the document, without modifying it or
+    /// producing any updates. An existing cluster with the same id is replaced.
+    ///
+    /// * `cluster` is a Cluster
+    /// * `note` is its note number, or undefined for an in-text cluster
+    /// * `before` is the id of the cluster it goes before. If undefined, it stays where the
+    ///   existing cluster is, or goes at the end.
+    /// * `format` is optional, and otherwise one of the formats accepted by the constructor
+    #[wasm_bindgen(js_name = "previewCluster")]
+    pub fn preview_cluster(
+        &self,
+        cluster: JsValue,
+        note: Option<u32>,
+        before: Option<ClusterId>,
+        format: Option<String>,
+    ) -> Result<JsValue, JsValue> {
+        let cluster = cluster.into_serde().map_err(|e| {
+            ErrorPlaceholder::throw(&format!("could not parse cluster from host: {}", e))
+        })?;
+        let format = match format {
+            Some(f) => Some(
+                SupportedFormat::from_str(&f)
+                    .map_err(|_| JsError::new(&format!("unknown format `{}`", f)))?,
+            ),
+            None => None,
+        };
+        let eng = self.engine.borrow();
+        let preview = eng
+            .preview_cluster(cluster, note, before, format)
+            .map_err(|e| ErrorPlaceholder::throw(&format!("{:?}", e)))?;
+        Ok(JsValue::from_serde(&preview).unwrap())
+    }
+
+    /// The title, id, authors, categories and other metadata of the current style.
+    ///
+    /// * returns a `StyleInfo`
+    #[wasm_bindgen(js_name = "styleInfo")]
+    pub fn style_info(&self) -> Result<JsValue, JsValue> {
+        self.serde_result(|engine| engine.style_info())
+    }
+
+    /// Lists the problems the processor had to work around in the current document, like cites
+    /// of missing references, or dates it couldn't parse.
+    ///
+    /// * returns a `Diagnostic[]`
+    #[wasm_bindgen(js_name = "diagnostics")]
+    pub fn diagnostics(&self) -> Result<JsValue, JsValue> {
+        self.serde_result(|engine| engine.diagnostics())
+    }
+
+    /// Lists the references in the bibliography that are missing variables the style would
+    /// render for them.
+    ///
+    /// * returns an `IncompleteReference[]`
+    #[wasm_bindgen(js_name = "incompleteReferences")]
+    pub fn incomplete_references(&self) -> Result<JsValue, JsValue> {
+        self.serde_result(|engine| engine.incomplete_references())
+    }
+
+    /// Retrieve any clusters whose output changed since last time `batchedUpdates` was
+    /// called, and any that were removed. Intended to be called every time an edit has been
+    /// made. Every cluster in the returned summary should then be reflected in any UI.
     ///
     /// * returns an `UpdateSummary`
     #[wasm_bindgen(js_name = "batchedUpdates")]
@@ -250,6 +606,20 @@ impl Driver {
         JsValue::from_serde(&summary).unwrap()
     }
 
+    /// Calls `callback` with an `UpdateSummary` whenever an edit changes some output, so you
+    /// don't have to poll `batchedUpdates`. Pass `null`/`undefined` to stop.
+    ///
+    /// With `coalesce`, edits made in the same synchronous run of JS code are delivered together
+    /// as one summary, in a microtask once that code has finished. Otherwise, each edit is
+    /// delivered before the method that made it returns.
+    #[wasm_bindgen(js_name = "onUpdate")]
+    pub fn on_update(&mut self, callback: Option<Function>, coalesce: bool) {
+        self.notifier
+            .listen(&mut self.engine.borrow_mut(), callback, coalesce);
+        // Anything held back for the old callback goes to the new one.
+        self.notifier.edited(&self.engine);
+    }
+
     /// Drains the `batchedUpdates` queue manually. Use it to avoid serializing an unneeded
     /// `UpdateSummary`.
     #[wasm_bindgen(js_name = "drain")]
@@ -264,6 +634,7 @@ impl Driver {
     pub fn fetch_all(&self) -> Promise {
         let rc = self.engine.clone();
         let fetcher = self.fetcher.clone();
+        let notifier = self.notifier.clone();
         let future = async move {
             // Keep these two RefCell borrows short-lived. The { scope } ensures the first borrow
             // ends before the JS code runs indefinitely. Just in case someone calls the Driver
@@ -279,8 +650,8 @@ impl Driver {
                     .collect()
             };
             let pairs = fetch_all(&fetcher, langs).await;
-            let mut eng = rc.borrow_mut();
-            eng.store_locales(pairs);
+            rc.borrow_mut().store_locales(pairs);
+            notifier.edited(&rc);
             Ok(JsValue::null())
         };
         future_to_promise(future)
@@ -300,6 +671,70 @@ impl Driver {
     }
 }
 
+/// Delivers the engine's update announcements to the callback registered with `Driver.onUpdate`.
+///
+/// The engine only marks edits as pending; this decides when to flush them. Its listener only
+/// stores the summaries, because it runs while the engine is borrowed, and the callback may well
+/// call back into the Driver.
+#[derive(Clone, Default)]
+struct Notifier {
+    callback: Rc<RefCell<Option<Function>>>,
+    announced: Rc<RefCell<Vec<UpdateSummary>>>,
+    /// Flush in a microtask rather than straight after each edit.
+    coalesce: Rc<Cell<bool>>,
+    /// A flush is already waiting in the microtask queue.
+    scheduled: Rc<Cell<bool>>,
+}
+
+impl Notifier {
+    fn listen(&self, engine: &mut Processor, callback: Option<Function>, coalesce: bool) {
+        let listener: Option<UpdateListener> = callback.as_ref().map(|_| {
+            let announced = self.announced.clone();
+            Rc::new(move |summary: &UpdateSummary| announced.borrow_mut().push(summary.clone()))
+                as UpdateListener
+        });
+        *self.callback.borrow_mut() = callback;
+        engine.set_update_listener(listener);
+        self.coalesce.set(coalesce);
+        self.announced.borrow_mut().clear();
+    }
+
+    /// Call after every edit, once the engine is no longer borrowed.
+    fn edited(&self, engine: &Rc<RefCell<Processor>>) {
+        if !engine.borrow().has_pending_updates() {
+            return;
+        }
+        if !self.coalesce.get() {
+            engine.borrow_mut().flush_updates();
+            self.deliver();
+        } else if !self.scheduled.replace(true) {
+            let notifier = self.clone();
+            let engine = engine.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                notifier.scheduled.set(false);
+                engine.borrow_mut().flush_updates();
+                notifier.deliver();
+            });
+        }
+    }
+
+    fn deliver(&self) {
+        let announced = std::mem::replace(&mut *self.announced.borrow_mut(), Vec::new());
+        for summary in announced {
+            // Clone it, so the callback can call onUpdate itself.
+            let callback = match self.callback.borrow().clone() {
+                Some(callback) => callback,
+                None => return,
+            };
+            let summary = JsValue::from_serde(&summary).unwrap();
+            if let Err(e) = callback.call1(&JsValue::NULL, &summary) {
+                error!("caught: onUpdate callback threw");
+                log_js_error(e);
+            }
+        }
+    }
+}
+
 #[wasm_bindgen]
 extern "C" {
     #[derive(Clone)]
@@ -375,14 +810,147 @@ export type Reference = {
 
 export type CslType = "book" | "article" | "legal_case" | "article-journal";
 
+export type BibEntry<Output = string> = {
+    id: string;
+    value: Output;
+    /** Position in the sorted bibliography, starting from 1. */
+    number: number;
+    /** Only present when the style sets second-field-align. */
+    firstField: { first: Output; rest: Output } | null;
+    cited: boolean;
+};
+
 export interface BibliographyUpdate {
-    updatedEntries: { [key: string]: string };
+    /** The entries whose output changed, in bibliography order. */
+    updatedEntries: BibEntry[];
     entryIds?: string[];
+    /** References already in the bibliography whose citation number changed, with their new
+        numbers. */
+    renumbered: [string, number][];
 }
 
 export type UpdateSummary<Output = string> = {
+    /** Clusters whose output changed, in document order. */
     clusters: [number, Output][];
+    /** Clusters that were delivered before but are no longer in the document. */
+    removedClusters: number[];
     bibliography?: BibliographyUpdate;
+    namedBibliographies: [string, BibliographyUpdate][];
+    sectionBibliographies: [number, BibliographyUpdate][];
+};
+
+/** Fields to change on a reference, as for `Driver.patchReference`. Any field but `id`; `null`
+    removes a variable. */
+export type ReferencePatch = {
+    id?: undefined;
+    type?: CslType;
+    [key: string]: any;
+};
+
+export type DiagnosticKind =
+    | "missingReference"
+    | "unknownLocatorLabel"
+    | "literalDate"
+    | "nonNumericValue"
+    | "missingLocale"
+    | "invalidLocale";
+
+/** Something the processor had to work around. Cite problems have a `clusterId` and the
+    `citeIndex` within it; reference problems have a `refId`. */
+export type Diagnostic = {
+    severity: "Error" | "Warning";
+    kind: DiagnosticKind;
+    message: string;
+    clusterId?: number;
+    citeIndex?: number;
+    refId?: string;
+};
+
+/** A reference that lacks some of the CSL variables the style would render for its type. */
+export type IncompleteReference = {
+    refId: string;
+    missing: string[];
+};
+
+export type StyleInfoPerson = {
+    name: string;
+    email: string | null;
+    uri: string | null;
+};
+
+/** The metadata in a style's `<info>`, from `Driver.styleInfo` or `Driver.readStyleInfo`. */
+export type StyleInfo = {
+    id: string | null;
+    title: string | null;
+    titleShort: string | null;
+    links: { href: string; rel: "self" | "template" | "documentation" | "independent-parent"; }[];
+    authors: StyleInfoPerson[];
+    contributors: StyleInfoPerson[];
+    citationFormat: "author-date" | "author" | "numeric" | "label" | "note" | null;
+    categories: string[];
+    issn: string[];
+    eissn: string | null;
+    issnl: string | null;
+    summary: string | null;
+    published: string | null;
+    updated: string | null;
+    rights: string | null;
+    license: string | null;
+};
+
+/** Everything a Driver was given, from `Driver.takeSnapshot`. Treat it as opaque, apart from
+    storing it, e.g. with `JSON.stringify`. */
+export type ProcessorSnapshot = {
+    version: number;
+    [key: string]: any;
+};
+
+/** One of the edits in a `Driver.commit` batch. */
+export type Edit =
+    | { type: "insertCluster"; value: Cluster }
+    | { type: "removeCluster"; value: number }
+    | { type: "setReferences"; value: Reference[] }
+    | { type: "setClusterOrder"; value: ClusterPosition[] }
+    | { type: "renumberClusters"; value: [number, ClusterNumber][] };
+
+/** Passed to `Driver.onUpdate`. */
+export type UpdateCallback = (summary: UpdateSummary) => void;
+
+/** A reference field, and a value to compare it to. `field` is "type", "id" or a CSL variable.
+    "keyword" matches any one of several comma- or semicolon-separated keywords. */
+export type FieldMatch = { field: string; value: string; };
+
+/** A reference passes if it matches all of `select`, any of `include`, none of `exclude`, and
+    not all of `quash`. Empty lists are ignored. */
+export type BibliographyFilter = {
+    select?: FieldMatch[];
+    include?: FieldMatch[];
+    exclude?: FieldMatch[];
+    quash?: FieldMatch[];
+};
+
+export type SpanKind = "text" | "variable" | "label" | "names" | "conditional" | "group";
+
+export type SemanticSpan = {
+    kind: SpanKind;
+    variable?: string;
+    disambiguated?: boolean;
+    output: string;
+    children?: SemanticSpan[];
+};
+
+export type CiteSpans = {
+    citeId: string;
+    index: number;
+    refId: string;
+    prefix?: string;
+    suffix?: string;
+    span?: SemanticSpan;
+};
+
+export type StandaloneReference = {
+    citation: string;
+    bibEntry: string | null;
 };
 
 type InvalidCsl = {